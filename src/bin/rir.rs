@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path;
@@ -5,12 +6,18 @@ use std::process;
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-use ironring::{name_hashes, repackers, unpackers};
+use ironring::{name_hashes, params, repackers, unpackers};
+use ironring::formats::bhd::BhdFormat;
+use ironring::utils::crypto;
+use ironring::utils::str::{hex_to_bytes, n_pluralise};
 
 fn main() {
     let default_namefilepath: &str = &get_default_namefilepath();
     let matches = App::new("Rusted Iron Ring")
         .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(Arg::with_name("json")
+            .help("Prints parsed data as JSON (requires the serde feature)")
+            .long("json").global(true))
         .subcommand(SubCommand::with_name("bhd")
             .about("Extracts BHD/BDT contents")
             .arg(Arg::with_name("file")
@@ -22,7 +29,14 @@ fn main() {
             .arg(Arg::with_name("namefile")
                 .help("Namefile path, mapping hashes to file names")
                 .short("n").long("names").takes_value(true).required(false)
-                .default_value(default_namefilepath)))
+                .default_value(default_namefilepath))
+            .arg(Arg::with_name("game")
+                .help("Game the BHD comes from, defining its layout")
+                .short("g").long("game").takes_value(true).required(false)
+                .possible_values(&["ds1", "ds2", "ds3", "sekiro", "er"]).default_value("ds1"))
+            .arg(Arg::with_name("key")
+                .help("Public key (PEM) to decrypt the BHD, default is in res/keys/<game>")
+                .short("k").long("key").takes_value(true).required(false)))
         .subcommand(SubCommand::with_name("bhds")
             .about("Extracts all BHD/BDT content (alphabetically) in a folder")
             .arg(Arg::with_name("folder")
@@ -34,7 +48,25 @@ fn main() {
             .arg(Arg::with_name("namefile")
                 .help("Namefile path, mapping hashes to file names")
                 .short("n").long("names").takes_value(true).required(false)
-                .default_value(default_namefilepath)))
+                .default_value(default_namefilepath))
+            .arg(Arg::with_name("game")
+                .help("Game the BHD comes from, defining its layout")
+                .short("g").long("game").takes_value(true).required(false)
+                .possible_values(&["ds1", "ds2", "ds3", "sekiro", "er"]).default_value("ds1"))
+            .arg(Arg::with_name("key")
+                .help("Public key (PEM) to decrypt the BHD, default is in res/keys/<game>")
+                .short("k").long("key").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("bhd-pack")
+            .about("Packs files in a BHD/BDT pair")
+            .arg(Arg::with_name("files")
+                .help("Directory containing files to pack, as the BHD root")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output BHD file, the BDT is written next to it")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("buckets")
+                .help("Number of hash buckets, guessed from file count if not set")
+                .short("b").long("buckets").takes_value(true).required(false)))
         .subcommand(SubCommand::with_name("hash")
             .about("Calculates hash for a string")
             .arg(Arg::with_name("value")
@@ -48,8 +80,19 @@ fn main() {
             .arg(Arg::with_name("output")
                 .help("Output directory")
                 .short("o").long("output").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("dcx-pack")
+            .about("Compresses data in a DCX using the original DCX")
+            .arg(Arg::with_name("file")
+                .help("Original DCX file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("data")
+                .help("File to compress")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output DCX file")
+                .takes_value(true).required(true)))
         .subcommand(SubCommand::with_name("bnd")
-            .about("Extracts BND contents")
+            .about("Extracts BND3/BND4 contents")
             .arg(Arg::with_name("file")
                 .help("BND (or BND/DCX) file path")
                 .takes_value(true).required(true))
@@ -62,8 +105,19 @@ fn main() {
             .arg(Arg::with_name("decompress")
                 .help("Decompress file first if BND is in DCX")
                 .long("decompress").takes_value(false).required(false)))
+        .subcommand(SubCommand::with_name("bnd-pack")
+            .about("Repacks BND contents using the original BND")
+            .arg(Arg::with_name("file")
+                .help("Original BND file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("files")
+                .help("Directory containing files to pack")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output file")
+                .takes_value(true).required(true)))
         .subcommand(SubCommand::with_name("bhf")
-            .about("Extracts BHF/BDT contents")
+            .about("Extracts BHF3/BHF4 and BDT contents")
             .arg(Arg::with_name("file")
                 .help("BHF file path")
                 .takes_value(true).required(true))
@@ -73,19 +127,183 @@ fn main() {
             .arg(Arg::with_name("overwrite")
                 .help("Overwrite existing files")
                 .short("f").long("force").takes_value(false).required(false)))
+        .subcommand(SubCommand::with_name("bhf-pack")
+            .about("Repacks BHF3/BDT contents using the original BHF")
+            .arg(Arg::with_name("file")
+                .help("Original BHF file path, with its BDT next to it")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("files")
+                .help("Directory containing files to pack")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output BHF file, the BDT is written next to it")
+                .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("bhf-pack-dir")
+            .about("Packs files in a new BHF3/BDT pair")
+            .arg(Arg::with_name("files")
+                .help("Directory containing files to pack")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output BHF file, the BDT is written next to it")
+                .takes_value(true).required(true)))
         .subcommand(SubCommand::with_name("paramdef")
             .about("Print PARAMDEF contents")
             .arg(Arg::with_name("file")
-                .help("PARAMDEF file path")
+                .help("PARAMDEF file path, binary or XML")
                 .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("paramdef-convert")
+            .about("Converts a PARAMDEF between binary and XML")
+            .arg(Arg::with_name("file")
+                .help("PARAMDEF file path, binary or XML")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output file, written as XML if it ends with .xml")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("format_version")
+                .help("Format version of the binary PARAMDEF, e.g. 104")
+                .short("f").long("format-version").takes_value(true).required(false)))
         .subcommand(SubCommand::with_name("param")
             .about("Parse PARAM contents")
             .arg(Arg::with_name("file")
                 .help("PARAM file path")
                 .takes_value(true).required(true))
             .arg(Arg::with_name("paramdef")
-                .help("PARAMDEF file path")
+                .help("PARAMDEF file path, binary or XML")
                 .short("d").long("def").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("param-export")
+            .about("Exports PARAM rows to CSV using a PARAMDEF")
+            .arg(Arg::with_name("file")
+                .help("PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output CSV file")
+                .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("param-import")
+            .about("Imports PARAM rows from CSV using a PARAMDEF")
+            .arg(Arg::with_name("file")
+                .help("Original PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("csv")
+                .help("CSV file, as exported by param-export")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output PARAM file")
+                .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("param-check")
+            .about("Finds dangling references between PARAMs of a param binder")
+            .arg(Arg::with_name("file")
+                .help("Param binder, optionally in a DCX, or regulation file with --key")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdefs")
+                .help("Directory of PARAMDEF files, binary or XML")
+                .short("d").long("defs").takes_value(true).required(true))
+            .arg(Arg::with_name("refs")
+                .help("Reference map, with lines like \"PARAM_ST.field = TargetParam\"")
+                .short("r").long("refs").takes_value(true).required(true))
+            .arg(Arg::with_name("key")
+                .help("Regulation AES key, as 64 hex digits")
+                .short("k").long("key").takes_value(true).required(false))
+            .arg(Arg::with_name("who")
+                .help("List references to a row instead, as PARAM:ID")
+                .short("w").long("who").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("param-diff")
+            .about("Compares rows of two PARAM files")
+            .arg(Arg::with_name("old")
+                .help("Old PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("new")
+                .help("New PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .short("d").long("def").takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("param-infer")
+            .about("Guesses a PARAMDEF from PARAM row data")
+            .arg(Arg::with_name("file")
+                .help("PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output PARAMDEF, written as XML if it ends with .xml; else print it")
+                .takes_value(true).required(false))
+            .arg(Arg::with_name("refs")
+                .help("PARAM files whose IDs may be referenced")
+                .short("r").long("refs").takes_value(true).multiple(true).required(false)))
+        .subcommand(SubCommand::with_name("param-merge")
+            .about("Merges PARAM files modified from the same base")
+            .arg(Arg::with_name("base")
+                .help("Base PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("mods")
+                .help("Modified PARAM file paths, the last ones win conflicts")
+                .takes_value(true).multiple(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .short("d").long("def").takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output PARAM file")
+                .short("o").long("output").takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("param-migrate")
+            .about("Converts PARAM rows to another PARAMDEF version")
+            .arg(Arg::with_name("file")
+                .help("PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("old")
+                .help("PARAMDEF of the PARAM, binary or XML")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("new")
+                .help("New PARAMDEF, binary or XML")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output PARAM file")
+                .takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("param-query")
+            .about("Prints PARAM rows matching a filter")
+            .arg(Arg::with_name("file")
+                .help("PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("filter")
+                .help("Filter, e.g. \"id between 1000 and 1999 and weight > 2.5\"")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .short("d").long("def").takes_value(true).required(true))
+            .arg(Arg::with_name("fields")
+                .help("Comma-separated fields to print, all by default")
+                .short("f").long("fields").takes_value(true).required(false)))
+        .subcommand(SubCommand::with_name("param-edit")
+            .about("Edits PARAM rows with assignments")
+            .arg(Arg::with_name("file")
+                .help("PARAM file path")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("edit")
+                .help("Edit, e.g. \"weight *= 0.9 where id between 1000 and 1999\"")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdef")
+                .help("PARAMDEF file path, binary or XML")
+                .short("d").long("def").takes_value(true).required(true))
+            .arg(Arg::with_name("output")
+                .help("Output PARAM file")
+                .short("o").long("output").takes_value(true).required(true)))
+        .subcommand(SubCommand::with_name("parambnd")
+            .about("Lists PARAMs of a param binder or regulation file")
+            .arg(Arg::with_name("file")
+                .help("Param binder, optionally in a DCX, or regulation file with --key")
+                .takes_value(true).required(true))
+            .arg(Arg::with_name("paramdefs")
+                .help("Directory of PARAMDEF files, binary or XML")
+                .short("d").long("defs").takes_value(true).required(false))
+            .arg(Arg::with_name("key")
+                .help("Regulation AES key, as 64 hex digits")
+                .short("k").long("key").takes_value(true).required(false))
+            .arg(Arg::with_name("param")
+                .help("Name of a PARAM to print")
+                .short("p").long("param").takes_value(true).required(false)))
         .subcommand(SubCommand::with_name("dat")
             .about("Extracts King's Field IV DAT contents")
             .arg(Arg::with_name("file")
@@ -107,12 +325,28 @@ fn main() {
     process::exit(match matches.subcommand() {
         ("bhd", Some(s)) => cmd_bhd(s),
         ("bhds", Some(s)) => cmd_bhds(s),
+        ("bhd-pack", Some(s)) => cmd_bhd_pack(s),
         ("hash", Some(s)) => cmd_hash(s),
         ("dcx", Some(s)) => cmd_dcx(s),
+        ("dcx-pack", Some(s)) => cmd_dcx_pack(s),
         ("bnd", Some(s)) => cmd_bnd(s),
+        ("bnd-pack", Some(s)) => cmd_bnd_pack(s),
         ("bhf", Some(s)) => cmd_bhf(s),
+        ("bhf-pack", Some(s)) => cmd_bhf_pack(s),
+        ("bhf-pack-dir", Some(s)) => cmd_bhf_pack_dir(s),
         ("paramdef", Some(s)) => cmd_paramdef(s),
+        ("paramdef-convert", Some(s)) => cmd_paramdef_convert(s),
         ("param", Some(s)) => cmd_param(s),
+        ("param-export", Some(s)) => cmd_param_export(s),
+        ("param-import", Some(s)) => cmd_param_import(s),
+        ("param-check", Some(s)) => cmd_param_check(s),
+        ("param-diff", Some(s)) => cmd_param_diff(s),
+        ("param-infer", Some(s)) => cmd_param_infer(s),
+        ("param-merge", Some(s)) => cmd_param_merge(s),
+        ("param-migrate", Some(s)) => cmd_param_migrate(s),
+        ("param-query", Some(s)) => cmd_param_query(s),
+        ("param-edit", Some(s)) => cmd_param_edit(s),
+        ("parambnd", Some(s)) => cmd_parambnd(s),
         ("dat", Some(s)) => cmd_dat(s),
         ("dat-pack", Some(s)) => cmd_dat_pack(s),
         _ => 0,
@@ -127,6 +361,29 @@ fn get_default_namefilepath() -> String {
     String::from(namefile_path.to_str().unwrap())
 }
 
+/// Load the public key to decrypt this BHD, if any.
+///
+/// If no key is given, look for a key in the "res/keys" dir, next to
+/// the namefile; the key is optional as it is not needed for DS1.
+fn get_bhd_key(
+    args: &ArgMatches,
+    bhd_path: &path::Path,
+) -> Result<Option<crypto::RsaPublicKey>, String> {
+    if let Some(key_path) = args.value_of("key") {
+        return crypto::load_rsa_public_key_file(key_path).map(Some)
+    }
+    let program_path: path::PathBuf = env::current_exe().unwrap();
+    let mut key_path: path::PathBuf = path::PathBuf::from(program_path.parent().unwrap());
+    key_path.push("res/keys");
+    key_path.push(args.value_of("game").unwrap());
+    key_path.push(bhd_path.file_stem().unwrap_or_default());
+    key_path.set_extension("pem");
+    match key_path.to_str() {
+        Some(p) if key_path.is_file() => crypto::load_rsa_public_key_file(p).map(Some),
+        _ => Ok(None),
+    }
+}
+
 fn cmd_bhd(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
     let output_path: &str = args.value_of("output").unwrap();
@@ -137,7 +394,13 @@ fn cmd_bhd(args: &ArgMatches) -> i32 {
         Err(e) => { eprintln!("Failed to load namefile: {:?}", e); return 1 }
     };
 
-    match unpackers::bhd::extract_bhd(file_path, &names, output_path) {
+    let format = BhdFormat::from_game_name(args.value_of("game").unwrap()).unwrap();
+    let key = match get_bhd_key(args, path::Path::new(file_path)) {
+        Ok(k) => k,
+        Err(e) => { eprintln!("Failed to load key: {}", e); return 1 }
+    };
+
+    match unpackers::bhd::extract_bhd(file_path, &names, output_path, format, key.as_ref()) {
         Err(e) => { eprintln!("Failed to extract BHD: {:?}", e); 1 }
         _ => { 0 }
     }
@@ -159,22 +422,29 @@ fn cmd_bhds(args: &ArgMatches) -> i32 {
     };
     let mut bhd_paths = vec!();
     for entry in entries {
-        if !entry.is_ok() {
+        if entry.is_err() {
             continue
         }
         let path = entry.unwrap().path();
         if let Some(e) = path.extension() {
-            if e == "bhd5" {
+            if e == "bhd5" || e == "bhd" {
                 bhd_paths.push(path);
             }
         }
     }
     bhd_paths.sort();
 
+    let format = BhdFormat::from_game_name(args.value_of("game").unwrap()).unwrap();
     for bhd_path in bhd_paths {
         println!("Extracting {:?}", bhd_path);
+        let key = match get_bhd_key(args, &bhd_path) {
+            Ok(k) => k,
+            Err(e) => { eprintln!("Failed to load key: {}", e); return 1 }
+        };
         if let Some(path_str) = bhd_path.to_str() {
-            if let Err(e) = unpackers::bhd::extract_bhd(path_str, &names, output_path) {
+            let result = unpackers::bhd::extract_bhd(
+                path_str, &names, output_path, format, key.as_ref());
+            if let Err(e) = result {
                 eprintln!("Failed to extract BHD: {:?}", e);
                 return 1
             }
@@ -183,9 +453,27 @@ fn cmd_bhds(args: &ArgMatches) -> i32 {
     0
 }
 
+fn cmd_bhd_pack(args: &ArgMatches) -> i32 {
+    let files_path: &str = args.value_of("files").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let num_buckets: Option<u32> = match args.value_of("buckets").map(|b| b.parse::<u32>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(e)) => { eprintln!("Invalid bucket count: {}", e); return 1 }
+        None => None,
+    };
+    match repackers::bhd::pack_bhd_dir(files_path, output_path, num_buckets) {
+        Err(e) => { eprintln!("Failed to pack BHD: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
 fn cmd_hash(args: &ArgMatches) -> i32 {
     let value: &str = args.value_of("value").unwrap();
-    println!("{}", name_hashes::hash_as_string(name_hashes::hash(&value)));
+    let hash = name_hashes::hash_as_string(name_hashes::hash(value));
+    if args.is_present("json") {
+        return print_json(&hash)
+    }
+    println!("{}", hash);
     0
 }
 
@@ -203,6 +491,16 @@ fn cmd_dcx(args: &ArgMatches) -> i32 {
     }
 }
 
+fn cmd_dcx_pack(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let data_path: &str = args.value_of("data").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    match repackers::dcx::pack_dcx_file(file_path, data_path, output_path) {
+        Err(e) => { eprintln!("Failed to pack DCX: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
 fn cmd_bnd(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
     let output_path: &str = args.value_of("output").unwrap();
@@ -215,6 +513,16 @@ fn cmd_bnd(args: &ArgMatches) -> i32 {
     }
 }
 
+fn cmd_bnd_pack(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let files_path: &str = args.value_of("files").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    match repackers::bnd::pack_bnd_file(file_path, files_path, output_path) {
+        Err(e) => { eprintln!("Failed to pack BND: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
 fn cmd_bhf(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
     let output_path: &str = args.value_of("output").unwrap();
@@ -225,20 +533,77 @@ fn cmd_bhf(args: &ArgMatches) -> i32 {
     }
 }
 
+fn cmd_bhf_pack(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let files_path: &str = args.value_of("files").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    match repackers::bhf::pack_bhf_file(file_path, files_path, output_path) {
+        Err(e) => { eprintln!("Failed to pack BHF: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
+fn cmd_bhf_pack_dir(args: &ArgMatches) -> i32 {
+    let files_path: &str = args.value_of("files").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    match repackers::bhf::pack_bhf_dir(files_path, output_path) {
+        Err(e) => { eprintln!("Failed to pack BHF: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
 fn cmd_paramdef(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
-    match unpackers::paramdef::load_paramdef_file(file_path) {
+    match load_paramdef(file_path) {
+        Ok(paramdef) if args.is_present("json") => print_json(&paramdef),
         Ok(paramdef) => { unpackers::paramdef::print_paramdef(&paramdef); 0 }
         Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); 1 }
     }
 }
 
+fn cmd_paramdef_convert(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let mut paramdef = match load_paramdef(file_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    if let Some(format_version) = args.value_of("format_version") {
+        match format_version.parse::<u16>() {
+            Ok(v) => paramdef.header.format_version = v,
+            Err(_) => { eprintln!("Invalid format version: {}", format_version); return 1 }
+        }
+    }
+    if output_path.to_lowercase().ends_with(".xml") {
+        match unpackers::paramdef::export_paramdef_xml_file(&paramdef, output_path) {
+            Err(e) => { eprintln!("Failed to export PARAMDEF: {:?}", e); 1 }
+            _ => 0
+        }
+    } else {
+        match repackers::paramdef::pack_paramdef_file(&mut paramdef, output_path) {
+            Err(e) => { eprintln!("Failed to pack PARAMDEF: {:?}", e); 1 }
+            _ => 0
+        }
+    }
+}
+
+/// Load a binary PARAMDEF, or an XML layout if the path ends with ".xml".
+fn load_paramdef(
+    path: &str,
+) -> Result<ironring::formats::paramdef::Paramdef, unpackers::errors::UnpackError> {
+    if path.to_lowercase().ends_with(".xml") {
+        unpackers::paramdef::load_paramdef_xml_file(path)
+    } else {
+        unpackers::paramdef::load_paramdef_file(path)
+    }
+}
+
 fn cmd_param(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
     let paramdef_path: Option<&str> = args.value_of("paramdef");
 
-    let paramdef = if paramdef_path.is_some() {
-        match unpackers::paramdef::load_paramdef_file(paramdef_path.unwrap()) {
+    let paramdef = if let Some(paramdef_path) = paramdef_path {
+        match load_paramdef(paramdef_path) {
             Ok(paramdef) => Some(paramdef),
             Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
         }
@@ -251,6 +616,9 @@ fn cmd_param(args: &ArgMatches) -> i32 {
         Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
     };
 
+    if args.is_present("json") {
+        return print_json(&param)
+    }
     match paramdef {
         Some(paramdef) => unpackers::param::print_param_with_def(&param, &paramdef),
         None => unpackers::param::print_param(&param),
@@ -258,6 +626,337 @@ fn cmd_param(args: &ArgMatches) -> i32 {
     0
 }
 
+fn cmd_param_export(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let param = match unpackers::param::load_param_file(file_path, Some(&paramdef)) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    match unpackers::param::export_param_csv_file(&param, &paramdef, output_path) {
+        Err(e) => { eprintln!("Failed to export PARAM: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
+fn cmd_param_import(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let csv_path: &str = args.value_of("csv").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let mut param = match unpackers::param::load_param_file(file_path, Some(&paramdef)) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    if let Err(e) = repackers::param::import_param_csv_file(&mut param, &paramdef, csv_path) {
+        eprintln!("Failed to import CSV: {:?}", e);
+        return 1
+    }
+    match repackers::param::pack_param_file(&mut param, Some(&paramdef), output_path) {
+        Err(e) => { eprintln!("Failed to pack PARAM: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
+fn cmd_param_check(args: &ArgMatches) -> i32 {
+    let refs_path: &str = args.value_of("refs").unwrap();
+    let refs = match params::refs::load_reference_map_file(refs_path) {
+        Ok(refs) => refs,
+        Err(e) => { eprintln!("Failed to load reference map: {:?}", e); return 1 }
+    };
+    let (bundle, _) = match load_bundle(args) {
+        Ok(loaded) => loaded,
+        Err(e) => { eprintln!("{}", e); return 1 }
+    };
+    for name in &bundle.missing_defs {
+        eprintln!("Warning: {} has no PARAMDEF, its references are not checked.", name);
+    }
+
+    let references = match args.value_of("who") {
+        Some(who) => {
+            let row = who.rsplit_once(':')
+                .and_then(|(name, id)| id.parse::<u32>().ok().map(|id| (name, id)));
+            let (name, id) = match row {
+                Some(row) => row,
+                None => { eprintln!("Invalid row, expected PARAM:ID: {}", who); return 1 }
+            };
+            params::refs::find_referencing_rows(&bundle, &refs, name, id)
+        }
+        None => params::refs::find_dangling_references(&bundle, &refs),
+    };
+    if args.is_present("json") {
+        return print_json(&references)
+    }
+    for reference in &references {
+        println!("{}", reference);
+    }
+    0
+}
+
+fn cmd_param_diff(args: &ArgMatches) -> i32 {
+    let old_path: &str = args.value_of("old").unwrap();
+    let new_path: &str = args.value_of("new").unwrap();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let mut params = vec!();
+    for path in &[old_path, new_path] {
+        match unpackers::param::load_param_file(path, Some(&paramdef)) {
+            Ok(param) => params.push(param),
+            Err(e) => { eprintln!("Failed to load PARAM {}: {:?}", path, e); return 1 }
+        }
+    }
+    let diff = params::diff::diff_params(&params[0], &params[1], &paramdef);
+    if args.is_present("json") {
+        return print_json(&diff)
+    }
+    print!("{}", diff);
+    0
+}
+
+fn cmd_param_infer(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let param = match unpackers::param::load_param_file(file_path, None) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    let mut refs = vec!();
+    for ref_path in args.values_of("refs").unwrap_or_default() {
+        let name = path::Path::new(ref_path).file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| ref_path.to_string());
+        match unpackers::param::load_param_file(ref_path, None) {
+            Ok(ref_param) => refs.push((name, ref_param)),
+            Err(e) => { eprintln!("Failed to load PARAM {}: {:?}", ref_path, e); return 1 }
+        }
+    }
+    let refs: Vec<_> = refs.iter().map(|(name, ref_param)| (name.as_str(), ref_param)).collect();
+    let mut paramdef = match params::infer::infer_paramdef(&param, &refs) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to infer PARAMDEF: {}", e); return 1 }
+    };
+
+    match args.value_of("output") {
+        Some(output_path) if output_path.to_lowercase().ends_with(".xml") => {
+            match unpackers::paramdef::export_paramdef_xml_file(&paramdef, output_path) {
+                Err(e) => { eprintln!("Failed to export PARAMDEF: {:?}", e); 1 }
+                _ => 0
+            }
+        }
+        Some(output_path) => {
+            match repackers::paramdef::pack_paramdef_file(&mut paramdef, output_path) {
+                Err(e) => { eprintln!("Failed to pack PARAMDEF: {:?}", e); 1 }
+                _ => 0
+            }
+        }
+        None if args.is_present("json") => print_json(&paramdef),
+        None => { unpackers::paramdef::print_paramdef(&paramdef); 0 }
+    }
+}
+
+fn cmd_param_merge(args: &ArgMatches) -> i32 {
+    let base_path: &str = args.value_of("base").unwrap();
+    let mod_paths: Vec<&str> = args.values_of("mods").unwrap().collect();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let mut params = vec!();
+    for path in std::iter::once(&base_path).chain(mod_paths.iter()) {
+        match unpackers::param::load_param_file(path, Some(&paramdef)) {
+            Ok(param) => params.push(param),
+            Err(e) => { eprintln!("Failed to load PARAM {}: {:?}", path, e); return 1 }
+        }
+    }
+    let mods: Vec<&_> = params[1..].iter().collect();
+    let mut merge = params::merge::merge_params(&params[0], &mods, &paramdef);
+    let param = &mut merge.param;
+    if let Err(e) = repackers::param::pack_param_file(param, Some(&paramdef), output_path) {
+        eprintln!("Failed to pack PARAM: {:?}", e);
+        return 1
+    }
+    if args.is_present("json") {
+        return print_json(&merge.conflicts)
+    }
+    for conflict in &merge.conflicts {
+        println!("{}", conflict);
+    }
+    0
+}
+
+fn cmd_param_migrate(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let old_path: &str = args.value_of("old").unwrap();
+    let new_path: &str = args.value_of("new").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let mut paramdefs = vec!();
+    for path in &[old_path, new_path] {
+        match load_paramdef(path) {
+            Ok(paramdef) => paramdefs.push(paramdef),
+            Err(e) => { eprintln!("Failed to load PARAMDEF {}: {:?}", path, e); return 1 }
+        }
+    }
+    let param = match unpackers::param::load_param_file(file_path, Some(&paramdefs[0])) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    let mut param = match params::migrate::migrate_param(&param, &paramdefs[0], &paramdefs[1]) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to migrate PARAM: {:?}", e); return 1 }
+    };
+    match repackers::param::pack_param_file(&mut param, Some(&paramdefs[1]), output_path) {
+        Err(e) => { eprintln!("Failed to pack PARAM: {:?}", e); 1 }
+        _ => 0
+    }
+}
+
+type ParamdefMap = HashMap<String, ironring::formats::paramdef::Paramdef>;
+
+/// Load the param binder or regulation file of these arguments, with
+/// the PARAMDEFs of the "paramdefs" directory if given.
+fn load_bundle(args: &ArgMatches) -> Result<(params::bundle::ParamBundle, ParamdefMap), String> {
+    let file_path: &str = args.value_of("file").unwrap();
+    let paramdefs = match args.value_of("paramdefs") {
+        Some(dir_path) => params::bundle::load_paramdefs_dir(dir_path)
+            .map_err(|e| format!("Failed to load PARAMDEFs: {:?}", e))?,
+        None => HashMap::new(),
+    };
+    let bundle = match args.value_of("key") {
+        Some(key) => match hex_to_bytes(key) {
+            Some(key) => params::bundle::load_regulation_file(file_path, &key, &paramdefs),
+            None => return Err(format!("Invalid key: {}", key)),
+        },
+        None => params::bundle::load_param_bundle_file(file_path, &paramdefs),
+    };
+    let bundle = bundle.map_err(|e| format!("Failed to load params: {:?}", e))?;
+    Ok((bundle, paramdefs))
+}
+
+fn cmd_param_query(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let filter: &str = args.value_of("filter").unwrap();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let filter = match params::query::parse_filter(filter) {
+        Ok(filter) => filter,
+        Err(e) => { eprintln!("Invalid filter: {}", e); return 1 }
+    };
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let param = match unpackers::param::load_param_file(file_path, Some(&paramdef)) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    let fields: Vec<&str> = match args.value_of("fields") {
+        Some(fields) => fields.split(',').map(|f| f.trim()).collect(),
+        None => param.field_names.iter().map(|f| f.as_str()).collect(),
+    };
+    if let Some(field) = fields.iter().find(|f| param.field_index(f).is_none()) {
+        eprintln!("No field named {}.", field);
+        return 1
+    }
+    let rows = match params::query::query_param(&param, &filter) {
+        Ok(rows) => rows,
+        Err(e) => { eprintln!("Query failed: {}", e); return 1 }
+    };
+
+    if args.is_present("json") {
+        let rows: Vec<_> = rows.iter().map(|row| row.row).collect();
+        return print_json(&rows)
+    }
+    for row in &rows {
+        println!("  - {}", row.row);
+        for field in &fields {
+            if let Ok(value) = row.get(field) {
+                println!("    - {}  =  {}", field, value);
+            }
+        }
+    }
+    0
+}
+
+fn cmd_param_edit(args: &ArgMatches) -> i32 {
+    let file_path: &str = args.value_of("file").unwrap();
+    let edit: &str = args.value_of("edit").unwrap();
+    let paramdef_path: &str = args.value_of("paramdef").unwrap();
+    let output_path: &str = args.value_of("output").unwrap();
+    let edit = match params::query::parse_edit(edit) {
+        Ok(edit) => edit,
+        Err(e) => { eprintln!("Invalid edit: {}", e); return 1 }
+    };
+    let paramdef = match load_paramdef(paramdef_path) {
+        Ok(paramdef) => paramdef,
+        Err(e) => { eprintln!("Failed to load PARAMDEF: {:?}", e); return 1 }
+    };
+    let mut param = match unpackers::param::load_param_file(file_path, Some(&paramdef)) {
+        Ok(param) => param,
+        Err(e) => { eprintln!("Failed to load PARAM: {:?}", e); return 1 }
+    };
+    let ids = match params::query::edit_param(&mut param, &edit) {
+        Ok(ids) => ids,
+        Err(e) => { eprintln!("Edit failed: {}", e); return 1 }
+    };
+    if let Err(e) = repackers::param::pack_param_file(&mut param, Some(&paramdef), output_path) {
+        eprintln!("Failed to pack PARAM: {:?}", e);
+        return 1
+    }
+    if args.is_present("json") {
+        return print_json(&ids)
+    }
+    println!("Edited {}.", n_pluralise(ids.len() as i32, "row", "rows"));
+    0
+}
+
+fn cmd_parambnd(args: &ArgMatches) -> i32 {
+    let (bundle, paramdefs) = match load_bundle(args) {
+        Ok(loaded) => loaded,
+        Err(e) => { eprintln!("{}", e); return 1 }
+    };
+
+    if let Some(name) = args.value_of("param") {
+        let param = match bundle.get(name) {
+            Some(param) => param,
+            None => { eprintln!("No PARAM named {}.", name); return 1 }
+        };
+        if args.is_present("json") {
+            return print_json(param)
+        }
+        match paramdefs.get(&param.header.param_type) {
+            Some(paramdef) => unpackers::param::print_param_with_def(param, paramdef),
+            None => unpackers::param::print_param(param),
+        }
+        return 0
+    }
+    if args.is_present("json") {
+        return print_json(&bundle)
+    }
+    for (name, param) in &bundle.params {
+        let missing = if bundle.missing_defs.contains(name) { " (no PARAMDEF)" } else { "" };
+        println!(
+            "{}: {}, {}{}",
+            name,
+            param.header.param_type,
+            n_pluralise(param.rows.len() as i32, "row", "rows"),
+            missing
+        );
+    }
+    0
+}
+
 fn cmd_dat(args: &ArgMatches) -> i32 {
     let file_path: &str = args.value_of("file").unwrap();
     let output_path: &str = args.value_of("output").unwrap();
@@ -275,3 +974,17 @@ fn cmd_dat_pack(args: &ArgMatches) -> i32 {
         _ => 0
     }
 }
+
+#[cfg(feature = "serde")]
+fn print_json<T: serde::Serialize>(value: &T) -> i32 {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => { println!("{}", json); 0 }
+        Err(e) => { eprintln!("Failed to serialize to JSON: {}", e); 1 }
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+fn print_json<T>(_value: &T) -> i32 {
+    eprintln!("JSON output is not available, rir must be built with the serde feature.");
+    1
+}
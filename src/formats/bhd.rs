@@ -1,13 +1,58 @@
+//! BHD5 format, index of the BDT archives.
+//!
+//! Layout changes slightly with each game, see `BhdFormat`. From DS2
+//! onwards, BHD files are usually RSA-encrypted, see
+//! `unpackers::bhd::load_bhd`.
+
+use std::io;
+
 use nom::IResult;
-use nom::bytes::complete::tag;
+use nom::bytes::complete::{tag, take};
 use nom::multi::count;
 use nom::number::complete::*;
 use nom::sequence::tuple;
 
+use crate::formats::common::Pack;
+
+pub const MAGIC: &[u8] = b"BHD5";
+pub const HEADER_SIZE: usize = 0x18;
+pub const BUCKET_INFO_SIZE: usize = 0x8;
+pub const FILE_SIZE: usize = 0x10;
+pub const BDT_HEADER: &[u8] = b"BDF307D7R6\0\0\0\0\0\0";
+pub const DATA_ALIGN: usize = 0x10;
+pub const SHA_HASH_SIZE: usize = 0x20;
+pub const AES_KEY_SIZE: usize = 0x10;
+
+/// BHD5 layout variants, in chronological order.
+///
+/// Sekiro uses the DS3 layout.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BhdFormat {
+    DarkSouls1,
+    DarkSouls2,
+    DarkSouls3,
+    EldenRing,
+}
+
+impl BhdFormat {
+    /// Return the format for this short game name, e.g. "ds3".
+    pub fn from_game_name(name: &str) -> Option<BhdFormat> {
+        match name {
+            "ds1" => Some(BhdFormat::DarkSouls1),
+            "ds2" => Some(BhdFormat::DarkSouls2),
+            "ds3" | "sekiro" => Some(BhdFormat::DarkSouls3),
+            "er" => Some(BhdFormat::EldenRing),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BhdHeader {
     pub magic: Vec<u8>,
-    pub unk04: i8, // PC=0, PS3=-1
+    pub unk04: i8, // PC=-1, PS3=0
     pub unk05: i8,
     pub unk06: i8,
     pub unk07: i8,
@@ -15,11 +60,18 @@ pub struct BhdHeader {
     pub file_len: u32,
     pub num_buckets: u32,
     pub ofs_buckets: u32,
+    pub salt: Option<Vec<u8>>,  // DS2 and later.
 }
 
-fn parse_header(i: &[u8]) -> IResult<&[u8], BhdHeader> {
+fn parse_header(i: &[u8], format: BhdFormat) -> IResult<&[u8], BhdHeader> {
     let (i, (magic, flags, unk08, file_len, num_buckets, ofs_buckets)) =
-        tuple((tag(b"BHD5"), count(le_i8, 4), le_u32, le_u32, le_u32, le_u32))(i)?;
+        tuple((tag(MAGIC), count(le_i8, 4), le_u32, le_u32, le_u32, le_u32))(i)?;
+    let (i, salt) = if format >= BhdFormat::DarkSouls2 {
+        let (i, salt_len) = le_u32(i)?;
+        take(salt_len as usize)(i).map(|(i, s)| (i, Some(s.to_vec())))?
+    } else {
+        (i, None)
+    };
     Ok((
         i,
         BhdHeader {
@@ -32,11 +84,31 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], BhdHeader> {
             file_len,
             num_buckets,
             ofs_buckets,
+            salt,
         }
     ))
 }
 
+impl Pack for BhdHeader {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        f.write_all(&self.magic)?;
+        f.write_all(&[self.unk04 as u8, self.unk05 as u8, self.unk06 as u8, self.unk07 as u8])?;
+        f.write_all(&self.unk08.to_le_bytes())?;
+        f.write_all(&self.file_len.to_le_bytes())?;
+        f.write_all(&self.num_buckets.to_le_bytes())?;
+        f.write_all(&self.ofs_buckets.to_le_bytes())?;
+        let mut size = HEADER_SIZE;
+        if let Some(salt) = &self.salt {
+            f.write_all(&(salt.len() as u32).to_le_bytes())?;
+            f.write_all(salt)?;
+            size += 4 + salt.len();
+        }
+        Ok(size)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BhdBucketInfo {
     pub count: u32,
     pub offset: u32,
@@ -47,45 +119,218 @@ fn parse_bucket_info(i: &[u8]) -> IResult<&[u8], BhdBucketInfo> {
     Ok((i, BhdBucketInfo { count, offset }))
 }
 
+impl Pack for BhdBucketInfo {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        f.write_all(&self.count.to_le_bytes())?;
+        f.write_all(&self.offset.to_le_bytes())?;
+        Ok(BUCKET_INFO_SIZE)
+    }
+}
+
+/// Range of file data, from start to end offsets; -1 means unused.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BhdRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl BhdRange {
+    pub fn is_used(&self) -> bool { self.start != -1 && self.end != -1 && self.start != self.end }
+}
+
+fn parse_ranges(i: &[u8]) -> IResult<&[u8], Vec<BhdRange>> {
+    let (i, num_ranges) = le_u32(i)?;
+    count(
+        |i| tuple((le_i64, le_i64))(i).map(|(i, (start, end))| (i, BhdRange { start, end })),
+        num_ranges as usize
+    )(i)
+}
+
+/// SHA-256 hash of the file data within the ranges.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BhdShaHash {
+    pub hash: Vec<u8>,
+    pub ranges: Vec<BhdRange>,
+}
+
+fn parse_sha_hash(i: &[u8]) -> IResult<&[u8], BhdShaHash> {
+    let (i, (hash, ranges)) = tuple((take(SHA_HASH_SIZE), parse_ranges))(i)?;
+    Ok((i, BhdShaHash { hash: hash.to_vec(), ranges }))
+}
+
+/// AES-128 key for the file data within the ranges.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BhdAesKey {
+    pub key: Vec<u8>,
+    pub ranges: Vec<BhdRange>,
+}
+
+fn parse_aes_key(i: &[u8]) -> IResult<&[u8], BhdAesKey> {
+    let (i, (key, ranges)) = tuple((take(AES_KEY_SIZE), parse_ranges))(i)?;
+    Ok((i, BhdAesKey { key: key.to_vec(), ranges }))
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BhdFile {
-    pub hash: u32,
-    pub size: u32,
+    pub hash: u64,  // 64-bit for Elden Ring only.
+    pub size: u32,  // Padded size.
     pub offset: u64,
+    pub ofs_sha_hash: u64,
+    pub ofs_aes_key: u64,
+    pub unpadded_size: u64,  // DS3 and later, 0 if unknown.
+
+    pub sha_hash: Option<BhdShaHash>,
+    pub aes_key: Option<BhdAesKey>,
 }
 
+impl BhdFile {
+    /// Return the size of the actual file data.
+    pub fn data_size(&self) -> u64 {
+        if self.unpadded_size > 0 { self.unpadded_size } else { self.size as u64 }
+    }
+}
+
+/// Parse a DS1 file entry.
 pub fn parse_file(i: &[u8]) -> IResult<&[u8], BhdFile> {
-    let (i, (hash, size, offset)) = tuple((le_u32, le_u32, le_u64))(i)?;
-    Ok((i, BhdFile { hash, size, offset }))
+    parse_file_with_format(i, BhdFormat::DarkSouls1)
+}
+
+/// Parse a file entry, without its hash and key blocks.
+pub fn parse_file_with_format(i: &[u8], format: BhdFormat) -> IResult<&[u8], BhdFile> {
+    let (i, (hash, size, unpadded_size, offset)) = if format >= BhdFormat::EldenRing {
+        let (i, (hash, size, unpadded_size, offset)) =
+            tuple((le_u64, le_u32, le_u32, le_u64))(i)?;
+        (i, (hash, size, unpadded_size as u64, offset))
+    } else {
+        let (i, (hash, size, offset)) = tuple((le_u32, le_u32, le_u64))(i)?;
+        (i, (hash as u64, size, 0, offset))
+    };
+    let (i, (ofs_sha_hash, ofs_aes_key)) = if format >= BhdFormat::DarkSouls2 {
+        tuple((le_u64, le_u64))(i)?
+    } else {
+        (i, (0, 0))
+    };
+    let (i, unpadded_size) = if format == BhdFormat::DarkSouls3 {
+        le_u64(i)?
+    } else {
+        (i, unpadded_size)
+    };
+    Ok((
+        i,
+        BhdFile {
+            hash,
+            size,
+            offset,
+            ofs_sha_hash,
+            ofs_aes_key,
+            unpadded_size,
+            sha_hash: None,
+            aes_key: None,
+        }
+    ))
+}
+
+/// Only the DS1 layout is written, used by `repackers::bhd`.
+impl Pack for BhdFile {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        f.write_all(&(self.hash as u32).to_le_bytes())?;
+        f.write_all(&self.size.to_le_bytes())?;
+        f.write_all(&self.offset.to_le_bytes())?;
+        Ok(FILE_SIZE)
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bhd {
+    pub format: BhdFormat,
     pub header: BhdHeader,
     pub bucket_infos: Vec<BhdBucketInfo>,
     pub buckets: Vec<Vec<BhdFile>>,
 }
 
-/// Parse a BHD file into a usable Bhd struct.
+/// Parse a DS1 BHD file into a usable Bhd struct.
 pub fn parse(i: &[u8]) -> IResult<&[u8], Bhd> {
+    parse_with_format(i, BhdFormat::DarkSouls1)
+}
+
+/// Parse a decrypted BHD file into a usable Bhd struct.
+pub fn parse_with_format(i: &[u8], format: BhdFormat) -> IResult<&[u8], Bhd> {
     let full_file = i;
-    let (i, header) = parse_header(i)?;
+    let (_, header) = parse_header(i, format)?;
+    let i = &full_file[header.ofs_buckets as usize..];
     let (i, bucket_infos) = count(parse_bucket_info, header.num_buckets as usize)(i)?;
 
     let mut buckets: Vec<Vec<BhdFile>> = vec![];
     for b in 0..header.num_buckets {
         let bucket_info = &bucket_infos[b as usize];
         let bucket_data = &full_file[bucket_info.offset as usize..];
-        let (_, bucket) = count(parse_file, bucket_info.count as usize)(bucket_data)?;
+        let (_, mut bucket) = count(
+            |i| parse_file_with_format(i, format),
+            bucket_info.count as usize
+        )(bucket_data)?;
+        for file in &mut bucket {
+            if file.ofs_sha_hash != 0 {
+                let (_, sha_hash) = parse_sha_hash(&full_file[file.ofs_sha_hash as usize..])?;
+                file.sha_hash = Some(sha_hash);
+            }
+            if file.ofs_aes_key != 0 {
+                let (_, aes_key) = parse_aes_key(&full_file[file.ofs_aes_key as usize..])?;
+                file.aes_key = Some(aes_key);
+            }
+        }
         buckets.push(bucket);
     }
 
     Ok((
         i,
         Bhd {
+            format,
             header,
             bucket_infos,
             buckets,
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ds3() {
+        let mut data = vec!();
+        data.extend_from_slice(b"BHD5\xFF\0\0\0\x01\0\0\0");
+        data.extend_from_slice(&[0x70, 0, 0, 0, 1, 0, 0, 0, 0x20, 0, 0, 0]);
+        data.extend_from_slice(&[4, 0, 0, 0]);
+        data.extend_from_slice(b"salt");
+        // Bucket info, then file entry.
+        data.extend_from_slice(&[1, 0, 0, 0, 0x28, 0, 0, 0]);
+        data.extend_from_slice(&[0xEF, 0xBE, 0xAD, 0xDE, 0x20, 0, 0, 0]);
+        data.extend_from_slice(&0x1000u64.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&0x50u64.to_le_bytes());
+        data.extend_from_slice(&0x1Au64.to_le_bytes());
+        // AES key block.
+        data.extend_from_slice(&[0x11; 16]);
+        data.extend_from_slice(&[1, 0, 0, 0]);
+        data.extend_from_slice(&0i64.to_le_bytes());
+        data.extend_from_slice(&0x10i64.to_le_bytes());
+
+        let (_, bhd) = parse_with_format(&data, BhdFormat::DarkSouls3).unwrap();
+        assert_eq!(bhd.header.salt.as_deref(), Some(b"salt".as_ref()));
+        let file = &bhd.buckets[0][0];
+        assert_eq!(file.hash, 0xDEADBEEF);
+        assert_eq!(file.offset, 0x1000);
+        assert_eq!(file.data_size(), 0x1A);
+        assert!(file.sha_hash.is_none());
+        let aes_key = file.aes_key.as_ref().unwrap();
+        assert_eq!(aes_key.key, vec![0x11; 16]);
+        assert_eq!(aes_key.ranges[0].end, 0x10);
+        assert!(aes_key.ranges[0].is_used());
+    }
+}
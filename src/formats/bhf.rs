@@ -1,3 +1,5 @@
+use std::io;
+
 use nom::IResult;
 use nom::bytes::complete::{tag, take};
 use nom::multi::count;
@@ -5,9 +7,21 @@ use nom::number::complete::*;
 use nom::sequence::tuple;
 
 use crate::formats::bnd::{BinderOptions, format, use_be};
-use crate::formats::common::{sjis_to_string, take_cstring};
+use crate::formats::bnd4;
+use crate::formats::common::{Pack, sjis_to_string, take_cstring};
+use crate::utils::bin::u32_to_bytes;
+
+pub const MAGIC: &[u8] = b"BHF3";
+pub const BHF4_MAGIC: &[u8] = b"BHF4";
+pub const HEADER_SIZE: usize = 0x20;
+pub const BDT_MAGIC: &[u8] = b"BDF3";
+pub const BDT_HEADER_SIZE: usize = 0x10;
+/// Version and raw format of DS1 BHF3, with IDs, paths and uncompressed sizes.
+pub const DS1_VERSION: &[u8] = b"07D7R6\0\0";
+pub const DS1_RAW_FORMAT: u8 = 0x74;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BhfHeader {
     pub magic: Vec<u8>,
     pub version: Vec<u8>,
@@ -31,7 +45,7 @@ impl BinderOptions for BhfHeader {
 
 fn parse_header(i: &[u8]) -> IResult<&[u8], BhfHeader> {
     let (i, (magic, version, raw_format, endianness, u8_unks)) =
-        tuple((tag(b"BHF3"), take(8usize), le_u8, le_u8, count(le_u8, 2)))(i)?;
+        tuple((tag(MAGIC), take(8usize), le_u8, le_u8, count(le_u8, 2)))(i)?;
     let format = format(endianness, raw_format);
     let u32_parser = if use_be(endianness, format) { be_u32 } else { le_u32 };
     let (i, (num_files, last_unks)) =
@@ -53,7 +67,22 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], BhfHeader> {
     ))
 }
 
+impl Pack for BhfHeader {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let be = self.use_be();
+        f.write_all(&self.magic)?;
+        f.write_all(&self.version)?;
+        f.write_all(&[self.raw_format, self.endianness, self.unk0E, self.unk0F])?;
+        f.write_all(&u32_to_bytes(self.num_files, be))?;
+        f.write_all(&u32_to_bytes(self.unk14, be))?;
+        f.write_all(&u32_to_bytes(self.unk18, be))?;
+        f.write_all(&u32_to_bytes(self.unk1C, be))?;
+        Ok(HEADER_SIZE)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BhfFileInfo {
     pub unk00: u8,
     pub unk01: u8,
@@ -66,6 +95,14 @@ pub struct BhfFileInfo {
     pub uncompressed_size: u32,
 
     pub path: Option<String>,
+    // Copied from the header, as they are required to pack the info.
+    pub format: u8,
+    pub big_endian: bool,
+}
+
+impl BinderOptions for BhfFileInfo {
+    fn format(&self) -> u8 { self.format }
+    fn use_be(&self) -> bool { self.big_endian }
 }
 
 fn parse_file_info<'a>(i: &'a[u8], header: &BhfHeader) -> IResult<&'a[u8], BhfFileInfo> {
@@ -89,16 +126,87 @@ fn parse_file_info<'a>(i: &'a[u8], header: &BhfHeader) -> IResult<&'a[u8], BhfFi
             ofs_path,
             uncompressed_size,
             path: None,
+            format: header.format(),
+            big_endian: header.use_be(),
         }
     ))
 }
 
+impl Pack for BhfFileInfo {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let be = self.use_be();
+        f.write_all(&[self.unk00, self.unk01, self.unk02, self.unk03])?;
+        f.write_all(&u32_to_bytes(self.size, be))?;
+        f.write_all(&u32_to_bytes(self.ofs_data, be))?;
+        if self.has_ids() {
+            f.write_all(&u32_to_bytes(self.id, be))?;
+        }
+        if self.has_paths() {
+            f.write_all(&u32_to_bytes(self.ofs_path, be))?;
+        }
+        if self.has_uncomp_size() {
+            f.write_all(&u32_to_bytes(self.uncompressed_size, be))?;
+        }
+        Ok(self.file_info_size())
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bhf {
     pub header: BhfHeader,
     pub file_infos: Vec<BhfFileInfo>,
 }
 
+impl Bhf {
+    /// Create a DS1 BHF3 with these internal paths and IDs from 0.
+    ///
+    /// Sizes and offsets are set when packing.
+    pub fn new(paths: &[String]) -> Bhf {
+        let header = BhfHeader {
+            magic: MAGIC.to_vec(),
+            version: DS1_VERSION.to_vec(),
+            raw_format: DS1_RAW_FORMAT,
+            endianness: 0,
+            unk0E: 0,
+            unk0F: 0,
+            num_files: paths.len() as u32,
+            unk14: 0,
+            unk18: 0,
+            unk1C: 0,
+        };
+        let file_infos = paths.iter().enumerate().map(|(index, path)| BhfFileInfo {
+            unk00: 0x40,
+            unk01: 0,
+            unk02: 0,
+            unk03: 0,
+            size: 0,
+            ofs_data: 0,
+            id: index as u32,
+            ofs_path: 0,
+            uncompressed_size: 0,
+            path: Some(path.to_owned()),
+            format: header.format(),
+            big_endian: header.use_be(),
+        }).collect();
+        Bhf { header, file_infos }
+    }
+
+    /// Return the alignment of files data in the BDT.
+    ///
+    /// It is guessed from the data offsets, as the largest power of two
+    /// dividing all of them, with a minimum of 0x10.
+    pub fn data_alignment(&self) -> usize {
+        let ofs_bits = self.file_infos.iter()
+            .filter(|info| info.size > 0)
+            .fold(0u32, |bits, info| bits | info.ofs_data);
+        match ofs_bits {
+            0 => 0x10,
+            bits => (1usize << bits.trailing_zeros()).max(0x10),
+        }
+    }
+}
+
 pub fn parse(i: &[u8]) -> IResult<&[u8], Bhf> {
     let full_file = i;
     let (i, header) = parse_header(i)?;
@@ -118,3 +226,11 @@ pub fn parse(i: &[u8]) -> IResult<&[u8], Bhf> {
     }
     Ok((full_file, Bhf { header, file_infos }))
 }
+
+/// BHF4 header and entries share the BND4 layout, only the magic differs.
+pub type Bhf4 = bnd4::Bnd4;
+
+/// Parse a BHF4 file, returning it with its full file data.
+pub fn parse_bhf4(i: &[u8]) -> IResult<&[u8], Bhf4> {
+    bnd4::parse_with_magic(i, BHF4_MAGIC)
+}
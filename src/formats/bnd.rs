@@ -1,19 +1,26 @@
+use std::io;
+
 use nom::IResult;
 use nom::bytes::complete::{tag, take};
 use nom::multi::count;
 use nom::number::complete::*;
 use nom::sequence::tuple;
 
-use crate::utils::bin::has_flag;
-use crate::formats::common::{sjis_to_string, take_cstring};
+use crate::utils::bin::{has_flag, u32_to_bytes};
+use crate::formats::common::{Pack, sjis_to_string, take_cstring};
+
+pub const FORMAT_BE: u8              = 0b00000001;
+pub const FORMAT_HAS_ID: u8          = 0b00000010;
+pub const FORMAT_HAS_NAME1: u8       = 0b00000100;
+pub const FORMAT_HAS_NAME2: u8       = 0b00001000;
+pub const FORMAT_LONG_OFFSETS: u8    = 0b00010000;
+pub const FORMAT_HAS_UNCOMP_SIZE: u8 = 0b00100000;
 
-const FORMAT_BE: u8              = 0b00000001;
-const FORMAT_HAS_ID: u8          = 0b00000010;
-const FORMAT_HAS_NAME1: u8       = 0b00000100;
-const FORMAT_HAS_NAME2: u8       = 0b00001000;
-const FORMAT_HAS_UNCOMP_SIZE: u8 = 0b00100000;
+pub const HEADER_SIZE: usize = 0x20;
+pub const DATA_ALIGN: usize = 0x10;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BndHeader {
     pub magic: Vec<u8>,
     pub version: Vec<u8>,
@@ -46,6 +53,20 @@ pub trait BinderOptions {
     fn has_uncomp_size(&self) -> bool {
         has_flag(self.format(), FORMAT_HAS_UNCOMP_SIZE)
     }
+
+    /// Return whether files data offsets are 64 bits (BND4 only).
+    fn has_long_offsets(&self) -> bool {
+        has_flag(self.format(), FORMAT_LONG_OFFSETS)
+    }
+
+    /// Return the size of a BND3 file info entry for these options.
+    fn file_info_size(&self) -> usize {
+        let mut size = 0xC;
+        if self.has_ids() { size += 4 }
+        if self.has_paths() { size += 4 }
+        if self.has_uncomp_size() { size += 4 }
+        size
+    }
 }
 
 impl BinderOptions for BndHeader {
@@ -94,7 +115,22 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], BndHeader> {
     ))
 }
 
+impl Pack for BndHeader {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let be = self.use_be();
+        f.write_all(&self.magic)?;
+        f.write_all(&self.version)?;
+        f.write_all(&[self.raw_format, self.endianness, self.bit_endianness, self.flags0F])?;
+        f.write_all(&u32_to_bytes(self.num_files, be))?;
+        f.write_all(&u32_to_bytes(self.ofs_data, be))?;
+        f.write_all(&u32_to_bytes(self.unk18, be))?;
+        f.write_all(&u32_to_bytes(self.unk1C, be))?;
+        Ok(HEADER_SIZE)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BndFileInfo {
     pub unk00: u8,
     pub unk01: u8,
@@ -107,6 +143,14 @@ pub struct BndFileInfo {
     pub uncompressed_size: u32,
 
     pub path: Option<String>,
+    // Copied from the header, as they are required to pack the info.
+    pub format: u8,
+    pub big_endian: bool,
+}
+
+impl BinderOptions for BndFileInfo {
+    fn format(&self) -> u8 { self.format }
+    fn use_be(&self) -> bool { self.big_endian }
 }
 
 fn parse_file_info<'a>(i: &'a[u8], header: &BndHeader) -> IResult<&'a[u8], BndFileInfo> {
@@ -130,11 +174,33 @@ fn parse_file_info<'a>(i: &'a[u8], header: &BndHeader) -> IResult<&'a[u8], BndFi
             ofs_path,
             uncompressed_size,
             path: None,
+            format: header.format(),
+            big_endian: header.use_be(),
         }
     ))
 }
 
+impl Pack for BndFileInfo {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let be = self.use_be();
+        f.write_all(&[self.unk00, self.unk01, self.unk02, self.unk03])?;
+        f.write_all(&u32_to_bytes(self.size, be))?;
+        f.write_all(&u32_to_bytes(self.ofs_data, be))?;
+        if self.has_ids() {
+            f.write_all(&u32_to_bytes(self.id, be))?;
+        }
+        if self.has_paths() {
+            f.write_all(&u32_to_bytes(self.ofs_path, be))?;
+        }
+        if self.has_uncomp_size() {
+            f.write_all(&u32_to_bytes(self.uncompressed_size, be))?;
+        }
+        Ok(self.file_info_size())
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bnd {
     pub header: BndHeader,
     pub file_infos: Vec<BndFileInfo>,
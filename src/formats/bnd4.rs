@@ -0,0 +1,334 @@
+//! BND4 format, used from DS2 onwards.
+//!
+//! Shares the format flags of BND3 through `BinderOptions`, with 64-bit
+//! sizes and offsets, optional UTF-16 paths and an optional hash table.
+//! BHF4 headers use the same layout with a different magic.
+
+use nom::IResult;
+use nom::bytes::complete::{tag, take};
+use nom::error::ErrorKind;
+use nom::multi::count;
+use nom::number::complete::*;
+use nom::sequence::tuple;
+
+use crate::formats::bnd::{BinderOptions, FORMAT_HAS_NAME1, format};
+use crate::formats::common::{sjis_to_string, take_cstring, take_utf16_cstring, utf16_to_string};
+
+pub const MAGIC: &[u8] = b"BND4";
+pub const HEADER_SIZE: usize = 0x40;
+
+/// Value of `extended` when the binder has a hash table.
+pub const EXTENDED_HASH_TABLE: u8 = 4;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4Header {
+    pub magic: Vec<u8>,
+    pub unk04: u8,
+    pub unk05: u8,
+    pub unk06: u8,
+    pub unk07: u8,
+    pub unk08: u8,
+    pub endianness: u8,
+    pub bit_endianness: u8,  // Inverted compared to BND3: 0 means big endian bit order.
+    pub unk0B: u8,
+    pub num_files: u32,
+    pub header_size: u64,
+    pub version: Vec<u8>,
+    pub file_info_size: u64,
+    pub ofs_data: u64,  // End of headers, including the hash table. Always 0 for BHF4.
+    pub unicode: u8,
+    pub raw_format: u8,
+    pub extended: u8,
+    pub unk33: u8,
+    pub unk34: u32,
+    pub ofs_hash_table: u64,
+}
+
+impl Bnd4Header {
+    pub fn is_unicode(&self) -> bool { self.unicode == 1 }
+    pub fn has_hash_table(&self) -> bool { self.extended == EXTENDED_HASH_TABLE }
+}
+
+impl BinderOptions for Bnd4Header {
+    /// See `formats::bnd::format` function.
+    fn format(&self) -> u8 { format((self.bit_endianness == 0) as u8, self.raw_format) }
+
+    /// Unlike BND3, only the endianness byte is used.
+    fn use_be(&self) -> bool { self.endianness == 1 }
+
+    fn file_info_size(&self) -> usize { self.file_info_size as usize }
+}
+
+fn parse_header<'a>(i: &'a[u8], magic: &[u8]) -> IResult<&'a[u8], Bnd4Header> {
+    let (i, (magic, unks, endianness, bit_endianness, unk0B)) =
+        tuple((tag(magic), count(le_u8, 5), le_u8, le_u8, le_u8))(i)?;
+    let use_be = endianness == 1;
+    let p_u32 = if use_be { be_u32 } else { le_u32 };
+    let p_u64 = if use_be { be_u64 } else { le_u64 };
+    let (i, (num_files, header_size, version, file_info_size, ofs_data)) =
+        tuple((p_u32, p_u64, take(8usize), p_u64, p_u64))(i)?;
+    let (i, (unicode, raw_format, extended, unk33, unk34, ofs_hash_table)) =
+        tuple((le_u8, le_u8, le_u8, le_u8, p_u32, p_u64))(i)?;
+    Ok((
+        i,
+        Bnd4Header {
+            magic: magic.to_vec(),
+            unk04: unks[0],
+            unk05: unks[1],
+            unk06: unks[2],
+            unk07: unks[3],
+            unk08: unks[4],
+            endianness,
+            bit_endianness,
+            unk0B,
+            num_files,
+            header_size,
+            version: version.to_vec(),
+            file_info_size,
+            ofs_data,
+            unicode,
+            raw_format,
+            extended,
+            unk33,
+            unk34,
+            ofs_hash_table,
+        }
+    ))
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4FileInfo {
+    pub unk00: u8,
+    pub unk01: u8,
+    pub unk02: u8,
+    pub unk03: u8,
+    pub unk04: i32,
+    pub size: u64,
+    pub uncompressed_size: u64,
+    pub ofs_data: u64,
+    pub id: u32,
+    pub ofs_path: u32,
+
+    pub path: Option<String>,
+}
+
+fn parse_file_info<'a>(i: &'a[u8], header: &Bnd4Header) -> IResult<&'a[u8], Bnd4FileInfo> {
+    let use_be = header.use_be();
+    let p_i32 = if use_be { be_i32 } else { le_i32 };
+    let p_u32 = if use_be { be_u32 } else { le_u32 };
+    let p_u64 = if use_be { be_u64 } else { le_u64 };
+    let (i, (flags, unk04, size)) = tuple((count(le_u8, 4), p_i32, p_u64))(i)?;
+
+    let (i, uncompressed_size) = if header.has_uncomp_size() { p_u64(i)? } else { (i, 0) };
+    let (i, ofs_data) = if header.has_long_offsets() {
+        p_u64(i)?
+    } else {
+        p_u32(i).map(|(i, o)| (i, o as u64))?
+    };
+    let (i, id) = if header.has_ids() { p_u32(i)? } else { (i, 0) };
+    let (i, ofs_path) = if header.has_paths() { p_u32(i)? } else { (i, 0) };
+    // Binders with only the first name flag store the ID after the path.
+    let (i, id) = if header.format() == FORMAT_HAS_NAME1 {
+        let (i, (id, _)) = tuple((p_u32, p_u32))(i)?;
+        (i, id)
+    } else {
+        (i, id)
+    };
+
+    Ok((
+        i,
+        Bnd4FileInfo {
+            unk00: flags[0],
+            unk01: flags[1],
+            unk02: flags[2],
+            unk03: flags[3],
+            unk04,
+            size,
+            uncompressed_size,
+            ofs_data,
+            id,
+            ofs_path,
+            path: None,
+        }
+    ))
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4HashGroup {
+    pub length: u32,
+    pub index: u32,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4PathHash {
+    pub hash: u32,
+    pub index: u32,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4HashTable {
+    pub ofs_hashes: u64,
+    pub num_groups: u32,
+    pub unk0C: u8,
+    pub unk0D: u8,
+    pub unk0E: u8,
+    pub unk0F: u8,
+    pub groups: Vec<Bnd4HashGroup>,
+    pub hashes: Vec<Bnd4PathHash>,
+}
+
+fn parse_hash_table<'a>(
+    i: &'a[u8],
+    full_file: &'a[u8],
+    num_files: usize,
+    use_be: bool,
+) -> IResult<&'a[u8], Bnd4HashTable> {
+    let p_u32 = if use_be { be_u32 } else { le_u32 };
+    let p_u64 = if use_be { be_u64 } else { le_u64 };
+    let (i, (ofs_hashes, num_groups, unks)) = tuple((p_u64, p_u32, count(le_u8, 4)))(i)?;
+    let (i, groups) = count(
+        |i| tuple((p_u32, p_u32))(i).map(|(i, (length, index))| (i, Bnd4HashGroup { length, index })),
+        num_groups as usize
+    )(i)?;
+    let (_, hashes) = count(
+        |i| tuple((p_u32, p_u32))(i).map(|(i, (hash, index))| (i, Bnd4PathHash { hash, index })),
+        num_files
+    )(data_from(full_file, ofs_hashes as usize)?)?;
+    Ok((
+        i,
+        Bnd4HashTable {
+            ofs_hashes,
+            num_groups,
+            unk0C: unks[0],
+            unk0D: unks[1],
+            unk0E: unks[2],
+            unk0F: unks[3],
+            groups,
+            hashes,
+        }
+    ))
+}
+
+/// Return the file data from this offset, failing instead of panicking
+/// if the offset is out of the file.
+fn data_from(full_file: &[u8], offset: usize) -> Result<&[u8], nom::Err<(&[u8], ErrorKind)>> {
+    full_file.get(offset..).ok_or(nom::Err::Failure((full_file, ErrorKind::Eof)))
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bnd4 {
+    pub header: Bnd4Header,
+    pub file_infos: Vec<Bnd4FileInfo>,
+    pub hash_table: Option<Bnd4HashTable>,
+}
+
+/// Parse a BND4 file to a Bnd4 struct.
+///
+/// On success, returns the full BND data along with the Bnd4 struct
+/// instead of the remaining data.
+pub fn parse(i: &[u8]) -> IResult<&[u8], Bnd4> {
+    parse_with_magic(i, MAGIC)
+}
+
+/// Parse a file using the BND4 layout but with another magic.
+pub fn parse_with_magic<'a>(i: &'a[u8], magic: &[u8]) -> IResult<&'a[u8], Bnd4> {
+    let full_file = i;
+    let (_, header) = parse_header(i, magic)?;
+    let file_info_size = header.file_info_size as usize;
+    let mut file_infos = Vec::with_capacity(header.num_files as usize);
+    for index in 0..header.num_files as usize {
+        // Use the declared info size to skip potential unknown fields.
+        let ofs_info = header.header_size as usize + index * file_info_size;
+        let (_, info) = parse_file_info(data_from(full_file, ofs_info)?, &header)?;
+        file_infos.push(info);
+    }
+
+    if header.has_paths() {
+        for info in &mut file_infos {
+            let path_data = data_from(full_file, info.ofs_path as usize)?;
+            info.path = if header.is_unicode() {
+                let (_, utf16_path) = take_utf16_cstring(path_data, header.use_be())?;
+                utf16_to_string(&utf16_path)
+            } else {
+                let (_, sjis_path) = take_cstring(path_data)?;
+                sjis_to_string(sjis_path)
+            };
+            if info.path.is_none() {
+                eprintln!("Failed to parse path at offset {:X}.", info.ofs_path);
+            }
+        }
+    }
+
+    let hash_table = if header.has_hash_table() {
+        let ofs_hash_table = header.ofs_hash_table as usize;
+        let (_, hash_table) = parse_hash_table(
+            data_from(full_file, ofs_hash_table)?,
+            full_file,
+            file_infos.len(),
+            header.use_be()
+        )?;
+        Some(hash_table)
+    } else {
+        None
+    };
+
+    Ok((full_file, Bnd4 { header, file_infos, hash_table }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // Unicode BND4 with IDs, names, uncompressed size and hash table.
+        let mut data = vec!();
+        data.extend_from_slice(b"BND4\0\0\0\0\0\0\x01\0");
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&0x40u64.to_le_bytes());
+        data.extend_from_slice(b"07D7R6\0\0");
+        data.extend_from_slice(&0x24u64.to_le_bytes());
+        data.extend_from_slice(&0x90u64.to_le_bytes());
+        data.extend_from_slice(&[1, 0x74, 4, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&0x70u64.to_le_bytes());
+        // File info.
+        data.extend_from_slice(&[0x40, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data.extend_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(&0x90u32.to_le_bytes());
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&0x64u32.to_le_bytes());
+        // Path and padding.
+        data.extend_from_slice(b"a\0.\0x\0\0\0\0\0\0\0");
+        // Hash table.
+        data.extend_from_slice(&0x88u64.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 0, 0, 0x10, 8, 8, 0]);
+        data.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0xEF, 0xBE, 0xAD, 0xDE, 0, 0, 0, 0]);
+        data.extend_from_slice(b"ABCD");
+
+        let (_, bnd4) = parse(&data).unwrap();
+        assert_eq!(bnd4.header.num_files, 1);
+        assert_eq!(bnd4.header.format(), 0x2E);
+        assert!(!bnd4.header.has_long_offsets());
+        let info = &bnd4.file_infos[0];
+        assert_eq!(info.size, 4);
+        assert_eq!(info.ofs_data, 0x90);
+        assert_eq!(info.id, 7);
+        assert_eq!(info.path.as_deref(), Some("a.x"));
+        let hash_table = bnd4.hash_table.unwrap();
+        assert_eq!(hash_table.groups.len(), 1);
+        assert_eq!(hash_table.hashes[0].hash, 0xDEADBEEF);
+
+        // Offsets out of the file are errors.
+        assert!(parse(&data[..0x50]).is_err());
+        data[0x70] = 0xFF;
+        assert!(parse(&data).is_err());
+    }
+}
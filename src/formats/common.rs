@@ -4,6 +4,9 @@ use std::io;
 use encoding_rs::SHIFT_JIS;
 use nom::IResult;
 use nom::bytes::complete::take_while;
+use nom::number::complete::{be_u16, le_u16};
+
+use crate::utils::bin as utils_bin;
 
 /// Trait for structs that are easy to pack to bytes.
 pub trait Pack {
@@ -25,6 +28,34 @@ pub fn take_cstring_from(i: &[u8], max_length: usize) -> IResult<&[u8], &[u8]> {
     take_cstring(i).map(|(_, s)| Ok((&i[max_length..], s)) )?
 }
 
+/// Parse a zero-terminated UTF-16 string from the slice.
+///
+/// Unlike `take_cstring`, the terminator is consumed.
+pub fn take_utf16_cstring(i: &[u8], use_be: bool) -> IResult<&[u8], Vec<u16>> {
+    let p_u16 = if use_be { be_u16 } else { le_u16 };
+    let mut chars = vec!();
+    let mut i = i;
+    loop {
+        let (rest, c) = p_u16(i)?;
+        i = rest;
+        if c == 0 {
+            break
+        }
+        chars.push(c);
+    }
+    Ok((i, chars))
+}
+
+/// Decode UTF-16 code units.
+pub fn utf16_to_string(i: &[u16]) -> Option<String> {
+    String::from_utf16(i).ok()
+}
+
+/// Encode a string as UTF-16 bytes in the requested byte order.
+pub fn string_to_utf16(s: &str, use_be: bool) -> Vec<u8> {
+    s.encode_utf16().flat_map(|c| utils_bin::u16_to_bytes(c, use_be).to_vec()).collect()
+}
+
 /// Decode a Shift JIS encoded byte slice.
 pub fn sjis_to_string(i: &[u8]) -> Option<String> {
     let (cow, _, has_errors) = SHIFT_JIS.decode(i);
@@ -34,6 +65,15 @@ pub fn sjis_to_string(i: &[u8]) -> Option<String> {
     Some(cow.to_string())
 }
 
+/// Encode a string as Shift JIS, or None if it has unmappable characters.
+pub fn string_to_sjis(s: &str) -> Option<Vec<u8>> {
+    let (cow, _, has_errors) = SHIFT_JIS.encode(s);
+    if has_errors {
+        return None
+    }
+    Some(cow.to_vec())
+}
+
 /// Decode a Shift JIS encoded byte slice or hex representation.
 pub fn sjis_to_string_lossy(i: &[u8]) -> String {
     sjis_to_string(i).unwrap_or(format!("{:x?}", i))
@@ -41,12 +81,22 @@ pub fn sjis_to_string_lossy(i: &[u8]) -> String {
 
 /// Represent an integer that can be 32 or 64 bits,
 /// depending on the platform and flags used.
+///
+/// Build 32-bit values with `from_u32` so the whole union is initialized.
+#[derive(Clone, Copy)]
 pub union VarSizeInt {
     pub vu32: u32,
     pub vu64: u64,
 }
 
 impl VarSizeInt {
+    /// Create a VarSizeInt holding a 32-bit value, with zeroed upper bytes.
+    pub fn from_u32(v: u32) -> VarSizeInt {
+        let mut vsi = VarSizeInt { vu64: 0 };
+        vsi.vu32 = v;
+        vsi
+    }
+
     /// Set u64 value if condition is true, else the u32 as u64.
     pub fn u64_if(&self, c: bool) -> u64 {
         if c { unsafe { self.vu64 } } else { unsafe { self.vu32 as u64 } }
@@ -59,6 +109,21 @@ impl fmt::Debug for VarSizeInt {
     }
 }
 
+/// Serialized as the raw 64-bit storage, whatever size is used.
+#[cfg(feature = "serde")]
+impl serde::Serialize for VarSizeInt {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(unsafe { self.vu64 })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for VarSizeInt {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(|v| VarSizeInt { vu64: v })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,4 +154,28 @@ mod tests {
             Ok((b"\x20\x20\x20\x20\x20\x20\x20\x20".as_ref(), b"ABC".as_ref()))
         );
     }
+
+    #[test]
+    fn test_take_utf16_cstring() {
+        assert_eq!(
+            take_utf16_cstring(b"A\0B\0\0\0\xFF", false),
+            Ok((b"\xFF".as_ref(), vec![0x41, 0x42]))
+        );
+        assert_eq!(
+            take_utf16_cstring(b"\0A\0B\0\0", true),
+            Ok((b"".as_ref(), vec![0x41, 0x42]))
+        );
+        assert!(take_utf16_cstring(b"A\0B", false).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_var_size_int_serde() {
+        let vsi = VarSizeInt::from_u32(0x1234);
+        let json = serde_json::to_string(&vsi).unwrap();
+        let vsi: VarSizeInt = serde_json::from_str(&json).unwrap();
+        assert_eq!(unsafe { vsi.vu32 }, 0x1234);
+        let vsi: VarSizeInt = serde_json::from_str("1311768467463790320").unwrap();
+        assert_eq!(unsafe { vsi.vu64 }, 0x1234_5678_9ABC_DEF0);
+    }
 }
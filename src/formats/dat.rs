@@ -13,6 +13,7 @@ pub const MAGIC: u32 = 0x1E048000;  // Maybe it's 2 shorts and the 1st is paddin
 pub const HEADER_PAD: usize = 0x38;  // Padding after the header.
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DatHeader {
     pub unk00: u32,
     pub num_files: u32,
@@ -35,6 +36,7 @@ pub const FILE_ENTRY_SIZE: usize = 0x40;
 pub const FILE_ENTRY_NAME_MAXLEN: usize = 0x34;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DatFileEntry {
     pub name: String,
     pub size: u32,
@@ -65,6 +67,7 @@ pub const INTERNAL_PATH_SEP: char = '/';
 pub const DATA_ALIGN: usize = 0x8000;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dat {
     pub header: DatHeader,
     pub files: Vec<DatFileEntry>,
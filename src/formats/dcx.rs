@@ -1,12 +1,13 @@
 //! DCX format.
 //!
-//! Support DFLT method only.
+//! Support DFLT, EDGE and ZSTD methods. Header values change slightly
+//! across games, see `DcxVariant`.
 
 use std::io;
 
 use nom::IResult;
 use nom::branch::alt;
-use nom::bytes::complete::tag;
+use nom::bytes::complete::{tag, take};
 use nom::multi::count;
 use nom::number::complete::*;
 use nom::sequence::tuple;
@@ -17,6 +18,7 @@ pub const HEADER_MAGIC: &[u8] = b"DCX\0";
 pub const HEADER_SIZE: usize = 0x18;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DcxHeader {
     pub magic: Vec<u8>,
     pub unk04: u32,
@@ -48,6 +50,7 @@ pub const SIZES_CHUNK_MAGIC: &[u8] = b"DCS\0";
 pub const SIZES_CHUNK_SIZE: usize = 0xC;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DcxSizes {
     pub magic: Vec<u8>,
     pub uncompressed_size: u32,
@@ -70,9 +73,10 @@ impl Pack for DcxSizes {
 }
 
 pub const PARAMS_CHUNK_MAGIC: &[u8] = b"DCP\0";
-pub const PARAMS_CHUNK_SIZE: usize = 0x32;
+pub const PARAMS_CHUNK_SIZE: usize = 0x20;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DcxParams {
     pub magic: Vec<u8>,
     pub method: Vec<u8>,
@@ -91,7 +95,7 @@ fn parse_params(i: &[u8]) -> IResult<&[u8], DcxParams> {
     let (i, (magic, method, ofs_dca, flags, unk10, unk14, unk18, unk1C)) =
         tuple((
             tag(PARAMS_CHUNK_MAGIC),
-            alt((tag(b"DFLT"), tag(b"EDGE"), tag(b"KRAK"))),
+            alt((tag(b"DFLT"), tag(b"EDGE"), tag(b"KRAK"), tag(b"ZSTD"))),
             be_u32,
             count(be_u8, 4),
             be_u32,
@@ -134,10 +138,16 @@ impl Pack for DcxParams {
     }
 }
 
+/// Compression level used by the ZSTD method, stored in `unk0C`.
+pub const ZSTD_LEVEL: u8 = 0x15;
+/// Value of `unk1C` for the ZSTD method.
+pub const ZSTD_UNK1C: u32 = 0x0001_0100;
+
 pub const ARCHIVE_CHUNK_MAGIC: &[u8] = b"DCA\0";
 pub const ARCHIVE_CHUNK_SIZE: usize = 0x8;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DcxArchive {
     pub magic: Vec<u8>,
     pub ofs_data: u32,
@@ -156,22 +166,268 @@ impl Pack for DcxArchive {
     }
 }
 
+pub const EDGE_TABLE_MAGIC: &[u8] = b"EgdT";
+pub const EDGE_TABLE_HEADER_SIZE: usize = 0x24;
+pub const DCP_EDGE_TABLE_HEADER_SIZE: usize = 0x20;
+pub const EDGE_CHUNK_INFO_SIZE: usize = 0x10;
+pub const EDGE_CHUNK_SIZE: usize = 0x10000;
+pub const EDGE_DATA_ALIGN: usize = 0x10;
+
+/// Chunk table of the EDGE method, right after the archive chunk.
+///
+/// Data is split in chunks of `chunk_size` bytes, each compressed with
+/// raw deflate (no zlib header) and aligned to 0x10.
+///
+/// The DCP_EDGE table is at the end of the file and has no
+/// `last_chunk_size`, so it is 0 and the following values are moved
+/// back by 4 bytes.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DcxEdgeTable {
+    pub magic: Vec<u8>,
+    pub unk04: u32,
+    pub ofs_chunk_infos: u32,
+    pub chunk_info_size: u32,
+    pub chunk_size: u32,
+    pub last_chunk_size: u32,  // Uncompressed.
+    pub table_size: u32,
+    pub num_chunks: u32,
+    pub unk20: u32,
+    pub chunk_infos: Vec<DcxEdgeChunkInfo>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DcxEdgeChunkInfo {
+    pub unk00: u32,
+    pub offset: u32,  // Relative to the start of the data.
+    pub size: u32,
+    pub compressed: u32,
+}
+
+impl DcxEdgeChunkInfo {
+    pub fn is_compressed(&self) -> bool { self.compressed == 1 }
+}
+
+fn parse_edge_chunk_info(i: &[u8]) -> IResult<&[u8], DcxEdgeChunkInfo> {
+    let (i, (unk00, offset, size, compressed)) = tuple((be_u32, be_u32, be_u32, be_u32))(i)?;
+    Ok((i, DcxEdgeChunkInfo { unk00, offset, size, compressed }))
+}
+
+fn parse_edge_table(i: &[u8]) -> IResult<&[u8], DcxEdgeTable> {
+    let full_table = i;
+    let (_, (magic, unk04, ofs_chunk_infos, chunk_info_size, chunk_size, last_chunk_size)) =
+        tuple((tag(EDGE_TABLE_MAGIC), be_u32, be_u32, be_u32, be_u32, be_u32))(i)?;
+    let (_, (table_size, num_chunks, unk20)) =
+        tuple((be_u32, be_u32, be_u32))(&full_table[0x18..])?;
+    let (i, chunk_infos) = count(parse_edge_chunk_info, num_chunks as usize)(
+        &full_table[ofs_chunk_infos as usize..]
+    )?;
+    Ok((
+        i,
+        DcxEdgeTable {
+            magic: magic.to_vec(),
+            unk04,
+            ofs_chunk_infos,
+            chunk_info_size,
+            chunk_size,
+            last_chunk_size,
+            table_size,
+            num_chunks,
+            unk20,
+            chunk_infos,
+        }
+    ))
+}
+
+fn parse_dcp_edge_table(i: &[u8]) -> IResult<&[u8], DcxEdgeTable> {
+    let full_table = i;
+    let (_, (magic, unk04, ofs_chunk_infos, chunk_info_size, chunk_size)) =
+        tuple((tag(EDGE_TABLE_MAGIC), be_u32, be_u32, be_u32, be_u32))(i)?;
+    let (_, (table_size, num_chunks, unk20)) =
+        tuple((be_u32, be_u32, be_u32))(&full_table[0x14..])?;
+    let (i, chunk_infos) = count(parse_edge_chunk_info, num_chunks as usize)(
+        &full_table[ofs_chunk_infos as usize..]
+    )?;
+    Ok((
+        i,
+        DcxEdgeTable {
+            magic: magic.to_vec(),
+            unk04,
+            ofs_chunk_infos,
+            chunk_info_size,
+            chunk_size,
+            last_chunk_size: 0,
+            table_size,
+            num_chunks,
+            unk20,
+            chunk_infos,
+        }
+    ))
+}
+
+impl DcxEdgeTable {
+    /// Write the table with the DCP_EDGE layout, see `DcxEdgeTable`.
+    pub fn write_dcp(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        f.write_all(&self.magic)?;
+        f.write_all(&self.unk04.to_be_bytes())?;
+        f.write_all(&self.ofs_chunk_infos.to_be_bytes())?;
+        f.write_all(&self.chunk_info_size.to_be_bytes())?;
+        f.write_all(&self.chunk_size.to_be_bytes())?;
+        f.write_all(&self.table_size.to_be_bytes())?;
+        f.write_all(&self.num_chunks.to_be_bytes())?;
+        f.write_all(&self.unk20.to_be_bytes())?;
+        self.write_chunk_infos(f)?;
+        Ok(DCP_EDGE_TABLE_HEADER_SIZE + self.chunk_infos.len() * EDGE_CHUNK_INFO_SIZE)
+    }
+
+    fn write_chunk_infos(&self, f: &mut dyn io::Write) -> io::Result<()> {
+        for chunk_info in &self.chunk_infos {
+            f.write_all(&chunk_info.unk00.to_be_bytes())?;
+            f.write_all(&chunk_info.offset.to_be_bytes())?;
+            f.write_all(&chunk_info.size.to_be_bytes())?;
+            f.write_all(&chunk_info.compressed.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Pack for DcxEdgeTable {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        f.write_all(&self.magic)?;
+        f.write_all(&self.unk04.to_be_bytes())?;
+        f.write_all(&self.ofs_chunk_infos.to_be_bytes())?;
+        f.write_all(&self.chunk_info_size.to_be_bytes())?;
+        f.write_all(&self.chunk_size.to_be_bytes())?;
+        f.write_all(&self.last_chunk_size.to_be_bytes())?;
+        f.write_all(&self.table_size.to_be_bytes())?;
+        f.write_all(&self.num_chunks.to_be_bytes())?;
+        f.write_all(&self.unk20.to_be_bytes())?;
+        self.write_chunk_infos(f)?;
+        Ok(EDGE_TABLE_HEADER_SIZE + self.chunk_infos.len() * EDGE_CHUNK_INFO_SIZE)
+    }
+}
+
+/// DCX layouts, named after their method and header values.
+///
+/// Names are the same as in SoulsFormats, with DFLT values being
+/// `header.unk04`, `header.unk10` and `params.unk0C` (the level).
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DcxVariant {
+    DCP_DFLT,              // DeS, no DCX header and the archive chunk last.
+    DCP_EDGE,              // DeS, like DCP_DFLT with an EDGE table after the archive.
+    DCX_EDGE,              // DeS and DS1 on PS3.
+    DCX_DFLT_10000_24_9,   // DS1
+    DCX_DFLT_10000_44_9,   // DS2, BB
+    DCX_DFLT_11000_44_8,   // Sekiro
+    DCX_DFLT_11000_44_9,   // DS3
+    DCX_DFLT_11000_44_9_15,
+    DCX_KRAK,
+    DCX_ZSTD,
+    Unknown,
+}
+
+impl DcxVariant {
+    /// Return DFLT `header.unk04`, `header.unk10` and level values.
+    pub fn dflt_values(&self) -> Option<(u32, u32, u8)> {
+        match self {
+            DcxVariant::DCX_DFLT_10000_24_9 => Some((0x10000, 0x24, 9)),
+            DcxVariant::DCX_DFLT_10000_44_9 => Some((0x10000, 0x44, 9)),
+            DcxVariant::DCX_DFLT_11000_44_8 => Some((0x11000, 0x44, 8)),
+            DcxVariant::DCX_DFLT_11000_44_9 => Some((0x11000, 0x44, 9)),
+            DcxVariant::DCX_DFLT_11000_44_9_15 => Some((0x11000, 0x44, 9)),
+            _ => None,
+        }
+    }
+}
+
+/// Value of `params.unk14` for DCX_DFLT_11000_44_9_15.
+pub const DFLT_15_UNK14: u32 = 0x0F00_0000;
+
+fn get_variant(header: &DcxHeader, params: &DcxParams) -> DcxVariant {
+    match params.method.as_slice() {
+        b"EDGE" => DcxVariant::DCX_EDGE,
+        b"KRAK" => DcxVariant::DCX_KRAK,
+        b"ZSTD" => DcxVariant::DCX_ZSTD,
+        b"DFLT" => {
+            let values = (header.unk04, header.unk10, params.unk0C);
+            let is_15 = params.unk14 == DFLT_15_UNK14;
+            match values {
+                (0x10000, 0x24, 9) => DcxVariant::DCX_DFLT_10000_24_9,
+                (0x10000, 0x44, 9) => DcxVariant::DCX_DFLT_10000_44_9,
+                (0x11000, 0x44, 8) => DcxVariant::DCX_DFLT_11000_44_8,
+                (0x11000, 0x44, 9) if is_15 => DcxVariant::DCX_DFLT_11000_44_9_15,
+                (0x11000, 0x44, 9) => DcxVariant::DCX_DFLT_11000_44_9,
+                _ => DcxVariant::Unknown,
+            }
+        }
+        _ => DcxVariant::Unknown,
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dcx {
-    pub header: DcxHeader,
+    pub variant: DcxVariant,
+    pub header: Option<DcxHeader>,  // None for DCP_DFLT.
     pub sizes: DcxSizes,
     pub params: DcxParams,
     pub archive: DcxArchive,
+    pub edge_table: Option<DcxEdgeTable>,
 }
 
+/// Parse DCX headers.
+///
+/// On success, returns the compressed data along with the Dcx struct.
 pub fn parse(i: &[u8]) -> IResult<&[u8], Dcx> {
+    if i.starts_with(PARAMS_CHUNK_MAGIC) {
+        return parse_dcp(i)
+    }
     let full_file = i;
-    let (_, header) = parse_header(&full_file)?;
+    let (_, header) = parse_header(full_file)?;
     let pos_dcs = header.ofs_dcs as usize;
     let (_, sizes) = parse_sizes(&full_file[pos_dcs..])?;
     let pos_dcp = header.ofs_dcp as usize;
     let (_, params) = parse_params(&full_file[pos_dcp..])?;
     let pos_dca = pos_dcp + params.ofs_dca as usize;
     let (i, archive) = parse_archive(&full_file[pos_dca..])?;
-    Ok((i, Dcx { header, sizes, params, archive }))
+    let edge_table = if params.method == b"EDGE" {
+        let (_, edge_table) = parse_edge_table(i)?;
+        Some(edge_table)
+    } else {
+        None
+    };
+    let pos_data = pos_dca + archive.ofs_data as usize;
+    let variant = get_variant(&header, &params);
+    Ok((
+        &full_file[pos_data..],
+        Dcx { variant, header: Some(header), sizes, params, archive, edge_table }
+    ))
+}
+
+/// Parse a DCP file: params, sizes, compressed data then archive.
+///
+/// DCP_EDGE has 4 null bytes after the sizes and its EDGE table after
+/// the archive chunk.
+fn parse_dcp(i: &[u8]) -> IResult<&[u8], Dcx> {
+    let (i, params) = parse_params(i)?;
+    let (i, sizes) = parse_sizes(i)?;
+    let is_edge = params.method == b"EDGE";
+    let (i, _) = if is_edge { tag(&[0u8; 4][..])(i)? } else { (i, &i[..0]) };
+    let (_, data) = take(sizes.compressed_size as usize)(i)?;
+    let (i, archive) = parse_archive(&i[data.len()..])?;
+    let edge_table = if is_edge {
+        let (_, edge_table) = parse_dcp_edge_table(i)?;
+        Some(edge_table)
+    } else {
+        None
+    };
+    let variant = match params.method.as_slice() {
+        b"DFLT" => DcxVariant::DCP_DFLT,
+        b"EDGE" => DcxVariant::DCP_EDGE,
+        _ => DcxVariant::Unknown,
+    };
+    Ok((data, Dcx { variant, header: None, sizes, params, archive, edge_table }))
 }
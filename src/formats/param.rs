@@ -1,23 +1,38 @@
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug};
+use std::io;
+use std::ops::{Deref, DerefMut};
 
+use nom::Err::Failure;
 use nom::IResult;
 use nom::bytes::complete::take;
+use nom::error::ErrorKind;
 use nom::multi::count;
 use nom::number::complete::*;
 use nom::sequence::tuple;
 
-use crate::formats::common::{sjis_to_string, take_cstring, take_cstring_from, VarSizeInt};
+use crate::formats::common::{
+    Pack, VarSizeInt, sjis_to_string, take_cstring, take_cstring_from, take_utf16_cstring,
+    utf16_to_string,
+};
 use crate::formats::paramdef;
-use crate::utils::bin::{has_flag, mask};
+use crate::utils::bin::{has_flag, mask, u16_to_bytes, u32_to_bytes, u64_to_bytes};
 use crate::utils::str as utils_str;
 
-const FLAGS2D_UNK1: u8           = 0b00000001;
-const FLAGS2D_32B_OFS_DATA: u8   = 0b00000010;
-const FLAGS2D_64B_OFS_DATA: u8   = 0b00000100;
-const FLAGS2D_OFS_STRING: u8     = 0b10000000;
-const _FLAGS2E_UNICODE_NAMES: u8 = 0b00000001;
+pub const FLAGS2D_UNK1: u8          = 0b00000001;
+pub const FLAGS2D_32B_OFS_DATA: u8  = 0b00000010;
+pub const FLAGS2D_64B_OFS_DATA: u8  = 0b00000100;
+pub const FLAGS2D_OFS_STRING: u8    = 0b10000000;
+pub const FLAGS2E_UNICODE_NAMES: u8 = 0b00000001;
 
-#[derive(Debug)]
+pub const HEADER_SIZE: usize = 0x30;
+pub const LONG_HEADER_SIZE: usize = 0x40;  // With 32 or 64-bit data offset.
+pub const ROW_INFO_SIZE: usize = 0xC;
+pub const LONG_ROW_INFO_SIZE: usize = 0x18;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamHeader {
     pub ofs_strings: u32,  // Unreliable.
     pub ofs_data: u16,
@@ -34,10 +49,38 @@ pub struct ParamHeader {
 }
 
 impl ParamHeader {
+    /// Create a header for a little-endian PARAM with 32-bit offsets, as
+    /// in DS1. Counts and offsets are set when packing.
+    pub fn new(param_type: &str) -> ParamHeader {
+        ParamHeader {
+            ofs_strings: 0,
+            ofs_data: 0,
+            unk06: 0,
+            paramdef_data_version: 1,
+            num_rows: 0,
+            param_type: param_type.to_string(),
+            ofs_name: None,
+            endianness: 0,
+            flags2D: 0,
+            flags2E: 0,
+            paramdef_format_version: 0,
+            ofs_data_long: None,
+        }
+    }
+
     pub fn use_be(&self) -> bool { use_be(self.endianness) }
     pub fn has_ofs_string_name(&self) -> bool { has_ofs_string_name(self.flags2D) }
     pub fn has_u32_ofs_data(&self) -> bool { has_u32_ofs_data(self.flags2D) }
     pub fn has_u64_ofs_data(&self) -> bool { has_u64_ofs_data(self.flags2D) }
+    pub fn has_unicode_names(&self) -> bool { has_flag(self.flags2E, FLAGS2E_UNICODE_NAMES) }
+
+    pub fn header_size(&self) -> usize {
+        if self.ofs_data_long.is_some() { LONG_HEADER_SIZE } else { HEADER_SIZE }
+    }
+
+    pub fn row_info_size(&self) -> usize {
+        if self.has_u64_ofs_data() { LONG_ROW_INFO_SIZE } else { ROW_INFO_SIZE }
+    }
 }
 
 impl fmt::Display for ParamHeader {
@@ -83,12 +126,13 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], ParamHeader> {
     let i = &i[0x2..];  // Skip endianness and flags2D.
     let (i, (flags2E, paramdef_format_version)) = tuple((le_u8, le_u8))(i)?;
 
+    // Data offset is followed by unused bytes, for a total of 0x10 bytes.
     let (i, ofs_data_long) = if use_u32_ofs_data {
         let (_, o) = p_u32(i)?;
-        (&i[0x20..], Some(VarSizeInt { vu32: o }))
+        (&i[0x10..], Some(VarSizeInt::from_u32(o)))
     } else if use_u64_ofs_data {
         let (_, o) = p_u64(i)?;
-        (&i[0x20..], Some(VarSizeInt { vu64: o }))
+        (&i[0x10..], Some(VarSizeInt { vu64: o }))
     } else {
         (i, None)
     };
@@ -112,13 +156,81 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], ParamHeader> {
     ))
 }
 
-#[derive(Debug)]
+impl Pack for ParamHeader {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let use_be = self.use_be();
+        f.write_all(&u32_to_bytes(self.ofs_strings, use_be))?;
+        f.write_all(&u16_to_bytes(self.ofs_data, use_be))?;
+        f.write_all(&u16_to_bytes(self.unk06, use_be))?;
+        f.write_all(&u16_to_bytes(self.paramdef_data_version, use_be))?;
+        f.write_all(&u16_to_bytes(self.num_rows, use_be))?;
+        if self.has_ofs_string_name() {
+            f.write_all(&[0u8; 4])?;
+            f.write_all(&u64_to_bytes(self.ofs_name.unwrap_or(0), use_be))?;
+            f.write_all(&[0u8; 0x14])?;
+        } else {
+            // Older params pad the type name with spaces.
+            let padding = if self.flags2D & FLAGS2D_UNK1 != 0 { b' ' } else { 0 };
+            let mut param_type = self.param_type.as_bytes().to_vec();
+            param_type.truncate(0x1F);
+            param_type.push(0);
+            param_type.resize(0x20, padding);
+            f.write_all(&param_type)?;
+        }
+        f.write_all(&[self.endianness, self.flags2D, self.flags2E, self.paramdef_format_version])?;
+        if let Some(ofs_data_long) = &self.ofs_data_long {
+            if self.has_u32_ofs_data() {
+                f.write_all(&u32_to_bytes(ofs_data_long.u64_if(false) as u32, use_be))?;
+                f.write_all(&[0u8; 0xC])?;
+            } else {
+                f.write_all(&u64_to_bytes(ofs_data_long.u64_if(true), use_be))?;
+                f.write_all(&[0u8; 0x8])?;
+            }
+        }
+        Ok(self.header_size())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamRow {
     pub id: u32,
     pub ofs_data: VarSizeInt,
     pub ofs_name: VarSizeInt,
     pub name: Option<String>,
     pub data: Vec<ParamRowValue>,
+    pub raw_data: Vec<u8>,  // Data as stored, even without a PARAMDEF.
+}
+
+impl ParamRow {
+    /// Create an unnamed row with these values. Offsets are set when
+    /// packing.
+    pub fn new(id: u32, data: Vec<ParamRowValue>) -> ParamRow {
+        ParamRow {
+            id,
+            ofs_data: VarSizeInt::from_u32(0),
+            ofs_name: VarSizeInt::from_u32(0),
+            name: None,
+            data,
+            raw_data: vec!(),
+        }
+    }
+
+    /// Write the row info, which depends on the header flags.
+    pub fn write_info(&self, header: &ParamHeader, f: &mut dyn io::Write) -> io::Result<usize> {
+        let use_be = header.use_be();
+        let use_u64 = header.has_u64_ofs_data();
+        f.write_all(&u32_to_bytes(self.id, use_be))?;
+        if use_u64 {
+            f.write_all(&[0u8; 4])?;
+            f.write_all(&u64_to_bytes(self.ofs_data.u64_if(true), use_be))?;
+            f.write_all(&u64_to_bytes(self.ofs_name.u64_if(true), use_be))?;
+        } else {
+            f.write_all(&u32_to_bytes(self.ofs_data.u64_if(false) as u32, use_be))?;
+            f.write_all(&u32_to_bytes(self.ofs_name.u64_if(false) as u32, use_be))?;
+        }
+        Ok(header.row_info_size())
+    }
 }
 
 impl fmt::Display for ParamRow {
@@ -127,9 +239,14 @@ impl fmt::Display for ParamRow {
     }
 }
 
-#[derive(strum_macros::IntoStaticStr)]
+#[derive(Clone, PartialEq, strum_macros::IntoStaticStr)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParamRowValue {
-    S8(i8), U8(u8), S16(i16), U16(u16), S32(i32), U32(u32), F32(f32), UNK(Vec<u8>)
+    S8(i8), U8(u8), S16(i16), U16(u16), S32(i32), U32(u32), S64(i64), U64(u64),
+    F32(f32), F64(f64), ANGLE32(f32),
+    FIXSTR(String), FIXSTRW(String),
+    ARRAY(Vec<ParamRowValue>),
+    UNK(Vec<u8>)
 }
 
 impl fmt::Debug for ParamRowValue {
@@ -139,6 +256,142 @@ impl fmt::Debug for ParamRowValue {
     }
 }
 
+impl ParamRowValue {
+    /// Parse a value for a field of this type from a string.
+    ///
+    /// Numbers use their usual representation, strings are used as is
+    /// and arrays are lists of numbers, e.g. "[1, 2]". Other types are
+    /// read as hexadecimal bytes, e.g. "00FF", and must be `num_bytes`
+    /// long. Bitfields are parsed as their integer type.
+    pub fn from_str_with_type(
+        s: &str,
+        type_str: &str,
+        num_bytes: usize,
+        is_bitfield: bool,
+    ) -> Result<ParamRowValue, String> {
+        let message = format!("Invalid {} value: \"{}\"", type_str, s);
+        match type_str {
+            "fixstr" => return Ok(ParamRowValue::FIXSTR(s.to_string())),
+            "fixstrW" => return Ok(ParamRowValue::FIXSTRW(s.to_string())),
+            "dummy8" if is_bitfield => return Ok(ParamRowValue::U8(s.trim().parse()
+                .map_err(|_| message)?)),
+            "dummy8" => {}
+            _ => match get_array_len(type_str, num_bytes) {
+                Some(1) => return parse_single_value_str(s, type_str).ok_or(message),
+                Some(n) => {
+                    let values = s.trim().trim_start_matches('[').trim_end_matches(']')
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|v| !v.is_empty())
+                        .map(|v| parse_single_value_str(v, type_str))
+                        .collect::<Option<Vec<ParamRowValue>>>()
+                        .filter(|values| values.len() == n);
+                    return values.map(ParamRowValue::ARRAY).ok_or(message)
+                }
+                None => {}
+            }
+        }
+        let bytes = utils_str::hex_to_bytes(s.trim()).filter(|b| b.len() == num_bytes);
+        bytes.map(ParamRowValue::UNK).ok_or(message)
+    }
+
+    /// Return the default value of a PARAMDEF field.
+    ///
+    /// Strings are empty, unknown types zeroed, and defaults that do not
+    /// fit in their type are replaced by 0.
+    pub fn from_field_default(field: &paramdef::ParamdefField) -> ParamRowValue {
+        let type_str = field.display_type.as_str();
+        let num_bytes = field.byte_count as usize;
+        let is_bitfield = field.bit_size() > 0;
+        let default = match type_str {
+            "fixstr" | "fixstrW" => String::new(),
+            "f32" | "f64" | "angle32" => field.default_value.to_string(),
+            _ => (field.default_value as i64).to_string(),
+        };
+        let repeat = |s: &str| match get_array_len(type_str, num_bytes) {
+            Some(n) if !is_bitfield => vec![s; n].join(" "),
+            _ => s.to_string(),
+        };
+        let parse = |s: &str| {
+            ParamRowValue::from_str_with_type(s, type_str, num_bytes, is_bitfield)
+        };
+        parse(&repeat(&default))
+            .or_else(|_| parse(&repeat("0")))
+            .unwrap_or_else(|_| ParamRowValue::UNK(vec![0; num_bytes]))
+    }
+
+    /// Return the numeric value, or None for strings, arrays and unknown types.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ParamRowValue::S8(i) => Some(*i as f64),
+            ParamRowValue::U8(i) => Some(*i as f64),
+            ParamRowValue::S16(i) => Some(*i as f64),
+            ParamRowValue::U16(i) => Some(*i as f64),
+            ParamRowValue::S32(i) => Some(*i as f64),
+            ParamRowValue::U32(i) => Some(*i as f64),
+            ParamRowValue::S64(i) => Some(*i as f64),
+            ParamRowValue::U64(i) => Some(*i as f64),
+            ParamRowValue::F32(i) => Some(*i as f64),
+            ParamRowValue::F64(i) => Some(*i),
+            ParamRowValue::ANGLE32(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    /// Return a string that can be parsed back with `from_str_with_type`.
+    pub fn to_value_string(&self) -> String {
+        match self {
+            ParamRowValue::UNK(bytes) => utils_str::bytes_to_hex(bytes),
+            v => format!("{}", v),
+        }
+    }
+
+    /// Return the integer value, or None for other types.
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            ParamRowValue::S8(i) => Some(*i as i128),
+            ParamRowValue::U8(i) => Some(*i as i128),
+            ParamRowValue::S16(i) => Some(*i as i128),
+            ParamRowValue::U16(i) => Some(*i as i128),
+            ParamRowValue::S32(i) => Some(*i as i128),
+            ParamRowValue::U32(i) => Some(*i as i128),
+            ParamRowValue::S64(i) => Some(*i as i128),
+            ParamRowValue::U64(i) => Some(*i as i128),
+            _ => None,
+        }
+    }
+
+    /// Return a value of the same type holding this integer, if it fits.
+    pub fn with_integer(&self, v: i128) -> Option<ParamRowValue> {
+        Some(match self {
+            ParamRowValue::S8(_) => ParamRowValue::S8(v.try_into().ok()?),
+            ParamRowValue::U8(_) => ParamRowValue::U8(v.try_into().ok()?),
+            ParamRowValue::S16(_) => ParamRowValue::S16(v.try_into().ok()?),
+            ParamRowValue::U16(_) => ParamRowValue::U16(v.try_into().ok()?),
+            ParamRowValue::S32(_) => ParamRowValue::S32(v.try_into().ok()?),
+            ParamRowValue::U32(_) => ParamRowValue::U32(v.try_into().ok()?),
+            ParamRowValue::S64(_) => ParamRowValue::S64(v.try_into().ok()?),
+            ParamRowValue::U64(_) => ParamRowValue::U64(v.try_into().ok()?),
+            ParamRowValue::F32(_) | ParamRowValue::F64(_) | ParamRowValue::ANGLE32(_) => {
+                return self.with_float(v as f64)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Return a value of the same type holding this number, if it fits.
+    ///
+    /// Integer types only accept integral numbers.
+    pub fn with_float(&self, v: f64) -> Option<ParamRowValue> {
+        match self {
+            ParamRowValue::F32(_) => Some(ParamRowValue::F32(v as f32)),
+            ParamRowValue::F64(_) => Some(ParamRowValue::F64(v)),
+            ParamRowValue::ANGLE32(_) => Some(ParamRowValue::ANGLE32(v as f32)),
+            _ if v.fract() == 0.0 && v.is_finite() => self.with_integer(v as i128),
+            _ => None,
+        }
+    }
+}
+
 // Could be probably be done better with a macro...
 impl fmt::Display for ParamRowValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -149,12 +402,68 @@ impl fmt::Display for ParamRowValue {
             ParamRowValue::U16(i) => fmt::Display::fmt(&i, f),
             ParamRowValue::S32(i) => fmt::Display::fmt(&i, f),
             ParamRowValue::U32(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::S64(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::U64(i) => fmt::Display::fmt(&i, f),
             ParamRowValue::F32(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::F64(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::ANGLE32(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::FIXSTR(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::FIXSTRW(i) => fmt::Display::fmt(&i, f),
+            ParamRowValue::ARRAY(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
             ParamRowValue::UNK(i) => fmt::Debug::fmt(&i, f),
         }
     }
 }
 
+/// Return the number of values for a numeric field, None for other types.
+fn get_array_len(type_str: &str, num_bytes: usize) -> Option<usize> {
+    match type_str {
+        "dummy8" | "fixstr" | "fixstrW" => None,
+        _ => paramdef::get_type_size(type_str)
+            .map(|size| size as usize)
+            .filter(|size| num_bytes >= *size && num_bytes.is_multiple_of(*size))
+            .map(|size| num_bytes / size),
+    }
+}
+
+/// Parse a single numeric value from a string.
+fn parse_single_value_str(s: &str, type_str: &str) -> Option<ParamRowValue> {
+    let s = s.trim();
+    Some(match type_str {
+        "s8" => ParamRowValue::S8(s.parse().ok()?),
+        "u8" => ParamRowValue::U8(s.parse().ok()?),
+        "s16" => ParamRowValue::S16(s.parse().ok()?),
+        "u16" => ParamRowValue::U16(s.parse().ok()?),
+        "s32" => ParamRowValue::S32(s.parse().ok()?),
+        "u32" | "b32" => ParamRowValue::U32(s.parse().ok()?),
+        "s64" => ParamRowValue::S64(s.parse().ok()?),
+        "u64" => ParamRowValue::U64(s.parse().ok()?),
+        "f32" => ParamRowValue::F32(s.parse().ok()?),
+        "f64" => ParamRowValue::F64(s.parse().ok()?),
+        "angle32" => ParamRowValue::ANGLE32(s.parse().ok()?),
+        _ => return None,
+    })
+}
+
+/// Decode a zero-terminated Shift JIS string stored in a fixed size.
+pub fn decode_fixstr(i: &[u8]) -> Option<String> {
+    let end = i.iter().position(|b| *b == 0).unwrap_or(i.len());
+    sjis_to_string(&i[..end])
+}
+
+/// Decode a zero-terminated UTF-16 string stored in a fixed size.
+pub fn decode_fixstr_w(i: &[u8], use_be: bool) -> Option<String> {
+    let chars: Vec<u16> = i.chunks_exact(2)
+        .map(|c| [c[0], c[1]])
+        .map(|c| if use_be { u16::from_be_bytes(c) } else { u16::from_le_bytes(c) })
+        .take_while(|c| *c != 0)
+        .collect();
+    utf16_to_string(&chars)
+}
+
 fn parse_row<'a>(i: &'a[u8], header: &ParamHeader) -> IResult<&'a[u8], ParamRow> {
     let p_u32 = if header.use_be() { be_u32 } else { le_u32 };
     let p_u64 = if header.use_be() { be_u64 } else { le_u64 };
@@ -164,10 +473,10 @@ fn parse_row<'a>(i: &'a[u8], header: &ParamHeader) -> IResult<&'a[u8], ParamRow>
         (i, (id, VarSizeInt { vu64: ofs_data }, VarSizeInt { vu64: ofs_name }))
     } else {
         let (i, (id, ofs_data, ofs_name)) = tuple((p_u32, p_u32, p_u32))(i)?;
-        (i, (id, VarSizeInt { vu32: ofs_data }, VarSizeInt { vu32: ofs_name }))
+        (i, (id, VarSizeInt::from_u32(ofs_data), VarSizeInt::from_u32(ofs_name)))
     };
 
-    Ok((i, ParamRow { id, ofs_data, ofs_name, name: None, data: vec!() }))
+    Ok((i, ParamRow { id, ofs_data, ofs_name, name: None, data: vec!(), raw_data: vec!() }))
 }
 
 /// Parse row data using field definitions from PARAMDEF.
@@ -177,33 +486,20 @@ fn parse_row_data<'a>(
     paramdef: &paramdef::Paramdef
 ) -> IResult<&'a[u8], Vec<ParamRowValue>> {
     let use_be = header.use_be();
+    let layout = paramdef.get_layout().map_err(|_| Failure((i, ErrorKind::Verify)))?;
     let mut data = vec!();
-    let mut bitfield = 0u16;     // Current bitfield being parsed. u16 is largest handled type.
-    let mut remaining_bits = 0;  // Remaining bits in bitfield.
-    let mut data_slice = i;
-    for field in &paramdef.fields {
+    for (field, field_layout) in paramdef.fields.iter().zip(layout.iter()) {
         let type_str = &field.display_type;
         let num_bytes = field.byte_count as usize;
-        let bit_size = field.bit_size();
-        let value = if bit_size == 0 {
-            let (rest, value) = parse_row_value(data_slice, type_str, num_bytes, use_be)?;
-            data_slice = rest;
-            remaining_bits = 0;
-            value
-        } else {
-            // Bitfield parsing. If it's the first bitfield in a series, get the containing bytes
-            // in the bitfield var.
-            if remaining_bits == 0 {
-                let (rest, bf) = parse_row_bitfield(data_slice, type_str, num_bytes, use_be)?;
-                data_slice = rest;
-                bitfield = bf;
-                remaining_bits = bit_size * 8;
+        let field_data = i.get(field_layout.offset..).unwrap_or(&[]);
+        let value = match field.bit_size() {
+            0 => parse_row_value(field_data, type_str, num_bytes, use_be)?.1,
+            bit_size => {
+                let (_, bitfield) = parse_row_bitfield(field_data, type_str, use_be)?;
+                let value = (bitfield >> field_layout.bit_offset) & mask(bit_size) as u32;
+                get_bitfield_value(value, type_str)
+                    .ok_or(Failure((field_data, ErrorKind::Verify)))?
             }
-            let value = parse_row_bitfield_value(bitfield, type_str, bit_size);
-            // Shift bitfield so next values can be parsed directly with a bitmask.
-            bitfield >>= bit_size;
-            remaining_bits -= bit_size;
-            value
         };
         data.push(value);
     }
@@ -211,11 +507,36 @@ fn parse_row_data<'a>(
 }
 
 /// Parse a single row value, using its type string.
+///
+/// Numeric fields larger than their type are parsed as arrays; unknown
+/// types, dummy8 and strings that can't be decoded are kept as bytes.
 fn parse_row_value<'a>(
     i: &'a[u8],
     type_str: &str,
     num_bytes: usize,
     use_be: bool
+) -> IResult<&'a[u8], ParamRowValue> {
+    let (rest, value_data) = take(num_bytes)(i)?;
+    let value = match type_str {
+        "fixstr" => decode_fixstr(value_data).map(ParamRowValue::FIXSTR),
+        "fixstrW" => decode_fixstr_w(value_data, use_be).map(ParamRowValue::FIXSTRW),
+        _ => match get_array_len(type_str, num_bytes) {
+            Some(1) => Some(parse_single_value(value_data, type_str, use_be)?.1),
+            Some(n) => {
+                let parser = |i| parse_single_value(i, type_str, use_be);
+                Some(ParamRowValue::ARRAY(count(parser, n)(value_data)?.1))
+            }
+            None => None,
+        }
+    };
+    Ok((rest, value.unwrap_or_else(|| ParamRowValue::UNK(value_data.to_vec()))))
+}
+
+/// Parse a single numeric value.
+fn parse_single_value<'a>(
+    i: &'a[u8],
+    type_str: &str,
+    use_be: bool
 ) -> IResult<&'a[u8], ParamRowValue> {
     Ok(match type_str {
         "s8" => le_i8(i)
@@ -228,51 +549,62 @@ fn parse_row_value<'a>(
             .map(|(i, v)| (i, ParamRowValue::U16(v)))?,
         "s32" => (if use_be { be_i32 } else { le_i32 }) (i)
             .map(|(i, v)| (i, ParamRowValue::S32(v)))?,
-        "u32" => (if use_be { be_u32 } else { le_u32 }) (i)
+        "u32" | "b32" => (if use_be { be_u32 } else { le_u32 }) (i)
             .map(|(i, v)| (i, ParamRowValue::U32(v)))?,
+        "s64" => (if use_be { be_i64 } else { le_i64 }) (i)
+            .map(|(i, v)| (i, ParamRowValue::S64(v)))?,
+        "u64" => (if use_be { be_u64 } else { le_u64 }) (i)
+            .map(|(i, v)| (i, ParamRowValue::U64(v)))?,
         "f32" => (if use_be { be_f32 } else { le_f32 }) (i)
             .map(|(i, v)| (i, ParamRowValue::F32(v)))?,
-        _ => take(num_bytes)(i)
-            .map(|(i, v)| (i, ParamRowValue::UNK(v.to_vec())))?,
+        "f64" => (if use_be { be_f64 } else { le_f64 }) (i)
+            .map(|(i, v)| (i, ParamRowValue::F64(v)))?,
+        "angle32" => (if use_be { be_f32 } else { le_f32 }) (i)
+            .map(|(i, v)| (i, ParamRowValue::ANGLE32(v)))?,
+        _ => return Err(Failure((i, ErrorKind::Verify))),
     })
 }
 
-/// Parse a bitfield unsigned int of the max handled size (u16).
+/// Parse the int containing a bitfield, up to 32 bits.
 ///
-/// Parsing is done using the type string. Whatever the size of the int
-/// is, place it in the max handled size to avoid maintaining one
-/// bitfield for each type.
-fn parse_row_bitfield<'a>(
-    bf: &'a[u8],
-    type_str: &str,
-    num_bytes: usize,
-    use_be: bool
-) -> IResult<&'a[u8], u16> {
-    let (rest, bf) = take(num_bytes)(bf)?;
-    let bitfield = match type_str {
-        "u8" => le_u8(bf).map(|(_, v)| v as u16)?,
-        "dummy8" => le_u8(bf).map(|(_, v)| v as u16)?,
-        "u16" => (if use_be { be_u16 } else { le_u16 })(bf).map(|(_, v)| v)?,
-        e => panic!("Unhandled PARAMDEF type {}", e),
-    };
-    Ok((rest, bitfield))
+/// Whatever the size of the int is, place it in the max handled size
+/// to avoid maintaining one bitfield for each type.
+fn parse_row_bitfield<'a>(bf: &'a[u8], type_str: &str, use_be: bool) -> IResult<&'a[u8], u32> {
+    Ok(match type_str {
+        "s8" | "u8" | "dummy8" => le_u8(bf).map(|(i, v)| (i, v as u32))?,
+        "s16" | "u16" => (if use_be { be_u16 } else { le_u16 })(bf).map(|(i, v)| (i, v as u32))?,
+        "s32" | "u32" => (if use_be { be_u32 } else { le_u32 })(bf)?,
+        _ => return Err(Failure((bf, ErrorKind::Verify))),
+    })
 }
 
-/// Parse a single row value (max u16) from a bitfield.
-fn parse_row_bitfield_value(bitfield: u16, type_str: &str, bit_size: usize) -> ParamRowValue {
-    let value = bitfield & mask(bit_size) as u16;
-    match type_str {
-        "u8" => ParamRowValue::U8(value as u8),
-        "dummy8" => ParamRowValue::U8(value as u8),
-        "u16" => ParamRowValue::U16(value),
-        e => panic!("Unhandled PARAMDEF type {}", e),
-    }
+/// Return a bitfield value, already shifted and masked, as its type.
+fn get_bitfield_value(value: u32, type_str: &str) -> Option<ParamRowValue> {
+    Some(match type_str {
+        "s8" => ParamRowValue::S8(value as i8),
+        "u8" | "dummy8" => ParamRowValue::U8(value as u8),
+        "s16" => ParamRowValue::S16(value as i16),
+        "u16" => ParamRowValue::U16(value as u16),
+        "s32" => ParamRowValue::S32(value as i32),
+        "u32" => ParamRowValue::U32(value),
+        _ => return None,
+    })
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Param {
     pub header: ParamHeader,
     pub rows: Vec<ParamRow>,
+    // Column names of the PARAMDEF fields used to parse row values.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub field_names: Vec<String>,
+    // Bit size of each field, 0 if it is not a bitfield.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub field_bits: Vec<usize>,
+    // Position of the first row of each ID, see `reindex`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub row_index: HashMap<u32, usize>,
 }
 
 impl fmt::Display for Param {
@@ -281,42 +613,396 @@ impl fmt::Display for Param {
     }
 }
 
+impl Param {
+    /// Create a PARAM with these rows, without field names.
+    pub fn new(mut header: ParamHeader, rows: Vec<ParamRow>) -> Param {
+        header.num_rows = rows.len() as u16;
+        let mut param = Param {
+            header,
+            rows,
+            field_names: vec!(),
+            field_bits: vec!(),
+            row_index: HashMap::new(),
+        };
+        param.reindex();
+        param
+    }
+
+    /// Set the names of row values, see `field_index`. No field is a bitfield.
+    pub fn with_field_names(mut self, field_names: &[&str]) -> Param {
+        self.field_names = field_names.iter().map(|name| name.to_string()).collect();
+        self.field_bits = vec![0; field_names.len()];
+        self
+    }
+
+    /// Set field names and bit sizes from the PARAMDEF used for row values.
+    pub fn set_fields(&mut self, paramdef: &paramdef::Paramdef) {
+        self.field_names = paramdef.fields.iter().map(|field| field.column_name()).collect();
+        self.field_bits = paramdef.fields.iter().map(|field| field.bit_size()).collect();
+    }
+
+    /// Return the largest row size that does not overlap the next row,
+    /// if there are at least two rows with data.
+    pub fn max_row_size(&self) -> Option<usize> {
+        get_max_row_size(&self.header, &self.rows)
+    }
+
+    /// Rebuild the row index, after adding, removing or moving rows.
+    ///
+    /// A stale index is not an error, but lookups fall back to a scan.
+    pub fn reindex(&mut self) {
+        self.row_index.clear();
+        for (index, row) in self.rows.iter().enumerate() {
+            self.row_index.entry(row.id).or_insert(index);
+        }
+    }
+
+    fn row_position(&self, id: u32) -> Option<usize> {
+        match self.row_index.get(&id) {
+            Some(&index) if self.rows.get(index).map(|r| r.id) == Some(id) => Some(index),
+            _ => self.rows.iter().position(|r| r.id == id),
+        }
+    }
+
+    /// Return the first row with this ID.
+    pub fn row(&self, id: u32) -> Result<RowRef<'_>, RowAccessError> {
+        let index = self.row_position(id).ok_or(RowAccessError::NoRow(id))?;
+        Ok(RowRef { row: &self.rows[index], field_names: &self.field_names })
+    }
+
+    /// Return the first row with this ID, for edition.
+    pub fn row_mut(&mut self, id: u32) -> Result<RowMut<'_>, RowAccessError> {
+        let index = self.row_position(id).ok_or(RowAccessError::NoRow(id))?;
+        let (field_names, field_bits) = (&self.field_names, &self.field_bits);
+        Ok(RowMut { row: &mut self.rows[index], field_names, field_bits })
+    }
+
+    /// Return the position of a field in row values.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.field_names.iter().position(|n| n == name)
+    }
+
+    /// Iterate over row IDs, in file order.
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.rows.iter().map(|row| row.id)
+    }
+
+    pub fn iter_rows(&self) -> impl Iterator<Item = RowRef<'_>> {
+        let field_names = &self.field_names;
+        self.rows.iter().map(move |row| RowRef { row, field_names })
+    }
+
+    pub fn iter_rows_mut(&mut self) -> impl Iterator<Item = RowMut<'_>> {
+        let (field_names, field_bits) = (&self.field_names, &self.field_bits);
+        self.rows.iter_mut().map(move |row| RowMut { row, field_names, field_bits })
+    }
+}
+
+/// Error when accessing row values by ID and field name.
+#[derive(Debug, PartialEq)]
+pub enum RowAccessError {
+    NoRow(u32),
+    NoField(String),
+    NoValues(u32),  // The row has been loaded without PARAMDEF.
+    Conversion { field: String, value: String, to: &'static str },
+}
+
+impl fmt::Display for RowAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowAccessError::NoRow(id) => write!(f, "No row with ID {}", id),
+            RowAccessError::NoField(name) => write!(f, "No field named {}", name),
+            RowAccessError::NoValues(id) => write!(f, "Row {} has no parsed values", id),
+            RowAccessError::Conversion { field, value, to } => {
+                write!(f, "Can't convert {} value {} to {}", field, value, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RowAccessError {}
+
+/// Row with access to its values by field name.
+pub struct RowRef<'a> {
+    pub row: &'a ParamRow,
+    field_names: &'a [String],
+}
+
+/// Row with access to its values by field name, for edition.
+pub struct RowMut<'a> {
+    pub row: &'a mut ParamRow,
+    field_names: &'a [String],
+    field_bits: &'a [usize],
+}
+
+impl Deref for RowRef<'_> {
+    type Target = ParamRow;
+    fn deref(&self) -> &ParamRow { self.row }
+}
+
+impl Deref for RowMut<'_> {
+    type Target = ParamRow;
+    fn deref(&self) -> &ParamRow { self.row }
+}
+
+impl DerefMut for RowMut<'_> {
+    fn deref_mut(&mut self) -> &mut ParamRow { self.row }
+}
+
+fn get_row_value<'a>(
+    row: &'a ParamRow,
+    field_names: &[String],
+    name: &str,
+) -> Result<&'a ParamRowValue, RowAccessError> {
+    let index = field_names.iter().position(|n| n == name)
+        .ok_or_else(|| RowAccessError::NoField(name.to_string()))?;
+    row.data.get(index).ok_or(RowAccessError::NoValues(row.id))
+}
+
+fn conversion_error(field: &str, value: &ParamRowValue, to: &'static str) -> RowAccessError {
+    RowAccessError::Conversion { field: field.to_string(), value: value.to_string(), to }
+}
+
+/// Return true if the value can be stored in a bitfield of this size,
+/// 0 meaning the field is not a bitfield.
+fn fits_in_bits(value: &ParamRowValue, bit_size: usize) -> bool {
+    match value.as_integer() {
+        Some(v) if bit_size > 0 && bit_size < 64 => v >= 0 && v < 1 << bit_size,
+        _ => true,
+    }
+}
+
+/// Typed getters, converting values when they fit in the requested type.
+macro_rules! impl_row_getters {
+    ($row_type:ident, $( $getter:ident: $int_type:ty ),*) => {
+        impl $row_type<'_> {
+            pub fn get(&self, name: &str) -> Result<&ParamRowValue, RowAccessError> {
+                get_row_value(self.row, self.field_names, name)
+            }
+
+            $(
+            pub fn $getter(&self, name: &str) -> Result<$int_type, RowAccessError> {
+                let value = self.get(name)?;
+                value.as_integer()
+                    .and_then(|v| <$int_type>::try_from(v).ok())
+                    .ok_or_else(|| conversion_error(name, value, stringify!($int_type)))
+            }
+            )*
+
+            pub fn get_f32(&self, name: &str) -> Result<f32, RowAccessError> {
+                self.get_f64(name).map(|v| v as f32)
+            }
+
+            pub fn get_f64(&self, name: &str) -> Result<f64, RowAccessError> {
+                let value = self.get(name)?;
+                value.as_f64().ok_or_else(|| conversion_error(name, value, "f64"))
+            }
+
+            pub fn get_str(&self, name: &str) -> Result<&str, RowAccessError> {
+                match self.get(name)? {
+                    ParamRowValue::FIXSTR(s) | ParamRowValue::FIXSTRW(s) => Ok(s),
+                    value => Err(conversion_error(name, value, "str")),
+                }
+            }
+
+            /// Iterate over field names and values.
+            pub fn fields(&self) -> impl Iterator<Item = (&str, &ParamRowValue)> {
+                self.field_names.iter().map(|n| n.as_str()).zip(self.row.data.iter())
+            }
+        }
+    };
+}
+
+impl_row_getters!(RowRef,
+    get_s8: i8, get_u8: u8, get_s16: i16, get_u16: u16,
+    get_s32: i32, get_u32: u32, get_s64: i64, get_u64: u64);
+impl_row_getters!(RowMut,
+    get_s8: i8, get_u8: u8, get_s16: i16, get_u16: u16,
+    get_s32: i32, get_u32: u32, get_s64: i64, get_u64: u64);
+
+impl RowMut<'_> {
+    /// Return the value and bit size of a field, 0 if it is not a bitfield.
+    fn get_mut(&mut self, name: &str) -> Result<(&mut ParamRowValue, usize), RowAccessError> {
+        let index = self.field_names.iter().position(|n| n == name)
+            .ok_or_else(|| RowAccessError::NoField(name.to_string()))?;
+        let id = self.row.id;
+        let bit_size = self.field_bits.get(index).copied().unwrap_or(0);
+        let value = self.row.data.get_mut(index).ok_or(RowAccessError::NoValues(id))?;
+        Ok((value, bit_size))
+    }
+
+    /// Replace a value by another of the same type.
+    pub fn set(&mut self, name: &str, value: ParamRowValue) -> Result<(), RowAccessError> {
+        let (current, bit_size) = self.get_mut(name)?;
+        let to: &'static str = (&*current).into();
+        if std::mem::discriminant(current) != std::mem::discriminant(&value) {
+            return Err(conversion_error(name, &value, to))
+        }
+        if !fits_in_bits(&value, bit_size) {
+            return Err(conversion_error(name, &value, to))
+        }
+        *current = value;
+        Ok(())
+    }
+
+    /// Set a numeric value, if it fits in the field type and bit size.
+    pub fn set_int(&mut self, name: &str, v: impl Into<i128>) -> Result<(), RowAccessError> {
+        let v = v.into();
+        let (current, bit_size) = self.get_mut(name)?;
+        let to: &'static str = (&*current).into();
+        *current = current.with_integer(v)
+            .filter(|value| fits_in_bits(value, bit_size))
+            .ok_or_else(|| RowAccessError::Conversion {
+                field: name.to_string(), value: v.to_string(), to
+            })?;
+        Ok(())
+    }
+
+    /// Set a numeric value, if it fits in the field type and bit size.
+    pub fn set_float(&mut self, name: &str, v: f64) -> Result<(), RowAccessError> {
+        let (current, bit_size) = self.get_mut(name)?;
+        let to: &'static str = (&*current).into();
+        *current = current.with_float(v)
+            .filter(|value| fits_in_bits(value, bit_size))
+            .ok_or_else(|| RowAccessError::Conversion {
+                field: name.to_string(), value: v.to_string(), to
+            })?;
+        Ok(())
+    }
+
+    /// Set a string value; its length is checked when packing.
+    pub fn set_str(&mut self, name: &str, v: &str) -> Result<(), RowAccessError> {
+        match self.get_mut(name)?.0 {
+            ParamRowValue::FIXSTR(s) | ParamRowValue::FIXSTRW(s) => {
+                *s = v.to_string();
+                Ok(())
+            }
+            value => Err(RowAccessError::Conversion {
+                field: name.to_string(),
+                value: v.to_string(),
+                to: (&*value).into(),
+            }),
+        }
+    }
+}
+
 /// Parse PARAM data, using PARAMDEF info if provided.
 pub fn parse<'a>(i: &'a[u8], paramdef: Option<&paramdef::Paramdef>) -> IResult<&'a[u8], Param> {
     let full_file = i;
     let (i, mut header) = parse_header(i)?;
-    if header.has_ofs_string_name() && header.ofs_name.is_some() {
-        let ofs_name = header.ofs_name.unwrap() as usize;
-        let (_, name) = take_cstring(&full_file[ofs_name..])?;
-        header.param_type.push_str(&String::from_utf8_lossy(name).to_string());
+    if header.has_ofs_string_name() {
+        if let Some(ofs_name) = header.ofs_name {
+            let (_, name) = take_cstring(&full_file[ofs_name as usize..])?;
+            header.param_type.push_str(String::from_utf8_lossy(name).as_ref());
+        }
     }
 
     let (i, mut rows) = count(|i| parse_row(i, &header), header.num_rows as usize)(i)?;
 
+    let use_u64 = header.has_u64_ofs_data();
     for row in &mut rows {
-        let ofs_name = row.ofs_name.u64_if(header.has_u64_ofs_data()) as usize;
+        let ofs_name = row.ofs_name.u64_if(use_u64) as usize;
         if ofs_name != 0 {
-            let (_, name) = take_cstring(&full_file[ofs_name..])?;
-            row.name = sjis_to_string(name).or_else(|| {
-                eprintln!("Can't parse row name: {:?}", name);
+            row.name = if header.has_unicode_names() {
+                let (_, name) = take_utf16_cstring(&full_file[ofs_name..], header.use_be())?;
+                utf16_to_string(&name)
+            } else {
+                let (_, name) = take_cstring(&full_file[ofs_name..])?;
+                sjis_to_string(name)
+            }.or_else(|| {
+                eprintln!("Can't parse row name at offset {:X}.", ofs_name);
                 None
             });
         }
     }
 
-    if paramdef.is_some() {
-        let def = paramdef.unwrap();
-        let row_size = def.row_size();
-        for row in &mut rows {
-            let ofs_data = row.ofs_data.u64_if(header.has_u64_ofs_data()) as usize;
-            if ofs_data == 0 {
-                continue
+    let guessed_row_size = guess_row_size(&header, &rows, full_file.len());
+    let row_size = match paramdef {
+        Some(def) => {
+            if def.header.data_version != header.paramdef_data_version {
+                eprintln!(
+                    "PARAMDEF version {} does not match PARAM version {}.",
+                    def.header.data_version, header.paramdef_data_version
+                );
             }
-            let ofs_data_end = ofs_data + row_size;
-            let (_, data) = parse_row_data(&full_file[ofs_data..ofs_data_end], &header, &def)?;
+            // The guessed size is only a hint, e.g. strings may not follow rows directly, but
+            // a row size larger than the gap between two rows would parse data of other rows.
+            let row_size = def.row_size();
+            if row_size != guessed_row_size && guessed_row_size > 0 {
+                eprintln!(
+                    "PARAMDEF row size {} does not match PARAM row size {}.",
+                    row_size, guessed_row_size
+                );
+            }
+            if matches!(get_max_row_size(&header, &rows), Some(max) if row_size > max) {
+                return Err(Failure((i, ErrorKind::Verify)))
+            }
+            row_size
+        }
+        None => guessed_row_size,
+    };
+    for row in &mut rows {
+        let ofs_data = row.ofs_data.u64_if(use_u64) as usize;
+        if ofs_data == 0 || ofs_data >= full_file.len() {
+            continue
+        }
+        let ofs_data_end = (ofs_data + row_size).min(full_file.len());
+        let raw_data = &full_file[ofs_data..ofs_data_end];
+        row.raw_data = raw_data.to_vec();
+        if let Some(def) = paramdef {
+            let (_, data) = parse_row_data(raw_data, &header, def)?;
             row.data = data;
         }
     }
 
-    Ok((i, Param { header, rows }))
+    let mut param = Param::new(header, rows);
+    if let Some(def) = paramdef {
+        param.set_fields(def);
+    }
+    Ok((i, param))
+}
+
+/// Guess the row size from data offsets, when no PARAMDEF is available.
+///
+/// Rows are usually stored contiguously and followed by strings, so
+/// use the smallest gap between data offsets or up to the strings.
+fn guess_row_size(header: &ParamHeader, rows: &[ParamRow], file_size: usize) -> usize {
+    let use_u64 = header.has_u64_ofs_data();
+    let data_offsets = get_data_offsets(header, rows);
+    let last_ofs = match data_offsets.last() {
+        Some(o) => *o,
+        None => return 0,
+    };
+    let data_end = rows.iter()
+        .map(|r| r.ofs_name.u64_if(use_u64) as usize)
+        .chain(header.ofs_name.map(|o| o as usize))
+        .chain(std::iter::once(header.ofs_strings as usize))
+        .filter(|o| *o > last_ofs)
+        .min()
+        .unwrap_or(file_size);
+    let last_row_size = data_end - last_ofs;
+    get_min_row_gap(&data_offsets).map_or(last_row_size, |gap| gap.min(last_row_size))
+}
+
+/// Return sorted and deduplicated row data offsets, ignoring null ones.
+fn get_data_offsets(header: &ParamHeader, rows: &[ParamRow]) -> Vec<usize> {
+    let use_u64 = header.has_u64_ofs_data();
+    let mut data_offsets: Vec<usize> = rows.iter()
+        .map(|r| r.ofs_data.u64_if(use_u64) as usize)
+        .filter(|o| *o != 0)
+        .collect();
+    data_offsets.sort_unstable();
+    data_offsets.dedup();
+    data_offsets
+}
+
+/// Return the smallest gap between data offsets, see `Param::max_row_size`.
+fn get_max_row_size(header: &ParamHeader, rows: &[ParamRow]) -> Option<usize> {
+    get_min_row_gap(&get_data_offsets(header, rows))
+}
+
+/// Return the smallest gap between sorted data offsets, if there are at
+/// least two of them.
+fn get_min_row_gap(data_offsets: &[usize]) -> Option<usize> {
+    data_offsets.windows(2).map(|w| w[1] - w[0]).min()
 }
@@ -1,14 +1,21 @@
 use std::fmt;
+use std::io;
 
 use nom::IResult;
+use nom::bytes::complete::take;
 use nom::multi::count;
 use nom::number::complete::*;
 use nom::sequence::tuple;
 
-use crate::formats::common::{sjis_to_string_lossy, take_cstring, take_cstring_from, VarSizeInt};
+use crate::formats::common::{
+    Pack, VarSizeInt, sjis_to_string_lossy, string_to_sjis, string_to_utf16, take_cstring,
+    take_cstring_from, take_utf16_cstring,
+};
+use crate::utils::bin::{u16_to_bytes, u32_to_bytes, u64_to_bytes};
 use crate::utils::str as utils_str;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamdefHeader {
     pub file_size: u32,
     pub header_size: u16,
@@ -27,8 +34,57 @@ impl ParamdefHeader {
     pub fn has_ofs_fields(&self) -> bool { has_ofs_fields(self.format_version) }
     pub fn has_64b_ofs_desc(&self) -> bool { self.format_version >= 201 }
     pub fn can_have_bit_size(&self) -> bool { self.format_version >= 102 }
+    pub fn has_internal_name(&self) -> bool { self.format_version >= 102 }
+    pub fn has_sort_id(&self) -> bool { self.format_version >= 104 }
+    pub fn is_unicode(&self) -> bool { self.unicode != 0 }
+
+    /// Encode a display name or description, in UTF-16 for unicode
+    /// PARAMDEFs and Shift JIS otherwise, without terminator.
+    ///
+    /// Returns None if it can't be encoded in Shift JIS.
+    pub fn encode_display_string(&self, s: &str) -> Option<Vec<u8>> {
+        if self.is_unicode() { Some(string_to_utf16(s, self.use_be())) } else { string_to_sjis(s) }
+    }
+
+    /// Return the header size for this format version.
+    pub fn header_size(&self) -> usize {
+        if self.has_ofs_fields() { HEADER_SIZE + 8 } else { HEADER_SIZE }
+    }
+
+    /// Return the field size for this format version.
+    pub fn field_size(&self) -> usize {
+        let mut size = FIELD_SIZE;
+        if self.has_64b_ofs_desc() { size += 4 }
+        if self.has_internal_name() { size += 0x20 }
+        if self.has_sort_id() { size += 4 }
+        size
+    }
 }
 
+impl Pack for ParamdefHeader {
+    fn write(&self, f: &mut dyn io::Write) -> io::Result<usize> {
+        let use_be = self.use_be();
+        f.write_all(&u32_to_bytes(self.file_size, use_be))?;
+        f.write_all(&u16_to_bytes(self.header_size, use_be))?;
+        f.write_all(&u16_to_bytes(self.data_version, use_be))?;
+        f.write_all(&u16_to_bytes(self.num_fields, use_be))?;
+        f.write_all(&u16_to_bytes(self.field_size, use_be))?;
+        f.write_all(&sjis_fixed_string(&self.param_name, 0x20)?)?;
+        f.write_all(&[self.endianness, self.unicode])?;
+        f.write_all(&u16_to_bytes(self.format_version, use_be))?;
+        if self.has_ofs_fields() {
+            f.write_all(&u64_to_bytes(self.ofs_fields, use_be))?;
+        }
+        Ok(self.header_size())
+    }
+}
+
+pub const HEADER_SIZE: usize = 0x30;  // Without the 64-bit fields offset of 201+.
+pub const FIELD_SIZE: usize = 0x8C;  // Fields of 101; later versions add to it.
+
+pub const EDIT_FLAG_WRAP: u32 = 1 << 0;
+pub const EDIT_FLAG_LOCK: u32 = 1 << 2;
+
 fn use_be(endianness: u8) -> bool { endianness == 0xFF }
 
 fn has_ofs_fields(format_version: u16) -> bool { format_version >= 201 }
@@ -36,6 +92,7 @@ fn has_ofs_fields(format_version: u16) -> bool { format_version >= 201 }
 fn parse_header(i: &[u8]) -> IResult<&[u8], ParamdefHeader> {
     let p_u32 = if use_be(i[0x2C]) { be_u32 } else { le_u32 };
     let p_u16 = if use_be(i[0x2C]) { be_u16 } else { le_u16 };
+    let p_u64 = if use_be(i[0x2C]) { be_u64 } else { le_u64 };
     let (i, (file_size, header_size, data_version, num_entries, entry_size)) =
         tuple((p_u32, p_u16, p_u16, p_u16, p_u16))(i)?;
     let (i, param_name) = take_cstring_from(i, 0x20)?;
@@ -43,7 +100,6 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], ParamdefHeader> {
         tuple((le_u8, le_u8, p_u16))(i)?;
 
     let (i, ofs_entries) = if has_ofs_fields(format_version) {
-        let p_u64 = if use_be(i[0x2C]) { be_u64 } else { le_u64 };
         p_u64(i)?
     } else {
         (i, 0)
@@ -57,7 +113,7 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], ParamdefHeader> {
             data_version,
             num_fields: num_entries,
             field_size: entry_size,
-            param_name: String::from_utf8_lossy(param_name).to_string(),
+            param_name: sjis_to_string_lossy(param_name),
             endianness,
             unicode,
             format_version,
@@ -67,6 +123,7 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], ParamdefHeader> {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamdefField {
     pub display_name: String,
     pub display_type: String,
@@ -95,12 +152,96 @@ impl ParamdefField {
            if !name.contains(":") {
                return 0
            }
-           if let Some(bit_size_str) = name.split(":").last().and_then(|s| Some(s.trim())) {
+           if let Some(bit_size_str) = name.split(":").last().map(|s| s.trim()) {
                return bit_size_str.parse::<usize>().unwrap_or(0)
            }
         }
         0
     }
+
+    /// Return the internal name without bit size or array length, or
+    /// the display name if there is no internal name.
+    pub fn column_name(&self) -> String {
+        match &self.internal_name {
+            Some(name) => name.split([':', '[']).next().unwrap_or("").trim().to_string(),
+            None => self.display_name.clone(),
+        }
+    }
+
+    /// Create a field from its type, size and internal name, also used
+    /// as display name. Other properties are zeroed.
+    pub fn new(type_str: &str, byte_count: u32, internal_name: &str) -> ParamdefField {
+        let is_float = matches!(type_str, "f32" | "f64" | "angle32");
+        ParamdefField {
+            display_name: internal_name.to_string(),
+            display_type: type_str.to_string(),
+            display_format: if is_float { "%f" } else { "%d" }.to_string(),
+            default_value: 0.0,
+            min_value: 0.0,
+            max_value: 0.0,
+            increment: 0.0,
+            edit_flags: 0,
+            byte_count,
+            ofs_desc: VarSizeInt::from_u32(0),
+            internal_type: type_str.to_string(),
+            internal_name: Some(internal_name.to_string()),
+            sort_id: 0,
+            description: None,
+        }
+    }
+}
+
+impl ParamdefField {
+    /// Write the field, which depends on the header format version.
+    ///
+    /// Strings are encoded in Shift JIS and zero-padded, see
+    /// `fixed_string`, except the display name of unicode PARAMDEFs.
+    pub fn write(&self, header: &ParamdefHeader, f: &mut dyn io::Write) -> io::Result<usize> {
+        let use_be = header.use_be();
+        let display_name = header.encode_display_string(&self.display_name)
+            .ok_or_else(|| encoding_error(&self.display_name))?;
+        f.write_all(&fixed_string(&display_name, 0x40))?;
+        f.write_all(&sjis_fixed_string(&self.display_type, 0x8)?)?;
+        f.write_all(&sjis_fixed_string(&self.display_format, 0x8)?)?;
+        for value in &[self.default_value, self.min_value, self.max_value, self.increment] {
+            f.write_all(&u32_to_bytes(value.to_bits(), use_be))?;
+        }
+        f.write_all(&u32_to_bytes(self.edit_flags, use_be))?;
+        f.write_all(&u32_to_bytes(self.byte_count, use_be))?;
+        if header.has_64b_ofs_desc() {
+            f.write_all(&u64_to_bytes(self.ofs_desc.u64_if(true), use_be))?;
+        } else {
+            f.write_all(&u32_to_bytes(self.ofs_desc.u64_if(false) as u32, use_be))?;
+        }
+        f.write_all(&sjis_fixed_string(&self.internal_type, 0x20)?)?;
+        if header.has_internal_name() {
+            let internal_name = self.internal_name.as_deref().unwrap_or("");
+            f.write_all(&sjis_fixed_string(internal_name, 0x20)?)?;
+        }
+        if header.has_sort_id() {
+            f.write_all(&u32_to_bytes(self.sort_id, use_be))?;
+        }
+        Ok(header.field_size())
+    }
+}
+
+/// Return a zero-terminated string padded to `length` bytes, truncated
+/// if needed to keep the terminator.
+pub fn fixed_string(s: &[u8], length: usize) -> Vec<u8> {
+    let mut data = s.to_vec();
+    data.truncate(length - 1);
+    data.resize(length, 0);
+    data
+}
+
+/// Return a Shift JIS `fixed_string`, failing on unmappable characters.
+fn sjis_fixed_string(s: &str, length: usize) -> io::Result<Vec<u8>> {
+    let sjis = string_to_sjis(s).ok_or_else(|| encoding_error(s))?;
+    Ok(fixed_string(&sjis, length))
+}
+
+fn encoding_error(s: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Can't encode \"{}\".", s))
 }
 
 impl fmt::Display for ParamdefField {
@@ -120,8 +261,25 @@ impl fmt::Display for ParamdefField {
     }
 }
 
+/// Return the size in bytes of a single value of this type, if known.
+pub fn get_type_size(type_str: &str) -> Option<u32> {
+    match type_str {
+        "s8" | "u8" | "dummy8" | "fixstr" => Some(1),
+        "s16" | "u16" | "fixstrW" => Some(2),
+        "s32" | "u32" | "b32" | "f32" | "angle32" => Some(4),
+        "s64" | "u64" | "f64" => Some(8),
+        _ => None,
+    }
+}
+
+/// Return true if this type can contain bitfields.
+pub fn is_bitfield_type(type_str: &str) -> bool {
+    matches!(type_str, "s8" | "u8" | "dummy8" | "s16" | "u16" | "s32" | "u32")
+}
+
 fn parse_field<'a>(i: &'a[u8], header: &ParamdefHeader) -> IResult<&'a[u8], ParamdefField> {
-    let (i, display_name) = take_cstring_from(i, 0x40)?;
+    let (i, display_name) = take(0x40usize)(i)?;
+    let (_, display_name) = parse_display_string(display_name, header)?;
     let (i, display_type) = take_cstring_from(i, 0x8)?;
     let (i, display_format) = take_cstring_from(i, 0x8)?;
 
@@ -132,28 +290,28 @@ fn parse_field<'a>(i: &'a[u8], header: &ParamdefHeader) -> IResult<&'a[u8], Para
     let (i, (default_value, min_value, max_value, increment, edit_flags, byte_count)) =
         tuple((p_f32, p_f32, p_f32, p_f32, p_u32, p_u32))(i)?;
 
-    let (i, ofs_desc) = if header.format_version < 201 {
-        let (i, o) = p_u32(i)?;
-        (i, VarSizeInt { vu32: o })
-    } else {
+    let (i, ofs_desc) = if header.has_64b_ofs_desc() {
         let (i, o) = p_u64(i)?;
         (i, VarSizeInt { vu64: o })
+    } else {
+        let (i, o) = p_u32(i)?;
+        (i, VarSizeInt::from_u32(o))
     };
 
     let (i, internal_type) = take_cstring_from(i, 0x20)?;
 
-    let (i, internal_name): (&[u8], Option<String>) = if header.format_version >= 102 {
+    let (i, internal_name): (&[u8], Option<String>) = if header.has_internal_name() {
         take_cstring_from(i, 0x20).map(|(i, s)| (i, Some(sjis_to_string_lossy(s))))?
     } else {
         (i, None)
     };
 
-    let (i, sort_id) = if header.format_version >= 104 { p_u32(i)? } else { (i, 0) };
+    let (i, sort_id) = if header.has_sort_id() { p_u32(i)? } else { (i, 0) };
 
     Ok((
         i,
         ParamdefField {
-            display_name: sjis_to_string_lossy(display_name),
+            display_name,
             display_type: sjis_to_string_lossy(display_type),
             display_format: sjis_to_string_lossy(display_format),
             default_value,
@@ -173,25 +331,92 @@ fn parse_field<'a>(i: &'a[u8], header: &ParamdefHeader) -> IResult<&'a[u8], Para
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Paramdef {
     pub header: ParamdefHeader,
     pub fields: Vec<ParamdefField>,
 }
 
+/// Position of a field value in a row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldLayout {
+    pub offset: usize,      // For bitfields, offset of the containing int.
+    pub bit_offset: usize,  // For bitfields, offset from the least significant bit.
+}
+
 impl Paramdef {
+    /// Create a little-endian PARAMDEF of format version 104, the first
+    /// with internal names and sort IDs. Sizes and offsets are set when
+    /// packing.
+    pub fn new(param_name: &str, fields: Vec<ParamdefField>) -> Paramdef {
+        let header = ParamdefHeader {
+            file_size: 0,
+            header_size: 0,
+            data_version: 1,
+            num_fields: fields.len() as u16,
+            field_size: 0,
+            param_name: param_name.to_string(),
+            endianness: 0,
+            unicode: 0,
+            format_version: 104,
+            ofs_fields: 0,
+        };
+        Paramdef { header, fields }
+    }
+
     pub fn row_size(&self) -> usize {
-        let mut num_bits: usize = 0;
-        self.fields.iter().map(|field| {
-            match field.bit_size() {
-                0 => field.byte_count as usize,
-                n => {
-                    num_bits += n;
-                    let num_bytes = num_bits / 8;
-                    num_bits %= 8;
-                    num_bytes
-                }
+        self.compute_layout().1
+    }
+
+    /// Return the position of each field in a row.
+    ///
+    /// Consecutive bitfields of the same type share their containing
+    /// int as long as they fit in it, dummy8 counting as u8. Returns an
+    /// error if a bitfield can't be stored in its type.
+    pub fn get_layout(&self) -> Result<Vec<FieldLayout>, String> {
+        for field in self.fields.iter().filter(|f| f.bit_size() > 0) {
+            let type_str = &field.display_type;
+            if !is_bitfield_type(type_str) || get_type_size(type_str) != Some(field.byte_count) {
+                return Err(format!("Unhandled bitfield type {} for field {}", type_str, field))
             }
-        }).sum()
+            if field.bit_size() > field.byte_count as usize * 8 {
+                return Err(format!("Bitfield too large for field {}", field))
+            }
+        }
+        Ok(self.compute_layout().0)
+    }
+
+    /// Return fields layout and row size, without checking bitfield types.
+    fn compute_layout(&self) -> (Vec<FieldLayout>, usize) {
+        let mut layout = Vec::with_capacity(self.fields.len());
+        let mut ofs = 0;
+        let mut bitfield: Option<(&str, usize)> = None;  // Current bitfield type and bit offset.
+        for field in &self.fields {
+            let num_bytes = field.byte_count as usize;
+            let bit_size = field.bit_size();
+            if bit_size == 0 {
+                layout.push(FieldLayout { offset: ofs, bit_offset: 0 });
+                ofs += num_bytes;
+                bitfield = None;
+                continue
+            }
+            let type_str = match field.display_type.as_str() {
+                "dummy8" => "u8",
+                t => t,
+            };
+            let bit_offset = match bitfield {
+                Some((t, bit_ofs)) if t == type_str && bit_ofs + bit_size <= num_bytes * 8 => {
+                    bit_ofs
+                }
+                _ => {
+                    ofs += num_bytes;
+                    0
+                }
+            };
+            layout.push(FieldLayout { offset: ofs - num_bytes, bit_offset });
+            bitfield = Some((type_str, bit_offset + bit_size));
+        }
+        (layout, ofs)
     }
 }
 
@@ -209,6 +434,18 @@ impl fmt::Display for Paramdef {
     }
 }
 
+/// Parse a zero-terminated display name or description, see
+/// `ParamdefHeader::encode_display_string`.
+fn parse_display_string<'a>(i: &'a[u8], header: &ParamdefHeader) -> IResult<&'a[u8], String> {
+    if header.is_unicode() {
+        let (i, chars) = take_utf16_cstring(i, header.use_be())?;
+        Ok((i, String::from_utf16_lossy(&chars)))
+    } else {
+        let (i, sjis) = take_cstring(i)?;
+        Ok((i, sjis_to_string_lossy(sjis)))
+    }
+}
+
 pub fn parse(i: &[u8]) -> IResult<&[u8], Paramdef> {
     let full_file = i;
     let (i, header) = parse_header(i)?;
@@ -229,8 +466,8 @@ pub fn parse(i: &[u8]) -> IResult<&[u8], Paramdef> {
         if ofs == 0 {
             continue
         }
-        let (_, sjis_desc) = take_cstring(&full_file[ofs..])?;
-        field.description = Some(sjis_to_string_lossy(sjis_desc));
+        let (_, description) = parse_display_string(&full_file[ofs..], &header)?;
+        field.description = Some(description);
     }
 
     Ok((i, Paramdef { header, fields }))
@@ -5,21 +5,39 @@ pub mod formats {
     pub mod bhd;
     pub mod bhf;
     pub mod bnd;
+    pub mod bnd4;
     pub mod common;
     pub mod dcx;
     pub mod dat;
     pub mod param;
     pub mod paramdef;
 }
+pub mod params {
+    pub mod bundle;
+    pub mod diff;
+    #[cfg(test)]
+    pub mod fixtures;
+    pub mod infer;
+    pub mod merge;
+    pub mod migrate;
+    pub mod query;
+    pub mod refs;
+}
 pub mod repackers {
+    pub mod bhd;
+    pub mod bhf;
+    pub mod bnd;
     pub mod dat;
     pub mod dcx;
     pub mod errors;
+    pub mod param;
+    pub mod paramdef;
 }
 pub mod unpackers {
     pub mod bhd;
     pub mod bhf;
     pub mod bnd;
+    pub mod bnd4;
     pub mod dcx;
     pub mod errors;
     pub mod dat;
@@ -28,6 +46,7 @@ pub mod unpackers {
 }
 pub mod utils {
     pub mod bin;
+    pub mod crypto;
     pub mod fs;
     pub mod str;
 }
@@ -16,11 +16,22 @@ pub fn hash(s: &str) -> u32 {
     val.to_u32_digits()[0]
 }
 
+/// Compute the 64-bit hash for a string, used since Elden Ring.
+pub fn hash_64(s: &str) -> u64 {
+    let s = s.to_lowercase();
+    s.chars().fold(0u64, |val, c| val.wrapping_mul(0x85).wrapping_add(c as u64))
+}
+
 /// Get the string representation for this hash.
 pub fn hash_as_string(h: u32) -> String {
     format!("{:08X}", h)
 }
 
+/// Get the string representation for this 64-bit hash.
+pub fn hash_64_as_string(h: u64) -> String {
+    format!("{:016X}", h)
+}
+
 /// Load a namelist file into a map.
 ///
 /// Format for the input file should be the following for every line:
@@ -28,11 +39,9 @@ pub fn hash_as_string(h: u32) -> String {
 pub fn load_name_map(path: &str) -> Result<HashMap<String, String>, Error> {
     let mut names = HashMap::new();
     let namefile = fs::File::open(path)?;
-    for line_ in BufReader::new(namefile).lines() {
-        if let Ok(line) = line_ {
-            let (hash, name) = line.split_at(8);
-            names.insert(hash.to_string(), name[2..].to_string());
-        }
+    for line in BufReader::new(namefile).lines().map_while(Result::ok) {
+        let (hash, name) = line.split_at(8);
+        names.insert(hash.to_string(), name[2..].to_string());
     }
     Ok(names)
 }
@@ -51,5 +60,12 @@ mod tests {
     fn test_hash_as_string() {
         assert_eq!(hash_as_string(0xCAFECAFE), "CAFECAFE");
         assert_eq!(hash_as_string(0xDECE), "0000DECE");
+        assert_eq!(hash_64_as_string(0xDECE), "000000000000DECE");
+    }
+
+    #[test]
+    fn test_hash_64() {
+        assert_eq!(hash_64("/A"), 0x2F * 0x85 + 0x61);
+        assert_eq!(hash_64("/chr"), hash_64("/CHR"));
     }
 }
@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path;
+
+use crate::formats::bnd4;
+use crate::formats::dcx;
+use crate::formats::param::Param;
+use crate::formats::paramdef::Paramdef;
+use crate::unpackers::bnd::{get_entry_file_name, load_bnd};
+use crate::unpackers::bnd4::load_bnd4;
+use crate::unpackers::dcx::load_dcx_data;
+use crate::unpackers::errors::UnpackError;
+use crate::unpackers::param::load_param;
+use crate::unpackers::paramdef::{load_paramdef_file, load_paramdef_xml_file};
+use crate::utils::crypto::{AES_BLOCK_SIZE, decrypt_aes_cbc};
+use crate::utils::fs as utils_fs;
+
+/// Size of the AES-256 key used by regulation files.
+pub const REGULATION_KEY_SIZE: usize = 32;
+
+/// PARAMs of a binder, indexed by name.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ParamBundle {
+    pub params: BTreeMap<String, Param>,
+    pub missing_defs: Vec<String>,  // Names of PARAMs loaded without PARAMDEF.
+}
+
+impl ParamBundle {
+    pub fn get(&self, name: &str) -> Option<&Param> {
+        self.params.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Param> {
+        self.params.get_mut(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.params.keys().map(|name| name.as_str())
+    }
+}
+
+/// Load a param binder from disk, see `load_param_bundle`.
+pub fn load_param_bundle_file(
+    bundle_path: &str,
+    paramdefs: &HashMap<String, Paramdef>,
+) -> Result<ParamBundle, UnpackError> {
+    let bundle_data = utils_fs::open_file_to_vec(path::Path::new(bundle_path))?;
+    load_param_bundle(&bundle_data, paramdefs)
+}
+
+/// Load all PARAMs of a BND3 or BND4 binder, optionally in a DCX.
+///
+/// PARAMs are named after their entry file name without extension.
+/// Each PARAM is loaded with the PARAMDEF of its param type, if any.
+pub fn load_param_bundle(
+    bundle_data: &[u8],
+    paramdefs: &HashMap<String, Paramdef>,
+) -> Result<ParamBundle, UnpackError> {
+    let decomp_data;
+    let bundle_data = if bundle_data.starts_with(dcx::HEADER_MAGIC) {
+        decomp_data = load_dcx_data(bundle_data)?.1;
+        &decomp_data[..]
+    } else {
+        bundle_data
+    };
+
+    let entries: Vec<(Option<String>, usize, usize)> = if bundle_data.starts_with(bnd4::MAGIC) {
+        load_bnd4(bundle_data)?.file_infos.into_iter()
+            .map(|info| (info.path, info.ofs_data as usize, info.size as usize))
+            .collect()
+    } else {
+        load_bnd(bundle_data)?.file_infos.into_iter()
+            .map(|info| (info.path, info.ofs_data as usize, info.size as usize))
+            .collect()
+    };
+
+    let mut bundle = ParamBundle { params: BTreeMap::new(), missing_defs: vec!() };
+    for (entry_path, ofs_data, size) in entries {
+        let entry_path = entry_path.unwrap_or_default();
+        let file_name = get_entry_file_name(&entry_path);
+        let name = match file_name.strip_suffix(".param") {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let param_data = bundle_data.get(ofs_data..ofs_data + size).ok_or_else(|| {
+            UnpackError::Parsing(format!("Entry {} is out of the binder data.", file_name))
+        })?;
+        // Load without values first, as the param type may be in the strings block.
+        let load_error = |e| UnpackError::Parsing(format!("Failed to load {}: {:?}", name, e));
+        let mut param = load_param(param_data, None).map_err(load_error)?;
+        match paramdefs.get(&param.header.param_type) {
+            Some(paramdef) => param = load_param(param_data, Some(paramdef)).map_err(load_error)?,
+            None => bundle.missing_defs.push(name.clone()),
+        }
+        bundle.params.insert(name, param);
+    }
+    Ok(bundle)
+}
+
+/// Load an encrypted regulation file from disk, see `load_regulation`.
+pub fn load_regulation_file(
+    regulation_path: &str,
+    key: &[u8],
+    paramdefs: &HashMap<String, Paramdef>,
+) -> Result<ParamBundle, UnpackError> {
+    let regulation_data = utils_fs::open_file_to_vec(path::Path::new(regulation_path))?;
+    load_regulation(&regulation_data, key, paramdefs)
+}
+
+/// Load PARAMs from a regulation file (DS3 Data0.bdt, ER regulation.bin).
+///
+/// Regulation files are param binders encrypted with AES-256 in CBC
+/// mode, with the IV in the first 16 bytes. The key depends on the game.
+pub fn load_regulation(
+    regulation_data: &[u8],
+    key: &[u8],
+    paramdefs: &HashMap<String, Paramdef>,
+) -> Result<ParamBundle, UnpackError> {
+    if key.len() != REGULATION_KEY_SIZE {
+        return Err(UnpackError::Decryption(format!(
+            "Regulation key must be {} bytes, got {}.", REGULATION_KEY_SIZE, key.len()
+        )))
+    }
+    if regulation_data.len() < AES_BLOCK_SIZE {
+        return Err(UnpackError::Decryption("Regulation file is too short.".to_string()))
+    }
+    let (iv, data) = regulation_data.split_at(AES_BLOCK_SIZE);
+    let mut data = data.to_vec();
+    decrypt_aes_cbc(&mut data, key, iv);
+    load_param_bundle(&data, paramdefs)
+}
+
+/// Load all PARAMDEFs of a directory, binary or XML, by param type.
+pub fn load_paramdefs_dir(dir_path: &str) -> Result<HashMap<String, Paramdef>, UnpackError> {
+    let mut paramdefs = HashMap::new();
+    for entry in fs::read_dir(dir_path)? {
+        let entry_path = entry?.path();
+        let path_str = match entry_path.to_str() {
+            Some(s) => s,
+            None => continue,
+        };
+        let paramdef = match entry_path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("xml") => load_paramdef_xml_file(path_str)?,
+            Some(e) if e.eq_ignore_ascii_case("paramdef") => load_paramdef_file(path_str)?,
+            _ => continue,
+        };
+        paramdefs.insert(paramdef.header.param_name.clone(), paramdef);
+    }
+    Ok(paramdefs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::formats::param::ParamRowValue;
+    use crate::params::fixtures::{PARAM_TYPE, build_paramdef, build_raw_param};
+    use crate::repackers::bnd::pack_bnd;
+    use crate::repackers::param::pack_param;
+    use crate::utils::crypto::encrypt_aes_cbc;
+
+    fn build_param_data(param_type: &str) -> Vec<u8> {
+        let mut param = build_raw_param(param_type, vec![(1, vec![0x2A, 0, 0, 0])]);
+        pack_param(&mut param, None).unwrap()
+    }
+
+    fn build_bundle() -> Vec<u8> {
+        // Start from a BND3 with two entries, like in `repackers::bnd` tests.
+        let mut original = vec!();
+        original.extend_from_slice(b"BND307D7R6\0\0\x74\x00\x00\x00");
+        original.extend_from_slice(&[2, 0, 0, 0, 0x5E, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        original.extend_from_slice(&[0x40, 0, 0, 0, 3, 0, 0, 0, 0x60, 0, 0, 0]);
+        original.extend_from_slice(&[10, 0, 0, 0, 0x50, 0, 0, 0, 3, 0, 0, 0]);
+        original.extend_from_slice(&[0x40, 0, 0, 0, 2, 0, 0, 0, 0x70, 0, 0, 0]);
+        original.extend_from_slice(&[20, 0, 0, 0, 0x57, 0, 0, 0, 2, 0, 0, 0]);
+        original.extend_from_slice(b"N:\\a.x\0N:\\b.y\0\0\0");
+        original.extend_from_slice(b"ABC\0\0\0\0\0\0\0\0\0\0\0\0\0DE");
+        let mut bnd = load_bnd(&original).unwrap();
+        bnd.file_infos[0].path = Some("N:\\param\\TestParam.param".to_owned());
+        bnd.file_infos[1].path = Some("N:\\param\\OtherParam.param".to_owned());
+        let files_data = vec![build_param_data(PARAM_TYPE), build_param_data("OTHER_ST")];
+        pack_bnd(&mut bnd, &files_data).unwrap()
+    }
+
+    #[test]
+    fn test_load_param_bundle() {
+        let mut paramdefs = HashMap::new();
+        paramdefs.insert(PARAM_TYPE.to_owned(), build_paramdef(&[("s32", 4, "a")]));
+        let bundle = load_param_bundle(&build_bundle(), &paramdefs).unwrap();
+        assert_eq!(bundle.names().collect::<Vec<&str>>(), vec!["OtherParam", "TestParam"]);
+        assert_eq!(bundle.get("TestParam").unwrap().rows[0].data, vec![ParamRowValue::S32(42)]);
+        assert!(bundle.get("OtherParam").unwrap().rows[0].data.is_empty());
+        assert_eq!(bundle.missing_defs, vec!["OtherParam"]);
+    }
+
+    #[test]
+    fn test_load_regulation() {
+        let key = [7u8; REGULATION_KEY_SIZE];
+        let iv = [3u8; AES_BLOCK_SIZE];
+        let mut data = build_bundle();
+        data.resize(data.len().div_ceil(AES_BLOCK_SIZE) * AES_BLOCK_SIZE, 0);
+        encrypt_aes_cbc(&mut data, &key, &iv);
+        let mut regulation = iv.to_vec();
+        regulation.append(&mut data);
+
+        let bundle = load_regulation(&regulation, &key, &HashMap::new()).unwrap();
+        assert_eq!(bundle.params.len(), 2);
+        assert!(load_regulation(&regulation, &key[..16], &HashMap::new()).is_err());
+    }
+}
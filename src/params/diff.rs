@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::formats::param::{Param, ParamRow, ParamRowValue};
+use crate::formats::paramdef::Paramdef;
+use crate::unpackers::param::get_field_column_name;
+
+/// Change of a single field value in a row.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FieldChange {
+    pub field: String,
+    pub old: ParamRowValue,
+    pub new: ParamRowValue,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RowDiff {
+    Added { id: u32, name: Option<String> },
+    Removed { id: u32, name: Option<String> },
+    Changed {
+        id: u32,
+        name: Option<String>,
+        old_name: Option<String>,  // Only set if the name changed.
+        changes: Vec<FieldChange>,
+    },
+}
+
+impl RowDiff {
+    pub fn id(&self) -> u32 {
+        match self {
+            RowDiff::Added { id, .. } | RowDiff::Removed { id, .. } => *id,
+            RowDiff::Changed { id, .. } => *id,
+        }
+    }
+}
+
+impl fmt::Display for RowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let noname = String::from("<noname>");
+        match self {
+            RowDiff::Added { id, name } => {
+                write!(f, "+ [{}] {}", id, name.as_ref().unwrap_or(&noname))
+            }
+            RowDiff::Removed { id, name } => {
+                write!(f, "- [{}] {}", id, name.as_ref().unwrap_or(&noname))
+            }
+            RowDiff::Changed { id, name, old_name, changes } => {
+                write!(f, "~ [{}] {}", id, name.as_ref().unwrap_or(&noname))?;
+                if let Some(old_name) = old_name {
+                    write!(f, "\n    Name: {} -> {}", old_name, name.as_ref().unwrap_or(&noname))?;
+                }
+                for change in changes {
+                    write!(f, "\n    {}: {} -> {}", change.field, change.old, change.new)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Differences between two versions of a PARAM, sorted by row ID.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ParamDiff {
+    pub rows: Vec<RowDiff>,
+}
+
+impl ParamDiff {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+impl fmt::Display for ParamDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.rows {
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare rows of two PARAMs loaded with the same PARAMDEF.
+///
+/// Rows are matched by ID; if an ID is used several times, occurrences
+/// are matched in order. Rows loaded without values are compared on
+/// their raw data, reported as a "<raw>" field.
+pub fn diff_params(old: &Param, new: &Param, paramdef: &Paramdef) -> ParamDiff {
+    let field_names: Vec<String> = paramdef.fields.iter().map(get_field_column_name).collect();
+    let old_rows = index_rows(old);
+    let new_rows = index_rows(new);
+
+    let mut keys: Vec<(u32, usize)> = old_rows.keys().chain(new_rows.keys()).cloned().collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut rows = vec!();
+    for key in keys {
+        let (id, _) = key;
+        match (old_rows.get(&key), new_rows.get(&key)) {
+            (Some(old_row), Some(new_row)) => {
+                let changes = diff_rows(old_row, new_row, &field_names);
+                let old_name = Some(old_row.name.clone()).filter(|n| *n != new_row.name);
+                if !changes.is_empty() || old_name.is_some() {
+                    let name = new_row.name.clone();
+                    let old_name = old_name.map(|n| n.unwrap_or_default());
+                    rows.push(RowDiff::Changed { id, name, old_name, changes });
+                }
+            }
+            (Some(old_row), None) => rows.push(RowDiff::Removed { id, name: old_row.name.clone() }),
+            (None, Some(new_row)) => rows.push(RowDiff::Added { id, name: new_row.name.clone() }),
+            (None, None) => {}
+        }
+    }
+    ParamDiff { rows }
+}
+
+/// Map rows by ID and occurrence of this ID.
+pub(crate) fn index_rows(param: &Param) -> HashMap<(u32, usize), &ParamRow> {
+    let mut occurrences: HashMap<u32, usize> = HashMap::new();
+    param.rows.iter().map(|row| {
+        let occurrence = occurrences.entry(row.id).or_insert(0);
+        *occurrence += 1;
+        ((row.id, *occurrence - 1), row)
+    }).collect()
+}
+
+fn diff_rows(old: &ParamRow, new: &ParamRow, field_names: &[String]) -> Vec<FieldChange> {
+    if old.data.is_empty() || new.data.is_empty() {
+        if old.raw_data == new.raw_data {
+            return vec!()
+        }
+        return vec![FieldChange {
+            field: "<raw>".to_string(),
+            old: ParamRowValue::UNK(old.raw_data.clone()),
+            new: ParamRowValue::UNK(new.raw_data.clone()),
+        }]
+    }
+    field_names.iter()
+        .zip(old.data.iter().zip(new.data.iter()))
+        .filter(|(_, (o, n))| o != n)
+        .map(|(field, (o, n))| FieldChange { field: field.clone(), old: o.clone(), new: n.clone() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::params::fixtures::{PARAM_TYPE, build_param, build_paramdef, s32_row};
+
+    #[test]
+    fn test_diff_params() {
+        let paramdef = build_paramdef(&[("s32", 4, "a"), ("s32", 4, "b")]);
+        let row = |id, name: &str, a, b| {
+            ParamRow { name: Some(name.to_owned()), ..s32_row(id, &[a, b]) }
+        };
+        let old = build_param(PARAM_TYPE, vec![
+            row(1, "A", 1, 2), row(2, "B", 3, 4), row(3, "C", 5, 6), row(3, "C2", 0, 0),
+        ]);
+        let new = build_param(PARAM_TYPE, vec![
+            row(3, "C", 5, 6), row(1, "A", 1, 20), row(4, "D", 7, 8), row(3, "X", 0, 0),
+        ]);
+        let diff = diff_params(&old, &new, &paramdef);
+        assert_eq!(diff.rows.iter().map(|r| r.id()).collect::<Vec<u32>>(), vec![1, 2, 3, 4]);
+        match &diff.rows[0] {
+            RowDiff::Changed { changes, old_name: None, .. } => {
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].field, "b");
+                assert_eq!(changes[0].new, ParamRowValue::S32(20));
+            }
+            r => panic!("Unexpected diff {:?}", r),
+        }
+        assert!(matches!(diff.rows[1], RowDiff::Removed { id: 2, .. }));
+        assert!(matches!(&diff.rows[2], RowDiff::Changed { old_name: Some(n), .. } if n == "C2"));
+        assert!(matches!(diff.rows[3], RowDiff::Added { id: 4, .. }));
+        assert_eq!(format!("{}", diff.rows[0]), "~ [1] A\n    b: 2 -> 20");
+
+        assert!(diff_params(&old, &old, &paramdef).is_empty());
+    }
+}
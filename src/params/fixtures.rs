@@ -0,0 +1,41 @@
+//! PARAM and PARAMDEF builders shared by tests.
+
+use crate::formats::param::{Param, ParamHeader, ParamRow, ParamRowValue};
+use crate::formats::paramdef::{Paramdef, ParamdefField};
+
+pub const PARAM_TYPE: &str = "TEST_PARAM_ST";
+
+/// Build a PARAMDEF of `PARAM_TYPE` with fields given as type, byte
+/// count and internal name.
+pub fn build_paramdef(fields: &[(&str, u32, &str)]) -> Paramdef {
+    let fields = fields.iter()
+        .map(|(type_str, byte_count, name)| ParamdefField::new(type_str, *byte_count, name))
+        .collect();
+    Paramdef::new(PARAM_TYPE, fields)
+}
+
+/// Build a DS1-like PARAM with these rows, without field names.
+pub fn build_param(param_type: &str, rows: Vec<ParamRow>) -> Param {
+    Param::new(ParamHeader::new(param_type), rows)
+}
+
+/// Build a row of s32 values, with null raw data of the same size.
+pub fn s32_row(id: u32, values: &[i32]) -> ParamRow {
+    ParamRow {
+        raw_data: vec![0; values.len() * 4],
+        ..ParamRow::new(id, values.iter().map(|v| ParamRowValue::S32(*v)).collect())
+    }
+}
+
+/// Build a PARAM with rows of s32 values, given as ID and values.
+pub fn build_s32_param(param_type: &str, rows: &[(u32, &[i32])]) -> Param {
+    build_param(param_type, rows.iter().map(|(id, values)| s32_row(*id, values)).collect())
+}
+
+/// Build a PARAM with rows of raw data only, as loaded without PARAMDEF.
+pub fn build_raw_param(param_type: &str, rows: Vec<(u32, Vec<u8>)>) -> Param {
+    let rows = rows.into_iter()
+        .map(|(id, raw_data)| ParamRow { raw_data, ..ParamRow::new(id, vec!()) })
+        .collect();
+    build_param(param_type, rows)
+}
@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+
+use crate::formats::param::Param;
+use crate::formats::paramdef::{Paramdef, ParamdefField};
+
+/// Format version used for inferred PARAMDEFs, the first with internal
+/// names and sort IDs.
+pub const INFERRED_FORMAT_VERSION: u16 = 104;
+
+/// Guessed type of a column of row data.
+#[derive(Debug, PartialEq)]
+enum Guess {
+    Padding,
+    Float,
+    Int { signed: bool, refs: Vec<String> },
+}
+
+/// Guess a PARAMDEF skeleton from the raw row data of a PARAM.
+///
+/// The row size is the one found when loading the PARAM, from offsets
+/// between rows. Each 4-byte aligned column is then guessed across all
+/// rows: zeroes everywhere are padding, plausible floats are f32, and
+/// integers too large for their size are split in smaller integers.
+/// Integer columns whose values all are IDs of one of the `others`
+/// PARAMs are marked as possible references in their description.
+/// Fields are named after their offset, e.g. "unk04", and the
+/// PARAMDEF can be exported to XML to be refined by hand.
+pub fn infer_paramdef(param: &Param, others: &[(&str, &Param)]) -> Result<Paramdef, String> {
+    let row_size = param.rows.iter().map(|row| row.raw_data.len()).max().unwrap_or(0);
+    if row_size == 0 {
+        return Err("PARAM has no row data.".to_string())
+    }
+    let rows: Vec<&[u8]> = param.rows.iter()
+        .map(|row| &row.raw_data[..])
+        .filter(|data| data.len() == row_size)
+        .collect();
+    let references: Vec<(&str, HashSet<u32>)> = others.iter()
+        .map(|(name, other)| (*name, other.ids().collect()))
+        .collect();
+    let use_be = param.header.use_be();
+
+    let mut columns: Vec<(usize, usize, Guess)> = vec!();
+    let mut ofs = 0;
+    while ofs < row_size {
+        let size = match ofs {
+            o if o % 4 == 0 && o + 4 <= row_size => 4,
+            o if o % 2 == 0 && o + 2 <= row_size => 2,
+            _ => 1,
+        };
+        guess_column(&rows, ofs, size, use_be, &references, &mut columns);
+        ofs += size;
+    }
+
+    // Merge consecutive padding columns.
+    let mut fields = vec!();
+    let mut padding: Option<(usize, usize)> = None;
+    for (ofs, size, guess) in columns {
+        if guess == Guess::Padding {
+            padding = match padding {
+                Some((pad_ofs, pad_size)) => Some((pad_ofs, pad_size + size)),
+                None => Some((ofs, size)),
+            };
+            continue
+        }
+        if let Some((pad_ofs, pad_size)) = padding.take() {
+            fields.push(build_padding_field(pad_ofs, pad_size));
+        }
+        fields.push(build_field(&rows, ofs, size, use_be, guess));
+    }
+    if let Some((pad_ofs, pad_size)) = padding {
+        fields.push(build_padding_field(pad_ofs, pad_size));
+    }
+
+    let mut paramdef = Paramdef::new(&param.header.param_type, fields);
+    paramdef.header.data_version = param.header.paramdef_data_version;
+    paramdef.header.endianness = param.header.endianness;
+    paramdef.header.unicode = param.header.has_unicode_names() as u8;
+    paramdef.header.format_version = INFERRED_FORMAT_VERSION;
+    Ok(paramdef)
+}
+
+/// Guess the type of a column, splitting it in smaller columns if needed.
+fn guess_column(
+    rows: &[&[u8]],
+    ofs: usize,
+    size: usize,
+    use_be: bool,
+    references: &[(&str, HashSet<u32>)],
+    columns: &mut Vec<(usize, usize, Guess)>,
+) {
+    let values = get_column_values(rows, ofs, size, use_be);
+    if values.iter().all(|v| *v == 0) {
+        return columns.push((ofs, size, Guess::Padding))
+    }
+    if size == 4 && values.iter().all(|v| is_plausible_float(*v as u32)) {
+        return columns.push((ofs, size, Guess::Float))
+    }
+    // Check references on the full value first: large IDs are still IDs.
+    let signed_values: Vec<i64> = values.iter().map(|v| sign_extend(*v, size)).collect();
+    let ids: HashSet<u32> = signed_values.iter()
+        .filter(|v| **v > 0)
+        .map(|v| *v as u32)
+        .collect();
+    let refs: Vec<String> = if ids.is_empty() || size == 1 {
+        vec!()
+    } else {
+        references.iter()
+            .filter(|(_, ref_ids)| ids.is_subset(ref_ids))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    };
+    let (min, max) = match size {
+        4 => (-(1 << 24), 1 << 24),
+        2 => (-0x100, 0x1000),
+        _ => (0, 0xFF),
+    };
+    if size > 1 && refs.is_empty() && signed_values.iter().any(|v| *v < min || *v > max) {
+        guess_column(rows, ofs, size / 2, use_be, references, columns);
+        guess_column(rows, ofs + size / 2, size / 2, use_be, references, columns);
+        return
+    }
+    let signed = size > 1 && signed_values.iter().any(|v| *v < 0);
+    columns.push((ofs, size, Guess::Int { signed: signed || !refs.is_empty(), refs }));
+}
+
+fn get_column_values(rows: &[&[u8]], ofs: usize, size: usize, use_be: bool) -> Vec<u64> {
+    rows.iter().map(|row| {
+        let bytes = &row[ofs..ofs + size];
+        let fold = |v: u64, b: &u8| (v << 8) | *b as u64;
+        if use_be { bytes.iter().fold(0, fold) } else { bytes.iter().rev().fold(0, fold) }
+    }).collect()
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    match size {
+        1 => value as u8 as i64,  // Bytes are kept unsigned.
+        2 => value as u16 as i16 as i64,
+        _ => value as u32 as i32 as i64,
+    }
+}
+
+/// Return true if this looks like a float a game designer would use.
+///
+/// Small integers are denormalized floats, so they are not plausible.
+fn is_plausible_float(value: u32) -> bool {
+    let f = f32::from_bits(value);
+    value == 0 || (f.is_normal() && (1e-6..1e9).contains(&f.abs()))
+}
+
+fn build_padding_field(ofs: usize, size: usize) -> ParamdefField {
+    let name = format!("pad{:02X}", ofs);
+    let internal_name = if size > 1 { format!("{}[{}]", name, size) } else { name.clone() };
+    new_field(name, internal_name, "dummy8", size, None)
+}
+
+fn build_field(
+    rows: &[&[u8]],
+    ofs: usize,
+    size: usize,
+    use_be: bool,
+    guess: Guess,
+) -> ParamdefField {
+    let name = format!("unk{:02X}", ofs);
+    let values = get_column_values(rows, ofs, size, use_be);
+    let (type_str, description) = match guess {
+        Guess::Float => {
+            let floats: Vec<f32> = values.iter().map(|v| f32::from_bits(*v as u32)).collect();
+            let min = floats.iter().cloned().fold(f32::INFINITY, f32::min);
+            let max = floats.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            ("f32", format!("Values from {} to {}.", min, max))
+        }
+        Guess::Int { signed, refs } => {
+            let type_str = match (size, signed) {
+                (1, _) => "u8",
+                (2, false) => "u16",
+                (2, true) => "s16",
+                (_, false) => "u32",
+                (_, true) => "s32",
+            };
+            let values: Vec<i64> = values.iter()
+                .map(|v| if signed { sign_extend(*v, size) } else { *v as i64 })
+                .collect();
+            let min = values.iter().min().unwrap_or(&0);
+            let max = values.iter().max().unwrap_or(&0);
+            let mut description = format!("Values from {} to {}.", min, max);
+            if !refs.is_empty() {
+                description.push_str(&format!(" Possible reference to {}.", refs.join(", ")));
+            }
+            (type_str, description)
+        }
+        Guess::Padding => ("dummy8", String::new()),
+    };
+    new_field(name.clone(), name, type_str, size, Some(description))
+}
+
+fn new_field(
+    name: String,
+    internal_name: String,
+    type_str: &str,
+    byte_count: usize,
+    description: Option<String>,
+) -> ParamdefField {
+    // No range, so values are not checked on CSV import.
+    ParamdefField {
+        display_name: name,
+        description,
+        ..ParamdefField::new(type_str, byte_count as u32, &internal_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::formats::param::ParamRowValue;
+    use crate::params::fixtures::{PARAM_TYPE, build_raw_param};
+    use crate::repackers::param::pack_param;
+    use crate::unpackers::param::load_param;
+
+    fn build_row(f: f32, reference: i32, a: u8, b: u8, c: i16) -> Vec<u8> {
+        let mut data = vec!();
+        data.extend_from_slice(&f.to_le_bytes());
+        data.extend_from_slice(&reference.to_le_bytes());
+        data.extend_from_slice(&[a, b]);
+        data.extend_from_slice(&c.to_le_bytes());
+        data.extend_from_slice(&[0; 6]);
+        data
+    }
+
+    #[test]
+    fn test_infer_paramdef() {
+        let mut param = build_raw_param(PARAM_TYPE, vec![
+            (1, build_row(1.5, 100, 1, 200, 3000)),
+            (2, build_row(0.0, -1, 3, 1, -1)),
+            (3, build_row(-2.25, 200, 0, 0xFF, 0)),
+        ]);
+        param.header.paramdef_data_version = 3;
+        let other = build_raw_param("OTHER_ST", vec![
+            (100, vec![0]), (200, vec![0]), (300, vec![0]),
+        ]);
+        let paramdef = infer_paramdef(&param, &[("Other", &other), ("Self", &param)]).unwrap();
+        let fields: Vec<String> = paramdef.fields.iter()
+            .map(|f| format!("{} {}", f.display_type, f.internal_name.as_ref().unwrap()))
+            .collect();
+        assert_eq!(fields, vec![
+            "f32 unk00", "s32 unk04", "u8 unk08", "u8 unk09", "s16 unk0A", "dummy8 pad0C[6]",
+        ]);
+        assert_eq!(paramdef.fields[0].description.as_deref(), Some("Values from -2.25 to 1.5."));
+        assert_eq!(
+            paramdef.fields[1].description.as_deref(),
+            Some("Values from -1 to 200. Possible reference to Other.")
+        );
+        assert_eq!(paramdef.row_size(), 18);
+        assert_eq!(paramdef.header.data_version, 3);
+
+        // The inferred PARAMDEF can be used to load the PARAM.
+        let data = pack_param(&mut param, None).unwrap();
+        let loaded = load_param(&data, Some(&paramdef)).unwrap();
+        assert_eq!(loaded.row(3).unwrap().get_f32("unk00"), Ok(-2.25));
+        assert_eq!(loaded.rows[1].data[4], ParamRowValue::S16(-1));
+
+        assert!(infer_paramdef(&build_raw_param(PARAM_TYPE, vec!()), &[]).is_err());
+    }
+
+    #[test]
+    fn test_infer_large_references() {
+        let ids = [20_000_000u32, 30_000_100, 0];
+        let param = build_raw_param(PARAM_TYPE, ids.iter().enumerate()
+            .map(|(index, id)| (index as u32, id.to_le_bytes().to_vec()))
+            .collect());
+        let target = build_raw_param("TARGET_ST", vec![
+            (20_000_000, vec![0]), (30_000_100, vec![0]),
+        ]);
+        let paramdef = infer_paramdef(&param, &[("Target", &target)]).unwrap();
+        assert_eq!(paramdef.fields.len(), 1);
+        assert_eq!(paramdef.fields[0].display_type, "s32");
+        assert_eq!(
+            paramdef.fields[0].description.as_deref(),
+            Some("Values from 0 to 30000100. Possible reference to Target.")
+        );
+
+        // Without a matching param, such values are split in smaller fields.
+        let paramdef = infer_paramdef(&param, &[]).unwrap();
+        assert!(paramdef.fields.len() > 1);
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+
+use crate::formats::param::{Param, ParamRow};
+use crate::formats::paramdef::Paramdef;
+use crate::params::diff::index_rows;
+use crate::unpackers::param::get_field_column_name;
+
+/// Field edited differently by several mods.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MergeConflict {
+    pub id: u32,
+    pub field: String,                 // "<row>" if a mod removed a row edited by another.
+    pub values: Vec<(usize, String)>,  // Mod index and its value.
+    pub kept: usize,                   // Index of the mod whose value is kept.
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self.values.iter()
+            .map(|(index, value)| format!("mod {}: {}", index + 1, value))
+            .collect();
+        write!(
+            f,
+            "[{}] {}: {} -- keeping mod {}",
+            self.id, self.field, values.join(", "), self.kept + 1
+        )
+    }
+}
+
+/// Merged PARAM with the conflicts met.
+#[derive(Debug)]
+pub struct ParamMerge {
+    pub param: Param,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merge mods of a base PARAM, all loaded with the same PARAMDEF.
+///
+/// Rows are matched by ID like in `params::diff`. For each row, fields
+/// changed by a single mod, or changed the same way by several mods,
+/// are merged in the base row. If mods change a field differently, the
+/// last mod wins and a conflict is reported; same if a mod removes a
+/// row edited by another mod, in which case the row is kept. Rows
+/// removed by mods and not edited are removed. Merged rows are sorted
+/// by ID and can be written with `repackers::param::pack_param`.
+pub fn merge_params(base: &Param, mods: &[&Param], paramdef: &Paramdef) -> ParamMerge {
+    let field_names: Vec<String> = paramdef.fields.iter().map(get_field_column_name).collect();
+    let base_rows = index_rows(base);
+    let mods_rows: Vec<_> = mods.iter().map(|m| index_rows(m)).collect();
+
+    let mut keys: Vec<(u32, usize)> = base_rows.keys()
+        .chain(mods_rows.iter().flat_map(|rows| rows.keys()))
+        .cloned()
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut rows = vec!();
+    let mut conflicts = vec!();
+    for key in keys {
+        let (id, _) = key;
+        let base_row = base_rows.get(&key).copied();
+        // Mods that added, removed or edited this row.
+        let changes: Vec<(usize, Option<&ParamRow>)> = mods_rows.iter()
+            .map(|rows| rows.get(&key).copied())
+            .enumerate()
+            .filter(|(_, row)| !rows_equal(*row, base_row))
+            .collect();
+        let edits: Vec<(usize, &ParamRow)> = changes.iter()
+            .filter_map(|(index, row)| row.map(|r| (*index, r)))
+            .collect();
+        if changes.is_empty() {
+            rows.extend(base_row.cloned());
+            continue
+        } else if edits.is_empty() {
+            continue
+        } else if edits.len() < changes.len() {
+            let values = changes.iter()
+                .map(|(index, row)| (*index, if row.is_some() { "<edited>" } else { "<removed>" }))
+                .map(|(index, value)| (index, value.to_string()))
+                .collect();
+            let kept = edits.last().unwrap().0;
+            conflicts.push(MergeConflict { id, field: "<row>".to_string(), values, kept });
+        }
+
+        let (last_index, last_row) = *edits.last().unwrap();
+        let mut row = base_row.unwrap_or(last_row).clone();
+        let names: Vec<(usize, Option<String>)> = edits.iter()
+            .map(|(index, row)| (*index, row.name.clone()))
+            .collect();
+        let (name, kept) = merge_value(base_row.map(|r| r.name.clone()), &names);
+        row.name = name;
+        if let Some(kept) = kept {
+            let values = names.into_iter()
+                .map(|(index, name)| (index, name.unwrap_or_default()))
+                .collect();
+            conflicts.push(MergeConflict { id, field: "Name".to_string(), values, kept });
+        }
+        if edits.iter().any(|(_, r)| r.data.len() != row.data.len()) {
+            // Rows without values can only be merged as a whole.
+            row = last_row.clone();
+            if edits.len() > 1 {
+                let values = edits.iter().map(|(i, _)| (*i, "<raw>".to_string())).collect();
+                let field = "<row>".to_string();
+                conflicts.push(MergeConflict { id, field, values, kept: last_index });
+            }
+        } else {
+            for (field_index, field_name) in field_names.iter().enumerate() {
+                let values: Vec<_> = edits.iter()
+                    .map(|(index, row)| (*index, row.data[field_index].clone()))
+                    .collect();
+                let base_value = base_row.map(|r| r.data[field_index].clone());
+                let (value, kept) = merge_value(base_value, &values);
+                row.data[field_index] = value;
+                if let Some(kept) = kept {
+                    let values = values.into_iter()
+                        .map(|(index, value)| (index, value.to_value_string()))
+                        .collect();
+                    let field = field_name.clone();
+                    conflicts.push(MergeConflict { id, field, values, kept });
+                }
+            }
+        }
+        rows.push(row);
+    }
+
+    let mut param = base.clone();
+    param.rows = rows;
+    param.rows.sort_by_key(|row| row.id);
+    param.header.num_rows = param.rows.len() as u16;
+    param.reindex();
+    ParamMerge { param, conflicts }
+}
+
+fn rows_equal(a: Option<&ParamRow>, b: Option<&ParamRow>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.name == b.name && a.data == b.data && a.raw_data == b.raw_data,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Merge values of mods against a base value, None if the row is new.
+///
+/// Returns the merged value and, on conflict, the index of the mod kept.
+fn merge_value<T: Clone + PartialEq>(
+    base: Option<T>,
+    values: &[(usize, T)],
+) -> (T, Option<usize>) {
+    let changed: Vec<&(usize, T)> = values.iter()
+        .filter(|(_, v)| base.as_ref() != Some(v))
+        .collect();
+    match (changed.last(), base) {
+        (None, Some(base)) => (base, None),
+        (None, None) => (values.last().unwrap().1.clone(), None),
+        (Some((index, value)), _) => {
+            let conflict = changed.iter().any(|(_, v)| v != value);
+            (value.clone(), Some(*index).filter(|_| conflict))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::formats::param::ParamRowValue;
+    use crate::params::fixtures::{PARAM_TYPE, build_paramdef, build_s32_param};
+
+    fn get_values(param: &Param) -> Vec<(u32, i32, i32)> {
+        param.rows.iter().map(|r| match r.data[..] {
+            [ParamRowValue::S32(a), ParamRowValue::S32(b)] => (r.id, a, b),
+            _ => panic!("Unexpected row data"),
+        }).collect()
+    }
+
+    #[test]
+    fn test_merge_params() {
+        let paramdef = build_paramdef(&[("s32", 4, "a"), ("s32", 4, "b")]);
+        let base = build_s32_param(PARAM_TYPE, &[
+            (1, &[0, 0]), (2, &[0, 0]), (3, &[0, 0]), (4, &[0, 0]),
+        ]);
+        let mod1 = build_s32_param(PARAM_TYPE, &[
+            (1, &[1, 0]), (2, &[5, 0]), (3, &[0, 0]), (5, &[1, 1]),
+        ]);
+        let mod2 = build_s32_param(PARAM_TYPE, &[
+            (1, &[0, 2]), (2, &[6, 0]), (4, &[0, 0]), (3, &[0, 3]),
+        ]);
+        let merge = merge_params(&base, &[&mod1, &mod2], &paramdef);
+        assert_eq!(
+            get_values(&merge.param),
+            vec![(1, 1, 2), (2, 6, 0), (3, 0, 3), (5, 1, 1)]
+        );
+        assert_eq!(merge.param.header.num_rows, 4);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(format!("{}", merge.conflicts[0]), "[2] a: mod 1: 5, mod 2: 6 -- keeping mod 2");
+
+        // Row 4 removed by mod 1 but edited by mod 2.
+        let mod1 = build_s32_param(PARAM_TYPE, &[(1, &[0, 0]), (2, &[0, 0]), (3, &[0, 0])]);
+        let mod2 = build_s32_param(PARAM_TYPE, &[
+            (1, &[0, 0]), (2, &[0, 0]), (3, &[0, 0]), (4, &[7, 0]),
+        ]);
+        let merge = merge_params(&base, &[&mod1, &mod2], &paramdef);
+        assert_eq!(get_values(&merge.param)[3], (4, 7, 0));
+        assert_eq!(merge.conflicts[0].field, "<row>");
+
+        // Same edit in both mods is not a conflict.
+        let merge = merge_params(&base, &[&mod2, &mod2], &paramdef);
+        assert!(merge.conflicts.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::formats::param::{Param, ParamRowValue};
+use crate::formats::paramdef::{Paramdef, ParamdefField};
+use crate::repackers::errors::PackError;
+use crate::repackers::param::write_row_data;
+use crate::unpackers::param::get_field_column_name;
+use crate::utils::bin::mask;
+
+/// Convert rows of a PARAM loaded with `old_def` to the layout of `new_def`.
+///
+/// Fields are matched by internal name. Values of fields whose type
+/// changed are converted if they fit the new type, else an error is
+/// returned. New fields get their default value and removed fields are
+/// dropped. Raw row data is rebuilt, so bits not covered by the new
+/// PARAMDEF are zeroed. The PARAM version is set to the new one.
+pub fn migrate_param(
+    param: &Param,
+    old_def: &Paramdef,
+    new_def: &Paramdef,
+) -> Result<Param, PackError> {
+    let old_fields: HashMap<String, usize> = old_def.fields.iter()
+        .enumerate()
+        .map(|(index, field)| (get_field_column_name(field), index))
+        .collect();
+    let field_map: Vec<Option<usize>> = new_def.fields.iter()
+        .map(|field| old_fields.get(&get_field_column_name(field)).copied())
+        .collect();
+
+    let mut migrated = param.clone();
+    migrated.header.paramdef_data_version = new_def.header.data_version;
+    migrated.set_fields(new_def);
+    let use_be = migrated.header.use_be();
+    let row_size = new_def.row_size();
+    for row in &mut migrated.rows {
+        if row.data.len() != old_def.fields.len() {
+            let message = format!("Row {} has not been loaded with the old PARAMDEF.", row.id);
+            return Err(PackError::Data(message))
+        }
+        let mut data = Vec::with_capacity(new_def.fields.len());
+        for (field, old_index) in new_def.fields.iter().zip(field_map.iter()) {
+            let value = match old_index {
+                Some(index) => {
+                    let old_field = &old_def.fields[*index];
+                    convert_value(&row.data[*index], old_field, field).ok_or_else(|| {
+                        PackError::Data(format!(
+                            "Row {}: can't convert {} value {} to {}.",
+                            row.id, old_field, row.data[*index], field
+                        ))
+                    })?
+                }
+                None => ParamRowValue::from_field_default(field),
+            };
+            data.push(value);
+        }
+        row.raw_data = vec![0; row_size];
+        write_row_data(&mut row.raw_data, &data, use_be, new_def)?;
+        row.data = data;
+    }
+    Ok(migrated)
+}
+
+/// Convert a value to another field type, if it fits.
+fn convert_value(
+    value: &ParamRowValue,
+    old_field: &ParamdefField,
+    new_field: &ParamdefField,
+) -> Option<ParamRowValue> {
+    let bit_size = new_field.bit_size();
+    if old_field.display_type == new_field.display_type
+        && old_field.byte_count == new_field.byte_count
+        && old_field.bit_size() <= bit_size
+        && (old_field.bit_size() == 0) == (bit_size == 0) {
+        return Some(value.clone())
+    }
+    let value = ParamRowValue::from_str_with_type(
+        &value.to_value_string(),
+        &new_field.display_type,
+        new_field.byte_count as usize,
+        bit_size > 0,
+    ).ok()?;
+    match value.as_f64() {
+        Some(v) if bit_size > 0 && (v < 0.0 || v > mask(bit_size) as f64) => None,
+        _ => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::formats::param::ParamRow;
+    use crate::params::fixtures::{PARAM_TYPE, build_param, build_paramdef};
+
+    #[test]
+    fn test_migrate_param() {
+        let old_def = build_paramdef(&[("s16", 2, "a"), ("u8", 1, "b"), ("u8", 1, "removed")]);
+        let mut new_def = build_paramdef(&[
+            ("u8", 1, "b:4"),
+            ("dummy8", 1, "pad:4"),
+            ("f32", 4, "new"),
+            ("s32", 4, "a"),
+            ("s16", 4, "arr[2]"),
+        ]);
+        new_def.header.data_version = 2;
+        new_def.fields[2].default_value = 1.5;
+        let param = build_param(PARAM_TYPE, vec![ParamRow::new(1, vec![
+            ParamRowValue::S16(-300),
+            ParamRowValue::U8(3),
+            ParamRowValue::U8(9),
+        ])]);
+        let migrated = migrate_param(&param, &old_def, &new_def).unwrap();
+        assert_eq!(migrated.header.paramdef_data_version, 2);
+        let row = &migrated.rows[0];
+        assert_eq!(row.data, vec![
+            ParamRowValue::U8(3),
+            ParamRowValue::U8(0),
+            ParamRowValue::F32(1.5),
+            ParamRowValue::S32(-300),
+            ParamRowValue::ARRAY(vec![ParamRowValue::S16(0), ParamRowValue::S16(0)]),
+        ]);
+        assert_eq!(row.raw_data.len(), 13);
+        assert_eq!(row.raw_data[..5], [0x03, 0x00, 0x00, 0xC0, 0x3F]);
+
+        // A value that does not fit in the new type.
+        let param = build_param(PARAM_TYPE, vec![ParamRow::new(1, vec![
+            ParamRowValue::S16(-300),
+            ParamRowValue::U8(17),
+            ParamRowValue::U8(9),
+        ])]);
+        assert!(migrate_param(&param, &old_def, &new_def).is_err());
+    }
+}
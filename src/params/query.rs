@@ -0,0 +1,765 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::formats::param::{Param, ParamRowValue, RowMut, RowRef};
+
+/// Error when parsing or running a query or an edit.
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    Parsing { position: usize, message: String },
+    UnknownField(String),
+    Evaluation { id: u32, message: String },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::Parsing { position, message } => {
+                write!(f, "Syntax error at character {}: {}", position + 1, message)
+            }
+            QueryError::UnknownField(name) => write!(f, "No field named {}", name),
+            QueryError::Evaluation { id, message } => write!(f, "Row {}: {}", id, message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Condition on row values, see `parse_filter`.
+#[derive(Debug)]
+pub struct Filter {
+    condition: Expr,
+}
+
+/// Assignments to row values, see `parse_edit`.
+#[derive(Debug)]
+pub struct Edit {
+    statements: Vec<Statement>,
+}
+
+#[derive(Debug)]
+struct Statement {
+    assignments: Vec<(String, Expr)>,
+    condition: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Int(i128),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Str(s) => write!(f, "\"{}\"", s),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp { Add, Sub, Mul, Div, Rem, Eq, Ne, Lt, Le, Gt, Ge, And, Or }
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/",
+            BinOp::Rem => "%", BinOp::Eq => "==", BinOp::Ne => "!=", BinOp::Lt => "<",
+            BinOp::Le => "<=", BinOp::Gt => ">", BinOp::Ge => ">=", BinOp::And => "and",
+            BinOp::Or => "or",
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Id,
+    Name,
+    Field(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+    In(Box<Expr>, Vec<Expr>),
+}
+
+/// Parse a filter on rows.
+///
+/// Fields are named after their PARAMDEF internal name, see
+/// `ParamdefField::column_name`; `id` and `name` are the row ID and
+/// name. Values can be combined with `+ - * / %`, compared with
+/// `== != < <= > >=`, `between x and y` or `in (x, y)`, and conditions
+/// with `and`, `or` and `not`. Strings are written in double quotes.
+/// For example: `id between 1000 and 1999 and (weight > 2.5 or name == "Club")`
+/// or `model in ("WP_A_0100", "WP_A_0200")`.
+pub fn parse_filter(text: &str) -> Result<Filter, QueryError> {
+    let mut parser = Parser::new(text)?;
+    let condition = parser.parse_expr()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("expected the end of the filter"))
+    }
+    Ok(Filter { condition })
+}
+
+/// Parse an edit of rows.
+///
+/// An edit is a list of statements separated by ";". Each statement
+/// assigns comma-separated fields, with "=" or "+=", "-=", "*=", "/=",
+/// optionally in rows matching a filter after "where", like
+/// `weight *= 0.9, sellValue = 0 where id between 1000000 and 1999999`.
+pub fn parse_edit(text: &str) -> Result<Edit, QueryError> {
+    let mut parser = Parser::new(text)?;
+    let mut statements = vec!();
+    loop {
+        while parser.eat(&Token::Semicolon) {}
+        if parser.peek().is_none() {
+            break
+        }
+        let mut assignments = vec![parser.parse_assignment()?];
+        while parser.eat(&Token::Comma) {
+            assignments.push(parser.parse_assignment()?);
+        }
+        let condition = if parser.eat_keyword("where") {
+            Some(parser.parse_expr()?)
+        } else {
+            None
+        };
+        statements.push(Statement { assignments, condition });
+        if parser.peek().is_some() && !parser.eat(&Token::Semicolon) {
+            return Err(parser.error("expected \",\", \"where\" or \";\""))
+        }
+    }
+    if statements.is_empty() {
+        return Err(parser.error("expected an assignment"))
+    }
+    Ok(Edit { statements })
+}
+
+/// Return rows matching the filter, in file order.
+pub fn query_param<'a>(param: &'a Param, filter: &Filter) -> Result<Vec<RowRef<'a>>, QueryError> {
+    check_fields(param, &filter.condition)?;
+    let mut rows = vec!();
+    for row in param.iter_rows() {
+        if matches(&filter.condition, &row)? {
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+/// Apply an edit to the rows of a PARAM, returning the IDs of the
+/// rows whose values changed, in file order.
+///
+/// Statements are applied in order; the assignments of a statement all
+/// use row values from before it. Numbers assigned to integer fields
+/// are rounded to the nearest integer, and must fit in the field type.
+/// If any assignment fails, the PARAM is left untouched.
+pub fn edit_param(param: &mut Param, edit: &Edit) -> Result<Vec<u32>, QueryError> {
+    for statement in &edit.statements {
+        for (field, expr) in &statement.assignments {
+            check_field(param, field)?;
+            check_fields(param, expr)?;
+        }
+        if let Some(condition) = &statement.condition {
+            check_fields(param, condition)?;
+        }
+    }
+
+    let mut edited = param.clone();
+    let mut changed = vec![false; edited.rows.len()];
+    for statement in &edit.statements {
+        // Evaluate the whole statement before assigning values.
+        let mut changes = vec!();
+        for (index, row) in edited.iter_rows().enumerate() {
+            if let Some(condition) = &statement.condition {
+                if !matches(condition, &row)? {
+                    continue
+                }
+            }
+            let values = statement.assignments.iter()
+                .map(|(field, expr)| Ok((field.as_str(), eval(expr, &row)?)))
+                .collect::<Result<Vec<(&str, Value)>, QueryError>>()?;
+            changes.push((index, values));
+        }
+        let mut rows: Vec<RowMut> = edited.iter_rows_mut().collect();
+        for (index, values) in changes {
+            let row = &mut rows[index];
+            for (field, value) in values {
+                let previous = row.get(field).cloned();
+                assign(row, field, value)?;
+                changed[index] |= row.get(field).ok() != previous.as_ref().ok();
+            }
+        }
+    }
+
+    let ids = edited.rows.iter()
+        .zip(changed)
+        .filter(|(_, changed)| *changed)
+        .map(|(row, _)| row.id)
+        .collect();
+    *param = edited;
+    Ok(ids)
+}
+
+fn check_field(param: &Param, field: &str) -> Result<(), QueryError> {
+    match param.field_index(field) {
+        Some(_) => Ok(()),
+        None => Err(QueryError::UnknownField(field.to_string())),
+    }
+}
+
+/// Check that all fields used in an expression exist in the PARAM.
+fn check_fields(param: &Param, expr: &Expr) -> Result<(), QueryError> {
+    match expr {
+        Expr::Literal(_) | Expr::Id | Expr::Name => Ok(()),
+        Expr::Field(field) => check_field(param, field),
+        Expr::Neg(e) | Expr::Not(e) => check_fields(param, e),
+        Expr::Binary(_, a, b) => { check_fields(param, a)?; check_fields(param, b) }
+        Expr::Between(e, low, high) => {
+            check_fields(param, e)?;
+            check_fields(param, low)?;
+            check_fields(param, high)
+        }
+        Expr::In(e, list) => {
+            check_fields(param, e)?;
+            list.iter().try_for_each(|item| check_fields(param, item))
+        }
+    }
+}
+
+fn matches(condition: &Expr, row: &RowRef) -> Result<bool, QueryError> {
+    match eval(condition, row)? {
+        Value::Bool(b) => Ok(b),
+        value => Err(eval_error(row, format!("{} is not a condition", value))),
+    }
+}
+
+fn eval_error(row: &RowRef, message: String) -> QueryError {
+    QueryError::Evaluation { id: row.id, message }
+}
+
+fn eval(expr: &Expr, row: &RowRef) -> Result<Value, QueryError> {
+    let error = |message: String| eval_error(row, message);
+    Ok(match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Id => Value::Int(row.id as i128),
+        Expr::Name => Value::Str(row.name.clone().unwrap_or_default()),
+        Expr::Field(field) => {
+            let value = row.get(field).map_err(|e| error(e.to_string()))?;
+            match value {
+                ParamRowValue::FIXSTR(s) | ParamRowValue::FIXSTRW(s) => Value::Str(s.clone()),
+                v => match (v.as_integer(), v.as_f64()) {
+                    (Some(i), _) => Value::Int(i),
+                    (None, Some(f)) => Value::Float(f),
+                    _ => return Err(error(format!("Field {} is not a number or string", field))),
+                },
+            }
+        }
+        Expr::Neg(e) => match eval(e, row)? {
+            Value::Int(i) => Value::Int(
+                i.checked_neg().ok_or_else(|| error(format!("Overflow negating {}", i)))?
+            ),
+            Value::Float(f) => Value::Float(-f),
+            v => return Err(error(format!("Can't negate {}", v))),
+        },
+        Expr::Not(e) => match eval(e, row)? {
+            Value::Bool(b) => Value::Bool(!b),
+            v => return Err(error(format!("{} is not a condition", v))),
+        },
+        Expr::Binary(op @ (BinOp::And | BinOp::Or), a, b) => {
+            let a = matches(a, row)?;
+            // Short-circuit, so "and" can guard conditions that would fail.
+            if a == (*op == BinOp::Or) {
+                Value::Bool(a)
+            } else {
+                Value::Bool(matches(b, row)?)
+            }
+        }
+        Expr::Binary(op, a, b) => {
+            binary_op(*op, eval(a, row)?, eval(b, row)?).map_err(error)?
+        }
+        Expr::Between(e, low, high) => {
+            let value = eval(e, row)?;
+            let low = compare(&value, &eval(low, row)?).map_err(error)?;
+            let high = compare(&value, &eval(high, row)?).map_err(error)?;
+            Value::Bool(low != Ordering::Less && high != Ordering::Greater)
+        }
+        Expr::In(e, list) => {
+            let value = eval(e, row)?;
+            for item in list {
+                if compare(&value, &eval(item, row)?).map_err(error)? == Ordering::Equal {
+                    return Ok(Value::Bool(true))
+                }
+            }
+            Value::Bool(false)
+        }
+    })
+}
+
+fn binary_op(op: BinOp, a: Value, b: Value) -> Result<Value, String> {
+    let ordering = |expected: &[Ordering]| -> Result<Value, String> {
+        Ok(Value::Bool(expected.contains(&compare(&a, &b)?)))
+    };
+    match op {
+        BinOp::Eq => return ordering(&[Ordering::Equal]),
+        BinOp::Ne => return ordering(&[Ordering::Less, Ordering::Greater]),
+        BinOp::Lt => return ordering(&[Ordering::Less]),
+        BinOp::Le => return ordering(&[Ordering::Less, Ordering::Equal]),
+        BinOp::Gt => return ordering(&[Ordering::Greater]),
+        BinOp::Ge => return ordering(&[Ordering::Greater, Ordering::Equal]),
+        _ => {}
+    }
+    let overflow = || format!("Overflow computing {} {} {}", a, op, b);
+    match (&a, &b) {
+        (Value::Int(x), Value::Int(y)) => {
+            let (x, y) = (*x, *y);
+            if y == 0 && matches!(op, BinOp::Div | BinOp::Rem) {
+                return Err("Division by zero".to_string())
+            }
+            let result = match op {
+                BinOp::Add => x.checked_add(y),
+                BinOp::Sub => x.checked_sub(y),
+                BinOp::Mul => x.checked_mul(y),
+                // Checked first, as the remainder can overflow too.
+                BinOp::Div => match x.checked_rem(y) {
+                    Some(0) => x.checked_div(y),
+                    Some(_) => return Ok(Value::Float(x as f64 / y as f64)),
+                    None => None,
+                },
+                _ => x.checked_rem(y),
+            };
+            result.map(Value::Int).ok_or_else(overflow)
+        }
+        _ => {
+            let (x, y) = match (as_float(&a), as_float(&b)) {
+                (Some(x), Some(y)) => (x, y),
+                _ => return Err(format!("Can't compute {} {} {}", a, op, b)),
+            };
+            if y == 0.0 && matches!(op, BinOp::Div | BinOp::Rem) {
+                return Err("Division by zero".to_string())
+            }
+            Ok(Value::Float(match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div => x / y,
+                _ => x % y,
+            }))
+        }
+    }
+}
+
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering, String> {
+    let ordering = match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        _ => match (as_float(a), as_float(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => None,
+        },
+    };
+    ordering.ok_or_else(|| format!("Can't compare {} and {}", a, b))
+}
+
+fn assign(row: &mut RowMut, field: &str, value: Value) -> Result<(), QueryError> {
+    let id = row.id;
+    let result = match &value {
+        Value::Int(i) => row.set_int(field, *i),
+        Value::Float(f) => match row.get(field).map(|v| v.as_integer().is_some()) {
+            Ok(true) => row.set_float(field, f.round()),
+            _ => row.set_float(field, *f),
+        },
+        Value::Str(s) => row.set_str(field, s),
+        Value::Bool(_) => {
+            let message = format!("Can't assign a condition to {}", field);
+            return Err(QueryError::Evaluation { id, message })
+        }
+    };
+    result.map_err(|e| QueryError::Evaluation { id, message: e.to_string() })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Value),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Semicolon,
+}
+
+const OPERATORS: [&str; 16] = [
+    "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=",
+    "<", ">", "=", "+", "-", "*", "/", "%",
+];
+
+/// Split text in tokens, with their byte position.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let error = |position, message: &str| {
+        QueryError::Parsing { position, message: message.to_string() }
+    };
+    let mut tokens = vec!();
+    let mut pos = 0;
+    while let Some(c) = text[pos..].chars().next() {
+        let start = pos;
+        let rest = &text[pos..];
+        let token = if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue
+        } else if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+            let number = &rest[..len];
+            pos += len;
+            let value = if number.contains('.') {
+                number.parse().map(Value::Float).ok()
+            } else {
+                number.parse().map(Value::Int).ok()
+            };
+            Token::Number(value.ok_or_else(|| error(start, "invalid number"))?)
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+            pos += len;
+            Token::Ident(rest[..len].to_string())
+        } else if c == '"' {
+            let mut s = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            loop {
+                match chars.next() {
+                    Some((i, '"')) => { pos += i + 1; break }
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => s.push(escaped),
+                        None => return Err(error(start, "unterminated string")),
+                    },
+                    Some((_, c)) => s.push(c),
+                    None => return Err(error(start, "unterminated string")),
+                }
+            }
+            Token::Str(s)
+        } else {
+            let token = match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                ';' => Token::Semicolon,
+                _ => match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                    Some(op) => Token::Op(op),
+                    None => return Err(error(start, &format!("unexpected character {:?}", c))),
+                },
+            };
+            pos += match &token { Token::Op(op) => op.len(), _ => 1 };
+            token
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from the lowest precedence:
+/// or, and, not, comparisons, sums, products, negation, atoms.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Parser, QueryError> {
+        Ok(Parser { tokens: tokenize(text)?, pos: 0, end: text.len() })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        let position = self.tokens.get(self.pos).map(|(p, _)| *p).unwrap_or(self.end);
+        QueryError::Parsing { position, message: message.to_string() }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, token: &Token, message: &str) -> Result<(), QueryError> {
+        if self.eat(token) { Ok(()) } else { Err(self.error(message)) }
+    }
+
+    fn parse_assignment(&mut self) -> Result<(String, Expr), QueryError> {
+        let field = match self.peek() {
+            Some(Token::Ident(ident)) if !is_keyword(ident) => ident.clone(),
+            _ => return Err(self.error("expected a field name")),
+        };
+        self.pos += 1;
+        let op = match self.next() {
+            Some(Token::Op("=")) => None,
+            Some(Token::Op("+=")) => Some(BinOp::Add),
+            Some(Token::Op("-=")) => Some(BinOp::Sub),
+            Some(Token::Op("*=")) => Some(BinOp::Mul),
+            Some(Token::Op("/=")) => Some(BinOp::Div),
+            _ => { self.pos -= 1; return Err(self.error("expected \"=\" or \"+=\", \"-=\"...")) }
+        };
+        let expr = self.parse_expr()?;
+        let expr = match op {
+            Some(op) => Expr::Binary(op, Box::new(Expr::Field(field.clone())), Box::new(expr)),
+            None => expr,
+        };
+        Ok((field, expr))
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.eat_keyword("or") {
+            expr = Expr::Binary(BinOp::Or, Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_not()?;
+        while self.eat_keyword("and") {
+            expr = Expr::Binary(BinOp::And, Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)))
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, QueryError> {
+        let expr = self.parse_sum()?;
+        if self.eat_keyword("between") {
+            let low = self.parse_sum()?;
+            if !self.eat_keyword("and") {
+                return Err(self.error("expected \"and\""))
+            }
+            let high = self.parse_sum()?;
+            return Ok(Expr::Between(Box::new(expr), Box::new(low), Box::new(high)))
+        }
+        if self.eat_keyword("in") {
+            self.expect(&Token::LParen, "expected \"(\"")?;
+            let mut list = vec![self.parse_sum()?];
+            while self.eat(&Token::Comma) {
+                list.push(self.parse_sum()?);
+            }
+            self.expect(&Token::RParen, "expected \")\"")?;
+            return Ok(Expr::In(Box::new(expr), list))
+        }
+        let op = match self.peek() {
+            Some(Token::Op("==")) => BinOp::Eq,
+            Some(Token::Op("!=")) => BinOp::Ne,
+            Some(Token::Op("<")) => BinOp::Lt,
+            Some(Token::Op("<=")) => BinOp::Le,
+            Some(Token::Op(">")) => BinOp::Gt,
+            Some(Token::Op(">=")) => BinOp::Ge,
+            _ => return Ok(expr),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(expr), Box::new(self.parse_sum()?)))
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_product()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("+")) => BinOp::Add,
+                Some(Token::Op("-")) => BinOp::Sub,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_product()?));
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("*")) => BinOp::Mul,
+                Some(Token::Op("/")) => BinOp::Div,
+                Some(Token::Op("%")) => BinOp::Rem,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        if self.eat(&Token::Op("-")) {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)))
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, QueryError> {
+        let expr = match self.peek() {
+            Some(Token::Number(value)) => Expr::Literal(value.clone()),
+            Some(Token::Str(s)) => Expr::Literal(Value::Str(s.clone())),
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("id") => Expr::Id,
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("name") => Expr::Name,
+            Some(Token::Ident(ident)) if !is_keyword(ident) => Expr::Field(ident.clone()),
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen, "expected \")\"")?;
+                return Ok(expr)
+            }
+            _ => return Err(self.error("expected a value")),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+fn is_keyword(ident: &str) -> bool {
+    ["and", "or", "not", "between", "in", "where", "id", "name"].iter()
+        .any(|keyword| ident.eq_ignore_ascii_case(keyword))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::formats::param::ParamRow;
+    use crate::params::fixtures::{PARAM_TYPE, build_param};
+
+    fn build_weapon_param() -> Param {
+        let row = |id: u32, name: &str, weight: f32, price: u16, model: &str| ParamRow {
+            name: Some(name.to_owned()),
+            ..ParamRow::new(id, vec![
+                ParamRowValue::F32(weight),
+                ParamRowValue::U16(price),
+                ParamRowValue::FIXSTR(model.to_owned()),
+                ParamRowValue::ARRAY(vec![ParamRowValue::U8(1)]),
+            ])
+        };
+        let rows = vec![
+            row(100, "Dagger", 0.5, 100, "WP_A_0100"),
+            row(1000000, "Club", 3.0, 150, "WP_A_0200"),
+            row(1000100, "Axe", 4.5, 300, "WP_A_0300"),
+            row(2000000, "Shield", 5.0, 65535, "WP_A_0400"),
+        ];
+        build_param(PARAM_TYPE, rows).with_field_names(&["weight", "price", "model", "array"])
+    }
+
+    fn query_ids(param: &Param, filter: &str) -> Vec<u32> {
+        let filter = parse_filter(filter).unwrap();
+        query_param(param, &filter).unwrap().iter().map(|row| row.id).collect()
+    }
+
+    #[test]
+    fn test_query_param() {
+        let param = build_weapon_param();
+        assert_eq!(query_ids(&param, "id between 1000000 and 1999999"), vec![1000000, 1000100]);
+        assert_eq!(query_ids(&param, "weight > 2 and price * 2 <= 300"), vec![1000000]);
+        assert_eq!(query_ids(&param, "not (weight >= 1) or name == \"Axe\""), vec![100, 1000100]);
+        assert_eq!(
+            query_ids(&param, "model in (\"WP_A_0100\", \"WP_A_0400\")"),
+            vec![100, 2000000]
+        );
+        assert_eq!(query_ids(&param, "price % 100 == 50 OR id - 1 == 99"), vec![100, 1000000]);
+        assert_eq!(
+            query_ids(&param, "-weight + 1 < 0 and 1 + 2 * 3 == 7"),
+            vec![1000000, 1000100, 2000000]
+        );
+        assert_eq!(query_ids(&param, "price / 300 == 0.5"), vec![1000000]);
+
+        let error = |filter: &str| {
+            parse_filter(filter).and_then(|filter| query_param(&param, &filter).map(|_| ()))
+        };
+        assert_eq!(error("weight >"), Err(QueryError::Parsing {
+            position: 8, message: "expected a value".to_owned()
+        }));
+        assert!(matches!(error("weight > 1 price"), Err(QueryError::Parsing { position: 11, .. })));
+        assert!(matches!(error("name == \"Club"), Err(QueryError::Parsing { .. })));
+        assert!(matches!(error("id between 1 or 2"), Err(QueryError::Parsing { .. })));
+        assert_eq!(error("wieght > 1"), Err(QueryError::UnknownField("wieght".to_owned())));
+        assert!(matches!(error("weight + 1"), Err(QueryError::Evaluation { id: 100, .. })));
+        assert!(matches!(error("model > 1"), Err(QueryError::Evaluation { .. })));
+        assert!(matches!(error("array == 1"), Err(QueryError::Evaluation { .. })));
+        assert!(matches!(error("price / 0 == 1"), Err(QueryError::Evaluation { .. })));
+        let min = "(-170141183460469231731687303715884105727 - 1)";
+        for filter in &[format!("-{} == 1", min), format!("{} / -1 == 1", min)] {
+            assert!(matches!(error(filter), Err(QueryError::Evaluation { .. })), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_edit_param() {
+        let mut param = build_weapon_param();
+        let edit = parse_edit(
+            "weight *= 0.9, price = price * 1.1 where id between 1000000 and 1999999;\n\
+             price -= 100 where name == \"Dagger\"; model = \"WP_B\" where id == 100;",
+        ).unwrap();
+        assert_eq!(edit_param(&mut param, &edit), Ok(vec![100, 1000000, 1000100]));
+        assert_eq!(param.row(1000000).unwrap().get_f32("weight"), Ok(2.7));
+        assert_eq!(param.row(1000000).unwrap().get_u16("price"), Ok(165));  // Rounded.
+        assert_eq!(param.row(1000100).unwrap().get_u16("price"), Ok(330));
+        assert_eq!(param.row(100).unwrap().get_u16("price"), Ok(0));
+        assert_eq!(param.row(100).unwrap().get_str("model"), Ok("WP_B"));
+        assert_eq!(param.row(2000000).unwrap().get_f32("weight"), Ok(5.0));
+
+        // Assignments use values from before the statement.
+        let edit = parse_edit("weight = price, price = weight where name == \"Axe\"").unwrap();
+        assert_eq!(edit_param(&mut param, &edit), Ok(vec![1000100]));
+        assert_eq!(param.row(1000100).unwrap().get_f32("weight"), Ok(330.0));
+        assert_eq!(param.row(1000100).unwrap().get_u16("price"), Ok(4));
+        assert_eq!(edit_param(&mut param, &parse_edit("price = price").unwrap()), Ok(vec!()));
+
+        // A failed edit leaves the PARAM untouched.
+        let edit = parse_edit("price = 1 where id == 100; price += 1").unwrap();
+        assert!(matches!(edit_param(&mut param, &edit), Err(QueryError::Evaluation {
+            id: 2000000, ..
+        })));
+        assert_eq!(param.row(100).unwrap().get_u16("price"), Ok(0));
+        let edit = parse_edit("price = weight > 1").unwrap();
+        assert!(matches!(edit_param(&mut param, &edit), Err(QueryError::Evaluation { .. })));
+        let edit = parse_edit("wieght = 1").unwrap();
+        assert_eq!(edit_param(&mut param, &edit), Err(QueryError::UnknownField("wieght".into())));
+
+        for text in &["", ";", "id = 1", "price 1", "price = 1 where", "price = 1 price = 2"] {
+            assert!(matches!(parse_edit(text), Err(QueryError::Parsing { .. })), "{}", text);
+        }
+    }
+}
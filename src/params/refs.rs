@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+use crate::formats::param::Param;
+use crate::params::bundle::ParamBundle;
+use crate::unpackers::errors::UnpackError;
+
+/// Fields of PARAMs that are IDs of rows in other PARAMs.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReferenceMap {
+    // Target PARAMs by source param type or name, then by field name.
+    pub refs: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl ReferenceMap {
+    pub fn new() -> ReferenceMap {
+        ReferenceMap::default()
+    }
+
+    /// Declare that a field of a param type, or param name, references
+    /// rows of these PARAMs, given by name or param type.
+    pub fn add(&mut self, source: &str, field: &str, targets: Vec<String>) {
+        self.refs.entry(source.to_string()).or_default()
+            .entry(field.to_string()).or_default()
+            .extend(targets);
+    }
+
+    /// Return the targets of a field of a source param type or name.
+    pub fn targets(&self, source: &str, field: &str) -> Option<&[String]> {
+        self.refs.get(source).and_then(|fields| fields.get(field)).map(|t| t.as_slice())
+    }
+
+    /// Return the referencing fields of a PARAM, by field name.
+    fn param_fields(&self, name: &str, param: &Param) -> BTreeMap<&str, &[String]> {
+        [param.header.param_type.as_str(), name].iter()
+            .filter_map(|source| self.refs.get(*source))
+            .flat_map(|fields| fields.iter())
+            .map(|(field, targets)| (field.as_str(), targets.as_slice()))
+            .collect()
+    }
+}
+
+/// Load a reference map from a text file, see `load_reference_map`.
+pub fn load_reference_map_file(map_path: &str) -> Result<ReferenceMap, UnpackError> {
+    let text = fs::read_to_string(map_path)?;
+    load_reference_map(&text)
+}
+
+/// Load a reference map from text.
+///
+/// Each line maps a field to the PARAMs it references, like
+/// "EQUIP_PARAM_WEAPON_ST.behaviorVariationId = BehaviorParam_PC".
+/// Several targets can be separated by commas. Text after a "#" is
+/// ignored.
+pub fn load_reference_map(text: &str) -> Result<ReferenceMap, UnpackError> {
+    let mut map = ReferenceMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue
+        }
+        let line_error = || {
+            UnpackError::Parsing(format!("Invalid reference on line {}: \"{}\"", index + 1, line))
+        };
+        let (field_path, targets) = line.split_once('=').ok_or_else(line_error)?;
+        let (source, field) = field_path.trim().rsplit_once('.').ok_or_else(line_error)?;
+        let targets: Vec<String> = targets.split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if source.is_empty() || field.is_empty() || targets.is_empty() {
+            return Err(line_error())
+        }
+        map.add(source, field, targets);
+    }
+    Ok(map)
+}
+
+/// Value of a referencing field in a row.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Reference {
+    pub param: String,
+    pub id: u32,
+    pub field: String,
+    pub value: i64,
+    pub targets: Vec<String>,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {} -> {} ({})",
+            self.param, self.id, self.field, self.value, self.targets.join(", ")
+        )
+    }
+}
+
+/// Return the PARAMs of a bundle with this name or param type.
+fn resolve_target<'a>(bundle: &'a ParamBundle, target: &str) -> Vec<&'a Param> {
+    match bundle.get(target) {
+        Some(param) => vec![param],
+        None => bundle.params.values().filter(|p| p.header.param_type == target).collect(),
+    }
+}
+
+/// Call `f` for each positive reference of the bundle with its targets.
+fn for_each_reference<'a>(
+    bundle: &'a ParamBundle,
+    refs: &ReferenceMap,
+    mut f: impl FnMut(Reference, &[&'a Param]),
+) {
+    for (name, param) in &bundle.params {
+        for (field, targets) in refs.param_fields(name, param) {
+            let target_params: Vec<&Param> = targets.iter()
+                .flat_map(|target| resolve_target(bundle, target))
+                .collect();
+            for row in param.iter_rows() {
+                let value = match row.get(field).ok().and_then(|v| v.as_integer()) {
+                    Some(value) if value > 0 => value as i64,
+                    _ => continue,
+                };
+                let reference = Reference {
+                    param: name.clone(),
+                    id: row.id,
+                    field: field.to_string(),
+                    value,
+                    targets: targets.to_vec(),
+                };
+                f(reference, &target_params);
+            }
+        }
+    }
+}
+
+/// Return references to rows that do not exist in any of their targets.
+///
+/// Only PARAMs loaded with a PARAMDEF can be checked. IDs of 0 or less
+/// usually mean "none" and are ignored, as are references whose target
+/// PARAMs are not in the bundle.
+pub fn find_dangling_references(bundle: &ParamBundle, refs: &ReferenceMap) -> Vec<Reference> {
+    let mut dangling = vec!();
+    for_each_reference(bundle, refs, |reference, targets| {
+        let id = reference.value as u32;
+        if !targets.is_empty() && targets.iter().all(|target| target.row(id).is_err()) {
+            dangling.push(reference);
+        }
+    });
+    dangling
+}
+
+/// Return references to a row of a PARAM, given by name or param type.
+///
+/// If a field can reference several PARAMs, its value points to those
+/// that have this row, or to all of them if none has it.
+pub fn find_referencing_rows(
+    bundle: &ParamBundle,
+    refs: &ReferenceMap,
+    target: &str,
+    id: u32,
+) -> Vec<Reference> {
+    let target_params = resolve_target(bundle, target);
+    let mut referencing = vec!();
+    for_each_reference(bundle, refs, |reference, targets| {
+        if reference.value != id as i64 {
+            return
+        }
+        let mut pointed: Vec<&Param> = targets.iter()
+            .filter(|target| target.row(id).is_ok())
+            .cloned()
+            .collect();
+        if pointed.is_empty() {
+            pointed = targets.to_vec();
+        }
+        if pointed.iter().any(|p| target_params.iter().any(|t| std::ptr::eq(*p, *t))) {
+            referencing.push(reference);
+        }
+    });
+    referencing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::params::fixtures::build_s32_param;
+
+    fn build_bundle() -> ParamBundle {
+        let mut params = BTreeMap::new();
+        let weapon = build_s32_param("WEAPON_ST", &[
+            (1, &[10]), (2, &[20]), (3, &[-1]), (4, &[0]), (5, &[10]),
+        ]);
+        params.insert("Weapon".to_owned(), weapon.with_field_names(&["effectId"]));
+        params.insert(
+            "Effect".to_owned(),
+            build_s32_param("EFFECT_ST", &[(10, &[0])]).with_field_names(&["value"]),
+        );
+        params.insert(
+            "Effect2".to_owned(),
+            build_s32_param("EFFECT_ST", &[(30, &[0])]).with_field_names(&["value"]),
+        );
+        ParamBundle { params, missing_defs: vec!() }
+    }
+
+    #[test]
+    fn test_load_reference_map() {
+        let map = load_reference_map(
+            "# Comment\nWEAPON_ST.effectId = Effect, EFFECT_ST  # Both\n\nOther.a.b=X\n"
+        ).unwrap();
+        assert_eq!(map.targets("WEAPON_ST", "effectId").unwrap(), ["Effect", "EFFECT_ST"]);
+        assert_eq!(map.targets("Other.a", "b").unwrap(), ["X"]);
+        assert!(map.targets("WEAPON_ST", "other").is_none());
+        assert!(load_reference_map("WEAPON_ST.effectId").is_err());
+        assert!(load_reference_map("effectId = Effect").is_err());
+        assert!(load_reference_map("WEAPON_ST.effectId = ").is_err());
+    }
+
+    #[test]
+    fn test_find_references() {
+        let bundle = build_bundle();
+        let mut refs = ReferenceMap::new();
+        refs.add("WEAPON_ST", "effectId", vec!["Effect".to_owned()]);
+        let dangling = find_dangling_references(&bundle, &refs);
+        assert_eq!(dangling.len(), 1);
+        assert_eq!(format!("{}", dangling[0]), "Weapon [2] effectId -> 20 (Effect)");
+
+        // Targets by param type include all PARAMs of that type.
+        let mut refs = ReferenceMap::new();
+        refs.add("Weapon", "effectId", vec!["EFFECT_ST".to_owned()]);
+        assert_eq!(find_dangling_references(&bundle, &refs)[0].id, 2);
+        let referencing = find_referencing_rows(&bundle, &refs, "Effect", 10);
+        assert_eq!(referencing.iter().map(|r| r.id).collect::<Vec<u32>>(), vec![1, 5]);
+        assert!(find_referencing_rows(&bundle, &refs, "Effect2", 10).is_empty());
+        assert!(find_referencing_rows(&bundle, &refs, "Weapon", 10).is_empty());
+    }
+}
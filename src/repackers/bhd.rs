@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path;
+
+use crate::formats::bhd;
+use crate::formats::common::Pack;
+use crate::name_hashes;
+use crate::repackers::errors::PackError;
+use crate::utils::bin as utils_bin;
+use crate::utils::fs as utils_fs;
+
+/// Pack a directory as a BHD5/BDT pair.
+///
+/// Walks recursively in `files_path`; internal paths are the relative
+/// paths with a leading separator, e.g. "/chr/c0000.anibnd.dcx", as in
+/// namefiles. The BDT is written next to `output_path` with the "bdt"
+/// extension. If `num_buckets` is None, `get_num_buckets` is used.
+/// Files are streamed to the BDT, so archive size is not an issue.
+pub fn pack_bhd_dir(
+    files_path: &str,
+    output_path: &str,
+    num_buckets: Option<u32>,
+) -> Result<(), PackError> {
+    let mut files = vec!();
+    collect_files(path::Path::new(files_path), "/", &mut files)?;
+    files.sort();
+
+    let output_path = path::Path::new(output_path);
+    let mut bdt_file = fs::File::create(output_path.with_extension("bdt"))?;
+    bdt_file.write_all(bhd::BDT_HEADER)?;
+    let mut ofs = bhd::BDT_HEADER.len() as u64;
+    let mut entries = vec!();
+    for (internal_path, file_path) in &files {
+        let data = utils_fs::open_file_to_vec(file_path)?;
+        let entry = write_bdt_entry(&mut bdt_file, ofs, internal_path, &data)?;
+        ofs = entry.offset + entry.size as u64;
+        entries.push((internal_path.as_str(), entry));
+    }
+
+    let num_buckets = num_buckets.unwrap_or_else(|| get_num_buckets(entries.len()));
+    let bhd = build_bhd(entries, num_buckets)?;
+    let mut bhd_file = fs::File::create(output_path)?;
+    write_bhd(&bhd, &mut bhd_file)?;
+    Ok(())
+}
+
+/// Pack files, given as internal path and data, as a BHD5/BDT pair.
+///
+/// Returns the BHD and BDT data.
+pub fn pack_bhd(
+    files: &[(String, Vec<u8>)],
+    num_buckets: u32,
+) -> Result<(Vec<u8>, Vec<u8>), PackError> {
+    let mut bdt = bhd::BDT_HEADER.to_vec();
+    let mut entries = vec!();
+    for (internal_path, data) in files {
+        let ofs = bdt.len() as u64;
+        let entry = write_bdt_entry(&mut bdt, ofs, internal_path, data)?;
+        entries.push((internal_path.as_str(), entry));
+    }
+
+    let bhd = build_bhd(entries, num_buckets)?;
+    let mut bhd_data = Vec::with_capacity(bhd.header.file_len as usize);
+    write_bhd(&bhd, &mut bhd_data)?;
+    Ok((bhd_data, bdt))
+}
+
+/// Return a bucket count suited for this number of files.
+///
+/// It is the first prime number above a seventh of the file count,
+/// which gives buckets of a few files each.
+pub fn get_num_buckets(num_files: usize) -> u32 {
+    let is_prime = |n: u32| {
+        n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+    };
+    (num_files as u32 / 7..).find(|n| is_prime(*n)).unwrap()
+}
+
+/// Distribute entries in buckets and compute all BHD offsets.
+///
+/// Entries, given with their internal path, are placed in the bucket at
+/// index hash % num_buckets, which is where the game will look for
+/// them. Two paths with the same hash are an error, as the game could
+/// only find one of them.
+pub fn build_bhd(
+    files: Vec<(&str, bhd::BhdFile)>,
+    num_buckets: u32,
+) -> Result<bhd::Bhd, PackError> {
+    if num_buckets == 0 {
+        return Err(PackError::Data("BHD must have at least one bucket.".to_owned()))
+    }
+
+    let mut paths: HashMap<u64, &str> = HashMap::new();
+    let mut buckets: Vec<Vec<bhd::BhdFile>> = (0..num_buckets).map(|_| vec!()).collect();
+    for (internal_path, file) in files {
+        if let Some(other_path) = paths.insert(file.hash, internal_path) {
+            return Err(PackError::Data(format!(
+                "Hash collision between {} and {} (hash {:X}).",
+                other_path, internal_path, file.hash
+            )))
+        }
+        buckets[(file.hash % num_buckets as u64) as usize].push(file);
+    }
+
+    let ofs_buckets = bhd::HEADER_SIZE;
+    let mut ofs = ofs_buckets + buckets.len() * bhd::BUCKET_INFO_SIZE;
+    let mut bucket_infos = vec!();
+    for bucket in &buckets {
+        bucket_infos.push(bhd::BhdBucketInfo { count: bucket.len() as u32, offset: ofs as u32 });
+        ofs += bucket.len() * bhd::FILE_SIZE;
+    }
+
+    let header = bhd::BhdHeader {
+        magic: bhd::MAGIC.to_vec(),
+        unk04: -1,
+        unk05: 0,
+        unk06: 0,
+        unk07: 0,
+        unk08: 1,
+        file_len: ofs as u32,
+        num_buckets,
+        ofs_buckets: ofs_buckets as u32,
+        salt: None,
+    };
+    Ok(bhd::Bhd { format: bhd::BhdFormat::DarkSouls1, header, bucket_infos, buckets })
+}
+
+/// Write a BHD, with offsets as computed by `build_bhd`.
+pub fn write_bhd(bhd: &bhd::Bhd, f: &mut dyn io::Write) -> Result<usize, io::Error> {
+    let mut size = bhd.header.write(f)?;
+    for bucket_info in &bhd.bucket_infos {
+        size += bucket_info.write(f)?;
+    }
+    for bucket in &bhd.buckets {
+        for file in bucket {
+            size += file.write(f)?;
+        }
+    }
+    Ok(size)
+}
+
+/// Write padding then file data at BDT offset `ofs`, returning its entry.
+fn write_bdt_entry(
+    bdt: &mut dyn io::Write,
+    ofs: u64,
+    internal_path: &str,
+    data: &[u8],
+) -> Result<bhd::BhdFile, PackError> {
+    let size = u32::try_from(data.len()).map_err(|_| {
+        PackError::Data(format!("File {} is too large for a BDT entry.", internal_path))
+    })?;
+    let padding = utils_bin::pad(ofs as usize, bhd::DATA_ALIGN);
+    bdt.write_all(&vec![0u8; padding])?;
+    bdt.write_all(data)?;
+    Ok(bhd::BhdFile {
+        hash: name_hashes::hash(internal_path) as u64,
+        size,
+        offset: ofs + padding as u64,
+        ofs_sha_hash: 0,
+        ofs_aes_key: 0,
+        unpadded_size: 0,
+        sha_hash: None,
+        aes_key: None,
+    })
+}
+
+/// Recursively walks in `dir` to list files with their internal paths.
+///
+/// `prefix` is initially "/" and will contain current relative dir with
+/// separator suffixed during walks, e.g. "/chr/".
+fn collect_files(
+    dir: &path::Path,
+    prefix: &str,
+    files: &mut Vec<(String, path::PathBuf)>,
+) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?.path();
+        if let Some(name) = entry.file_name().and_then(|n| n.to_str()) {
+            let mut internal_path = String::from(prefix);
+            internal_path.push_str(name);
+            if entry.is_dir() {
+                internal_path.push('/');
+                collect_files(&entry, &internal_path, files)?;
+            } else if entry.is_file() {
+                files.push((internal_path, entry));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nom::multi::count;
+
+    #[test]
+    fn test_get_num_buckets() {
+        assert_eq!(get_num_buckets(0), 2);
+        assert_eq!(get_num_buckets(10), 2);
+        assert_eq!(get_num_buckets(21), 3);
+        assert_eq!(get_num_buckets(100), 17);
+    }
+
+    #[test]
+    fn test_pack_bhd() {
+        let files = vec![
+            ("/chr/c0000.anibnd.dcx".to_owned(), b"ABC".to_vec()),
+            ("/chr/c0000.chrbnd.dcx".to_owned(), b"DEFG".to_vec()),
+            ("/chr/c0000.esd.dcx".to_owned(), b"".to_vec()),
+        ];
+        let (bhd_data, bdt_data) = pack_bhd(&files, 3).unwrap();
+        let (_, bhd) = bhd::parse(&bhd_data).unwrap();
+        assert_eq!(bhd.header.file_len as usize, bhd_data.len());
+        assert_eq!(bhd.buckets.iter().map(|b| b.len()).sum::<usize>(), 3);
+        for (index, bucket) in bhd.buckets.iter().enumerate() {
+            for file in bucket {
+                assert_eq!(file.hash % 3, index as u64);
+            }
+        }
+        // Buckets are written right after bucket infos.
+        let ofs_files = bhd.bucket_infos[0].offset as usize;
+        assert_eq!(ofs_files, bhd::HEADER_SIZE + 3 * bhd::BUCKET_INFO_SIZE);
+        let (_, all_files) = count(bhd::parse_file, 3)(&bhd_data[ofs_files..]).unwrap();
+
+        let hash = name_hashes::hash("/chr/c0000.chrbnd.dcx") as u64;
+        let file = all_files.iter().find(|f| f.hash == hash).unwrap();
+        assert_eq!(file.offset, 0x20);
+        assert_eq!(&bdt_data[0x20..0x24], b"DEFG");
+
+        assert!(pack_bhd(&files, 0).is_err());
+
+        let mut files = files;
+        files.push(("/CHR/C0000.ESD.DCX".to_owned(), b"".to_vec()));
+        match pack_bhd(&files, 3) {
+            Err(PackError::Data(message)) => assert_eq!(
+                message,
+                format!(
+                    "Hash collision between /chr/c0000.esd.dcx and /CHR/C0000.ESD.DCX (hash {:X}).",
+                    name_hashes::hash("/chr/c0000.esd.dcx")
+                )
+            ),
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::io::Write;
+use std::path;
+
+use crate::formats::bhf;
+use crate::formats::bnd::{self, BinderOptions};
+use crate::formats::common::{Pack, string_to_sjis};
+use crate::repackers::errors::PackError;
+use crate::unpackers::bhf::{get_bdt_for_bhf, get_entry_file_name, load_bhf_file};
+use crate::utils::bin as utils_bin;
+use crate::utils::fs as utils_fs;
+
+/// Repack a previously unpacked BHF and its BDT with files from `files_path`.
+///
+/// The original BHF is used as a template for entry order, IDs, flags,
+/// internal paths and data alignment. Entry files are looked up in
+/// `files_path` with the names used by `unpackers::bhf::extract_bhf`;
+/// missing files keep their original data from the original BDT. The
+/// new BDT is written next to `output_path`.
+pub fn pack_bhf_file(bhf_path: &str, files_path: &str, output_path: &str) -> Result<(), PackError> {
+    let mut bhf = load_bhf_file(bhf_path)?;
+    let bdt_path = get_bdt_for_bhf(bhf_path)
+        .ok_or_else(|| PackError::Data(format!("Can't find BDT for BHF: {}", bhf_path)))?;
+    let output_bdt_path = get_bdt_for_bhf(output_path)
+        .ok_or_else(|| PackError::Data(format!("Can't name BDT for BHF: {}", output_path)))?;
+    let bdt_data = utils_fs::open_file_to_vec(&bdt_path)?;
+
+    let files_path = path::Path::new(files_path);
+    let mut files_data = vec!();
+    for file_info in &bhf.file_infos {
+        let file_path = file_info.path.as_ref()
+            .map(|p| files_path.join(get_entry_file_name(p)))
+            .filter(|p| p.is_file());
+        let data = match file_path {
+            Some(p) => utils_fs::open_file_to_vec(&p)?,
+            None => {
+                let ofs_start = file_info.ofs_data as usize;
+                let ofs_end = ofs_start + file_info.size as usize;
+                bdt_data.get(ofs_start..ofs_end)
+                    .ok_or_else(|| PackError::Data(format!(
+                        "Entry data at {:X} is out of the original BDT.", ofs_start
+                    )))?
+                    .to_vec()
+            }
+        };
+        files_data.push(data);
+    }
+
+    let alignment = bhf.data_alignment();
+    let (output_bhf_data, output_bdt_data) = pack_bhf(&mut bhf, &files_data, alignment)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&output_bhf_data)?;
+    let mut output_bdt_file = fs::File::create(output_bdt_path)?;
+    output_bdt_file.write_all(&output_bdt_data)?;
+    Ok(())
+}
+
+/// Pack the files of `files_path` in a new DS1 BHF3 and its BDT.
+///
+/// Files are sorted by name and use it as internal path, with IDs from
+/// 0; subdirectories are ignored. The BDT is written next to
+/// `output_path`.
+pub fn pack_bhf_dir(files_path: &str, output_path: &str) -> Result<(), PackError> {
+    let output_bdt_path = get_bdt_for_bhf(output_path)
+        .ok_or_else(|| PackError::Data(format!("Can't name BDT for BHF: {}", output_path)))?;
+    let mut file_paths = vec!();
+    for entry in fs::read_dir(files_path)? {
+        let entry_path = entry?.path();
+        if entry_path.is_file() {
+            file_paths.push(entry_path);
+        }
+    }
+    file_paths.sort();
+
+    let mut paths = vec!();
+    let mut files_data = vec!();
+    for file_path in &file_paths {
+        let name = file_path.file_name().and_then(|n| n.to_str())
+            .ok_or_else(|| PackError::Data(format!("Invalid file name: {:?}", file_path)))?;
+        paths.push(name.to_string());
+        files_data.push(utils_fs::open_file_to_vec(file_path)?);
+    }
+
+    let mut bhf = bhf::Bhf::new(&paths);
+    let (output_bhf_data, output_bdt_data) = pack_bhf(&mut bhf, &files_data, bnd::DATA_ALIGN)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&output_bhf_data)?;
+    let mut output_bdt_file = fs::File::create(output_bdt_path)?;
+    output_bdt_file.write_all(&output_bdt_data)?;
+    Ok(())
+}
+
+/// Pack a BHF and its BDT with this data for each entry, in the same order.
+///
+/// Header and entries metadata are kept, but file count, offsets, sizes
+/// and path table are rebuilt and updated in `bhf`. Each file data is
+/// aligned to `alignment` in the BDT, see `Bhf::data_alignment`.
+/// Entries without a path are refused if paths are used. Returns the
+/// BHF and BDT data.
+pub fn pack_bhf(
+    bhf: &mut bhf::Bhf,
+    files_data: &[Vec<u8>],
+    alignment: usize,
+) -> Result<(Vec<u8>, Vec<u8>), PackError> {
+    if files_data.len() != bhf.file_infos.len() {
+        return Err(PackError::Data(format!(
+            "BHF has {} entries but {} files were provided.",
+            bhf.file_infos.len(),
+            files_data.len()
+        )))
+    }
+
+    // BDT header only repeats the BHF version.
+    let mut bdt = Vec::with_capacity(bhf::BDT_HEADER_SIZE);
+    bdt.extend_from_slice(bhf::BDT_MAGIC);
+    bdt.extend_from_slice(&bhf.header.version);
+    bdt.extend_from_slice(&[0u8; 4]);
+
+    for (file_info, file_data) in bhf.file_infos.iter_mut().zip(files_data.iter()) {
+        let padding = utils_bin::pad(bdt.len(), alignment);
+        bdt.append(&mut vec![0u8; padding]);
+        file_info.ofs_data = bdt.len() as u32;
+        file_info.size = file_data.len() as u32;
+        if file_info.has_uncomp_size() {
+            file_info.uncompressed_size = file_data.len() as u32;
+        }
+        bdt.extend_from_slice(file_data);
+    }
+
+    // Path table is right after the entries.
+    let header = &mut bhf.header;
+    header.num_files = bhf.file_infos.len() as u32;
+    let mut ofs = bhf::HEADER_SIZE + bhf.file_infos.len() * header.file_info_size();
+    let mut paths_data = vec!();
+    if header.has_paths() {
+        for (index, file_info) in bhf.file_infos.iter_mut().enumerate() {
+            let path = file_info.path.as_deref()
+                .ok_or_else(|| PackError::Data(format!("Entry {} has no valid path.", index)))?;
+            let mut sjis_path = string_to_sjis(path).ok_or_else(|| PackError::encoding_err(path))?;
+            sjis_path.push(b'\0');
+            file_info.ofs_path = ofs as u32;
+            ofs += sjis_path.len();
+            paths_data.append(&mut sjis_path);
+        }
+    }
+
+    let mut bhf_data = Vec::with_capacity(ofs);
+    bhf.header.write(&mut bhf_data)?;
+    for file_info in &bhf.file_infos {
+        file_info.write(&mut bhf_data)?;
+    }
+    bhf_data.append(&mut paths_data);
+    Ok((bhf_data, bdt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::unpackers::bhf::load_bhf;
+
+    #[test]
+    fn test_pack_bhf() {
+        // BHF3 with IDs, names and uncompressed size, little endian.
+        let mut original_bhf = vec!();
+        original_bhf.extend_from_slice(b"BHF307D7R6\0\0\x74\x00\x00\x00");
+        original_bhf.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        original_bhf.extend_from_slice(&[0x40, 0, 0, 0, 3, 0, 0, 0, 0x10, 0, 0, 0]);
+        original_bhf.extend_from_slice(&[10, 0, 0, 0, 0x50, 0, 0, 0, 3, 0, 0, 0]);
+        original_bhf.extend_from_slice(&[0x40, 0, 0, 0, 2, 0, 0, 0, 0x20, 0, 0, 0]);
+        original_bhf.extend_from_slice(&[20, 0, 0, 0, 0x56, 0, 0, 0, 2, 0, 0, 0]);
+        original_bhf.extend_from_slice(b"a.tpf\0b.tpf\0");
+        let mut original_bdt = vec!();
+        original_bdt.extend_from_slice(b"BDF307D7R6\0\0\0\0\0\0");
+        original_bdt.extend_from_slice(b"ABC\0\0\0\0\0\0\0\0\0\0\0\0\0DE");
+
+        let mut bhf = load_bhf(&original_bhf).unwrap();
+        assert_eq!(bhf.data_alignment(), 0x10);
+        let files_data = vec![b"ABC".to_vec(), b"DE".to_vec()];
+        let (bhf_data, bdt_data) = pack_bhf(&mut bhf, &files_data, 0x10).unwrap();
+        assert_eq!(bhf_data, original_bhf);
+        assert_eq!(bdt_data, original_bdt);
+
+        // Changing file sizes must shift following offsets.
+        let files_data = vec![vec![0xFFu8; 0x11], b"DE".to_vec()];
+        let (bhf_data, bdt_data) = pack_bhf(&mut bhf, &files_data, 0x10).unwrap();
+        let repacked = load_bhf(&bhf_data).unwrap();
+        assert_eq!(repacked.file_infos[0].size, 0x11);
+        assert_eq!(repacked.file_infos[1].ofs_data, 0x30);
+        assert_eq!(&bdt_data[0x30..], b"DE");
+
+        bhf.file_infos[0].path = Some("\u{1F600}.tpf".to_owned());
+        assert!(matches!(pack_bhf(&mut bhf, &files_data, 0x10), Err(PackError::Data(_))));
+        bhf.file_infos[0].path = None;
+        assert!(matches!(pack_bhf(&mut bhf, &files_data, 0x10), Err(PackError::Data(_))));
+    }
+
+    #[test]
+    fn test_pack_bhf_dir() {
+        let files_dir = std::env::temp_dir().join(format!("rir-bhf-dir-{}", std::process::id()));
+        fs::create_dir_all(files_dir.join("subdir")).unwrap();
+        fs::write(files_dir.join("b.tpf"), b"DE").unwrap();
+        fs::write(files_dir.join("a.tpf"), b"ABC").unwrap();
+        let output_path = files_dir.join("out.tpfbhd");
+        let output = output_path.to_str().unwrap();
+        let result = pack_bhf_dir(files_dir.to_str().unwrap(), output);
+        let bhf_data = fs::read(&output_path);
+        let bdt_data = fs::read(files_dir.join("out.tpfbdt"));
+        fs::remove_dir_all(&files_dir).unwrap();
+        result.unwrap();
+
+        let bhf = load_bhf(&bhf_data.unwrap()).unwrap();
+        let paths: Vec<_> = bhf.file_infos.iter().map(|i| i.path.as_deref().unwrap()).collect();
+        assert_eq!(paths, vec!["a.tpf", "b.tpf"]);
+        assert_eq!(bhf.file_infos[1].id, 1);
+        assert_eq!(bhf.file_infos[1].uncompressed_size, 2);
+        let bdt_data = bdt_data.unwrap();
+        assert_eq!(&bdt_data[..0xC], b"BDF307D7R6\0\0");
+        assert_eq!(&bdt_data[0x10..0x13], b"ABC");
+        assert_eq!(&bdt_data[0x20..], b"DE");
+    }
+}
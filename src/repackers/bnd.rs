@@ -0,0 +1,143 @@
+use std::fs;
+use std::io::Write;
+use std::path;
+
+use crate::formats::bnd::{self, BinderOptions};
+use crate::formats::common::{Pack, string_to_sjis};
+use crate::repackers::errors::PackError;
+use crate::unpackers::bnd::{get_entry_file_name, load_bnd_file};
+use crate::utils::bin as utils_bin;
+use crate::utils::fs as utils_fs;
+
+/// Repack a previously unpacked BND with files from `files_path`.
+///
+/// The original BND is used as a template for entry order, IDs, flags
+/// and internal paths. Entry files are looked up in `files_path` with
+/// the names used by `unpackers::bnd::extract_bnd`; missing files keep
+/// their original data.
+pub fn pack_bnd_file(bnd_path: &str, files_path: &str, output_path: &str) -> Result<(), PackError> {
+    let (mut bnd, bnd_data) = load_bnd_file(bnd_path)?;
+    let files_path = path::Path::new(files_path);
+    let mut files_data = vec!();
+    for file_info in &bnd.file_infos {
+        let file_path = file_info.path.as_ref()
+            .map(|p| files_path.join(get_entry_file_name(p)))
+            .filter(|p| p.is_file());
+        let data = match file_path {
+            Some(p) => utils_fs::open_file_to_vec(&p)?,
+            None => {
+                let ofs_start = file_info.ofs_data as usize;
+                let ofs_end = ofs_start + file_info.size as usize;
+                bnd_data.get(ofs_start..ofs_end)
+                    .ok_or_else(|| PackError::Data(format!(
+                        "Entry data at {:X} is out of the original BND.", ofs_start
+                    )))?
+                    .to_vec()
+            }
+        };
+        files_data.push(data);
+    }
+
+    let output_data = pack_bnd(&mut bnd, &files_data)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&output_data)?;
+    Ok(())
+}
+
+/// Pack a BND with this data for each entry, in the same order.
+///
+/// Header and entries metadata are kept, but file count, offsets, sizes
+/// and path table are rebuilt and updated in `bnd`. Entries without a
+/// path, e.g. that failed to decode, are refused if paths are used.
+pub fn pack_bnd(bnd: &mut bnd::Bnd, files_data: &[Vec<u8>]) -> Result<Vec<u8>, PackError> {
+    if files_data.len() != bnd.file_infos.len() {
+        return Err(PackError::Data(format!(
+            "BND has {} entries but {} files were provided.",
+            bnd.file_infos.len(),
+            files_data.len()
+        )))
+    }
+
+    // Compute path table layout, right after the entries.
+    let header = &mut bnd.header;
+    header.num_files = bnd.file_infos.len() as u32;
+    let mut ofs = bnd::HEADER_SIZE + bnd.file_infos.len() * header.file_info_size();
+    let mut paths_data = vec!();
+    if header.has_paths() {
+        for (index, file_info) in bnd.file_infos.iter_mut().enumerate() {
+            let path = file_info.path.as_deref()
+                .ok_or_else(|| PackError::Data(format!("Entry {} has no valid path.", index)))?;
+            let mut sjis_path = string_to_sjis(path).ok_or_else(|| PackError::encoding_err(path))?;
+            sjis_path.push(b'\0');
+            file_info.ofs_path = ofs as u32;
+            ofs += sjis_path.len();
+            paths_data.append(&mut sjis_path);
+        }
+    }
+    header.ofs_data = ofs as u32;
+
+    // Compute data layout; each non-empty file is aligned.
+    let mut data = vec!();
+    for (file_info, file_data) in bnd.file_infos.iter_mut().zip(files_data.iter()) {
+        if !file_data.is_empty() {
+            let padding = utils_bin::pad(ofs + data.len(), bnd::DATA_ALIGN);
+            data.append(&mut vec![0u8; padding]);
+        }
+        file_info.ofs_data = (ofs + data.len()) as u32;
+        file_info.size = file_data.len() as u32;
+        if file_info.has_uncomp_size() {
+            file_info.uncompressed_size = file_data.len() as u32;
+        }
+        data.extend_from_slice(file_data);
+    }
+
+    let mut output = Vec::with_capacity(ofs + data.len());
+    bnd.header.write(&mut output)?;
+    for file_info in &bnd.file_infos {
+        file_info.write(&mut output)?;
+    }
+    output.append(&mut paths_data);
+    output.append(&mut data);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::unpackers::bnd::load_bnd;
+
+    #[test]
+    fn test_pack_bnd() {
+        // BND3 with IDs, names and uncompressed size, little endian.
+        let mut original = vec!();
+        original.extend_from_slice(b"BND307D7R6\0\0\x74\x00\x00\x00");
+        original.extend_from_slice(&[2, 0, 0, 0, 0x5E, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        original.extend_from_slice(&[0x40, 0, 0, 0, 3, 0, 0, 0, 0x60, 0, 0, 0]);
+        original.extend_from_slice(&[10, 0, 0, 0, 0x50, 0, 0, 0, 3, 0, 0, 0]);
+        original.extend_from_slice(&[0x40, 0, 0, 0, 2, 0, 0, 0, 0x70, 0, 0, 0]);
+        original.extend_from_slice(&[20, 0, 0, 0, 0x57, 0, 0, 0, 2, 0, 0, 0]);
+        original.extend_from_slice(b"N:\\a.x\0N:\\b.y\0\0\0");
+        original.extend_from_slice(b"ABC\0\0\0\0\0\0\0\0\0\0\0\0\0DE");
+
+        let mut bnd = load_bnd(&original).unwrap();
+        assert_eq!(bnd.file_infos[0].path.as_deref(), Some("N:\\a.x"));
+        let files_data = vec![b"ABC".to_vec(), b"DE".to_vec()];
+        assert_eq!(pack_bnd(&mut bnd, &files_data).unwrap(), original);
+
+        // Changing file sizes must shift following offsets.
+        let files_data = vec![vec![0xFFu8; 0x11], b"DE".to_vec()];
+        let packed = pack_bnd(&mut bnd, &files_data).unwrap();
+        let repacked = load_bnd(&packed).unwrap();
+        assert_eq!(repacked.file_infos[0].size, 0x11);
+        assert_eq!(repacked.file_infos[1].ofs_data, 0x80);
+        assert_eq!(repacked.file_infos[1].uncompressed_size, 2);
+        assert_eq!(&packed[0x80..], b"DE");
+
+        assert!(pack_bnd(&mut bnd, &files_data[..1]).is_err());
+        bnd.file_infos[1].path = Some("N:\\\u{1F600}.y".to_owned());
+        assert!(matches!(pack_bnd(&mut bnd, &files_data), Err(PackError::Data(_))));
+        bnd.file_infos[1].path = None;
+        assert!(matches!(pack_bnd(&mut bnd, &files_data), Err(PackError::Data(_))));
+    }
+}
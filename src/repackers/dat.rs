@@ -24,7 +24,7 @@ pub fn pack_dat(files_path: &str, output_path: &str) -> Result<(), io::Error> {
     // Write header.
     let header = dat::DatHeader { unk00: dat::MAGIC, num_files: entries.len() as u32 };
     header.write(&mut output_file)?;
-    output_file.write_all(&vec![0u8; dat::HEADER_PAD])?;
+    output_file.write_all(&[0u8; dat::HEADER_PAD])?;
     ofs += dat::HEADER_SIZE;
 
     // Write entries, but shift their data offset beforehand.
@@ -77,7 +77,7 @@ fn pack_dat_dir(
 
 /// Pack the file in `files_data` and update `entries` accordingly.
 fn pack_dat_entry(
-    file_entry: &path::PathBuf,
+    file_entry: &path::Path,
     internal_name: String,
     metadata: &fs::Metadata,
     entries: &mut Vec<dat::DatFileEntry>,
@@ -1,36 +1,120 @@
+use std::convert::TryFrom;
 use std::fs;
 use std::io::Write;
+use std::path;
 
 use flate2::Compression;
-use flate2::write::ZlibEncoder;
+use flate2::write::{DeflateEncoder, ZlibEncoder};
 
 use crate::formats::common::Pack;
 use crate::formats::dcx;
 use crate::repackers::errors::PackError;
+use crate::unpackers::dcx::load_dcx;
+use crate::utils::bin as utils_bin;
+use crate::utils::fs as utils_fs;
+
+/// Repack a previously unpacked DCX with the content of `data_path`.
+///
+/// The original DCX is used as a template for its compression params.
+pub fn pack_dcx_file(dcx_path: &str, data_path: &str, output_path: &str) -> Result<(), PackError> {
+    let (mut dcx, _) = load_dcx(dcx_path)?;
+    let data = utils_fs::open_file_to_vec(path::Path::new(data_path))?;
+    let dcx_data = pack_dcx(&mut dcx, &data)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&dcx_data)?;
+    Ok(())
+}
 
 /// Repack a previously unpacked DCX with this new data.
 ///
 /// Params that are not well understood are reused, others are replaced
-/// with accurate values.
-pub fn pack_dcx(dcx: &mut dcx::Dcx, data: &[u8], output_path: &str) -> Result<(), PackError> {
-    dcx.sizes.uncompressed_size = data.len() as u32;
+/// with accurate values. Chunks are written contiguously, so chunk
+/// offsets are recomputed and updated in `dcx`. The DCX is written
+/// using the same variant it was loaded with. Returns the DCX data.
+pub fn pack_dcx(dcx: &mut dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
+    if let Some((unk04, unk10, level)) = dcx.variant.dflt_values() {
+        if let Some(header) = dcx.header.as_mut() {
+            header.unk04 = unk04;
+            header.unk10 = unk10;
+        }
+        dcx.params.unk0C = level;
+    }
+    let original_ofs_data = get_data_offset(dcx);
     let compressed = compress(dcx, data)?;
+    dcx.sizes.uncompressed_size = data.len() as u32;
     dcx.sizes.compressed_size = compressed.len() as u32;
+    dcx.params.ofs_dca = dcx::PARAMS_CHUNK_SIZE as u32;
+    let table_size = dcx.edge_table.as_ref().map(|t| t.table_size as usize).unwrap_or(0);
+    dcx.archive.ofs_data = (dcx::ARCHIVE_CHUNK_SIZE + table_size) as u32;
 
-    let mut output_file = fs::File::create(output_path)?;
-    dcx.header.write(&mut output_file)?;
-    dcx.sizes.write(&mut output_file)?;
-    dcx.params.write(&mut output_file)?;
-    dcx.archive.write(&mut output_file)?;
-    output_file.write_all(&compressed)?;
-    Ok(())
+    let header = match dcx.header.as_mut() {
+        Some(h) => h,
+        None => return pack_dcp(dcx, &compressed),
+    };
+    header.ofs_dcs = dcx::HEADER_SIZE as u32;
+    header.ofs_dcp = (dcx::HEADER_SIZE + dcx::SIZES_CHUNK_SIZE) as u32;
+    let ofs_data = header.ofs_dcp as usize
+        + dcx.params.ofs_dca as usize
+        + dcx.archive.ofs_data as usize;
+    // Unknown but follows the data offset, e.g. when the EDGE table grows.
+    let unk14 = header.unk14 as i64 + ofs_data as i64 - original_ofs_data as i64;
+    header.unk14 = u32::try_from(unk14).map_err(|_| {
+        PackError::Data(format!("Can't move DCX unk14 value {} to {}.", header.unk14, unk14))
+    })?;
+
+    let mut output = Vec::with_capacity(ofs_data + compressed.len());
+    header.write(&mut output)?;
+    dcx.sizes.write(&mut output)?;
+    dcx.params.write(&mut output)?;
+    dcx.archive.write(&mut output)?;
+    if let Some(edge_table) = &dcx.edge_table {
+        edge_table.write(&mut output)?;
+    }
+    output.extend_from_slice(&compressed);
+    Ok(output)
+}
+
+/// Write a DCP file, which has no header and its archive chunk last.
+///
+/// DCP_EDGE has 4 null bytes after the sizes and ends with its table.
+fn pack_dcp(dcx: &dcx::Dcx, compressed: &[u8]) -> Result<Vec<u8>, PackError> {
+    let headers_size = dcx::PARAMS_CHUNK_SIZE + dcx::SIZES_CHUNK_SIZE + dcx::ARCHIVE_CHUNK_SIZE;
+    let mut output = Vec::with_capacity(headers_size + compressed.len());
+    dcx.params.write(&mut output)?;
+    dcx.sizes.write(&mut output)?;
+    if dcx.edge_table.is_some() {
+        output.extend_from_slice(&[0u8; 4]);
+    }
+    output.extend_from_slice(compressed);
+    dcx.archive.write(&mut output)?;
+    if let Some(edge_table) = &dcx.edge_table {
+        edge_table.write_dcp(&mut output)?;
+    }
+    Ok(output)
+}
+
+/// Return the offset of the compressed data using DCX offsets.
+fn get_data_offset(dcx: &dcx::Dcx) -> usize {
+    match &dcx.header {
+        Some(header) => {
+            header.ofs_dcp as usize + dcx.params.ofs_dca as usize + dcx.archive.ofs_data as usize
+        }
+        None if dcx.edge_table.is_some() => dcx::PARAMS_CHUNK_SIZE + dcx::SIZES_CHUNK_SIZE + 4,
+        None => dcx::PARAMS_CHUNK_SIZE + dcx::SIZES_CHUNK_SIZE,
+    }
 }
 
 /// Compress data using DCX params.
-pub fn compress(dcx: &dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
+///
+/// For the EDGE method, the chunk table is rebuilt and updated in `dcx`.
+pub fn compress(dcx: &mut dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
     let method: &[u8] = dcx.params.method.as_slice();
     if method == b"DFLT" {
         compress_dflt(dcx, data)
+    } else if method == b"EDGE" {
+        compress_edge(dcx, data)
+    } else if method == b"ZSTD" {
+        compress_zstd(dcx, data)
     } else {
         let method_string = String::from_utf8_lossy(method).to_string();
         Err(PackError::Compression(format!("Method unknown: {}", method_string)))
@@ -40,6 +124,222 @@ pub fn compress(dcx: &dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
 fn compress_dflt(dcx: &dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
     let level = dcx.params.unk0C as u32;  // Unsure if it really is compression level.
     let half_size = data.len() / 2;  // Quicker allocation.
-    let encoder = ZlibEncoder::new(Vec::with_capacity(half_size), Compression::new(level));
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(half_size), Compression::new(level));
+    encoder.write_all(data)?;
     Ok(encoder.finish()?)
 }
+
+fn compress_edge(dcx: &mut dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
+    let level = dcx.params.unk0C as u32;
+    let is_dcp = dcx.variant == dcx::DcxVariant::DCP_EDGE;
+    let table_header_size =
+        if is_dcp { dcx::DCP_EDGE_TABLE_HEADER_SIZE } else { dcx::EDGE_TABLE_HEADER_SIZE };
+    let edge_table = dcx.edge_table.as_mut()
+        .ok_or_else(|| PackError::Compression("Missing EDGE chunk table.".to_owned()))?;
+    let chunk_size = match edge_table.chunk_size as usize {
+        0 => dcx::EDGE_CHUNK_SIZE,
+        s => s,
+    };
+
+    let mut compressed = vec!();
+    edge_table.chunk_infos.clear();
+    for chunk in data.chunks(chunk_size) {
+        let mut encoder = DeflateEncoder::new(vec!(), Compression::new(level));
+        encoder.write_all(chunk)?;
+        let chunk_data = encoder.finish()?;
+        edge_table.chunk_infos.push(dcx::DcxEdgeChunkInfo {
+            unk00: 0,
+            offset: compressed.len() as u32,
+            size: chunk_data.len() as u32,
+            compressed: 1,
+        });
+        compressed.extend_from_slice(&chunk_data);
+        let padding = utils_bin::pad(compressed.len(), dcx::EDGE_DATA_ALIGN);
+        compressed.append(&mut vec![0u8; padding]);
+    }
+
+    let num_chunks = edge_table.chunk_infos.len();
+    edge_table.chunk_size = chunk_size as u32;
+    edge_table.last_chunk_size = if is_dcp { 0 } else { (data.len() % chunk_size) as u32 };
+    edge_table.num_chunks = num_chunks as u32;
+    edge_table.ofs_chunk_infos = table_header_size as u32;
+    edge_table.chunk_info_size = dcx::EDGE_CHUNK_INFO_SIZE as u32;
+    edge_table.table_size = (table_header_size + num_chunks * dcx::EDGE_CHUNK_INFO_SIZE) as u32;
+    Ok(compressed)
+}
+
+/// Compress data with ZSTD, setting the DCP flags the games expect.
+fn compress_zstd(dcx: &mut dcx::Dcx, data: &[u8]) -> Result<Vec<u8>, PackError> {
+    dcx.params.unk0C = dcx::ZSTD_LEVEL;
+    dcx.params.unk1C = dcx::ZSTD_UNK1C;
+    Ok(zstd::stream::encode_all(data, dcx::ZSTD_LEVEL as i32)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::unpackers::dcx::load_dcx_data;
+
+    /// Build a DS1 DCX (DFLT, level 9) with this content.
+    fn build_ds1_dcx(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec!(), Compression::new(9));
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut dcx_data = vec!();
+        dcx_data.extend_from_slice(b"DCX\0\0\x01\0\0\0\0\0\x18\0\0\0\x24\0\0\0\x24\0\0\0\x2C");
+        dcx_data.extend_from_slice(b"DCS\0");
+        dcx_data.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        dcx_data.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        dcx_data.extend_from_slice(b"DCP\0DFLT\0\0\0\x20\x09\0\0\0");
+        dcx_data.extend_from_slice(b"\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\x01\0");
+        dcx_data.extend_from_slice(b"DCA\0\0\0\0\x08");
+        dcx_data.extend_from_slice(&compressed);
+        dcx_data
+    }
+
+    /// Build a PS3 DCX (EDGE) with this content, packing it from a template.
+    fn build_edge_dcx(data: &[u8]) -> Vec<u8> {
+        let mut dcx_data = vec!();
+        dcx_data.extend_from_slice(b"DCX\0\0\x01\0\0\0\0\0\x18\0\0\0\x24\0\0\0\x24\0\0\0\x50");
+        dcx_data.extend_from_slice(b"DCS\0\0\0\0\0\0\0\0\0");
+        dcx_data.extend_from_slice(b"DCP\0EDGE\0\0\0\x20\x09\0\0\0");
+        dcx_data.extend_from_slice(b"\0\x01\0\0\0\0\0\0\0\0\0\0\0\x10\x01\0");
+        dcx_data.extend_from_slice(b"DCA\0\0\0\0\x2C");
+        dcx_data.extend_from_slice(b"EgdT\0\x01\x01\0\0\0\0\x24\0\0\0\x10\0\x01\0\0");
+        dcx_data.extend_from_slice(b"\0\0\0\0\0\0\0\x24\0\0\0\0\0\x10\0\0");
+        let (mut dcx, _) = load_dcx_data(&dcx_data).unwrap();
+        pack_dcx(&mut dcx, data).unwrap()
+    }
+
+    #[test]
+    fn test_pack_dcx_edge() {
+        let content: Vec<u8> = (0..0x20000u32).map(|i| (i % 251) as u8).collect();
+        let packed = build_edge_dcx(&content);
+        let (mut dcx, data) = load_dcx_data(&packed).unwrap();
+        assert_eq!(data, content);
+        assert_eq!(dcx.header.as_ref().unwrap().unk14, 0x70);
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCX_EDGE);
+        assert_eq!(dcx.archive.ofs_data, 0x4C);
+        let edge_table = dcx.edge_table.as_ref().unwrap();
+        assert_eq!(edge_table.num_chunks, 2);
+        assert_eq!(edge_table.last_chunk_size, 0);
+        assert_eq!(edge_table.table_size, 0x44);
+        assert_eq!(edge_table.chunk_infos[1].offset % 0x10, 0);
+
+        let repacked = pack_dcx(&mut dcx, &data).unwrap();
+        assert_eq!(repacked, packed);
+    }
+
+    #[test]
+    fn test_pack_dcx_zstd() {
+        let mut dcx_data = vec!();
+        dcx_data.extend_from_slice(b"DCX\0\0\x01\x10\0\0\0\0\x18\0\0\0\x24\0\0\0\x44\0\0\0\x4C");
+        let empty = zstd::stream::encode_all(&b""[..], 1).unwrap();
+        dcx_data.extend_from_slice(b"DCS\0\0\0\0\0");
+        dcx_data.extend_from_slice(&(empty.len() as u32).to_be_bytes());
+        dcx_data.extend_from_slice(b"DCP\0ZSTD\0\0\0\x20\0\0\0\0");
+        dcx_data.extend_from_slice(&[0u8; 16]);
+        dcx_data.extend_from_slice(b"DCA\0\0\0\0\x08");
+        dcx_data.extend_from_slice(&empty);
+        let (mut dcx, _) = load_dcx_data(&dcx_data).unwrap();
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCX_ZSTD);
+
+        let content = b"Zstandard content, zstandard content.".repeat(8);
+        let packed = pack_dcx(&mut dcx, &content).unwrap();
+        assert_eq!(&packed[..0x18], &dcx_data[..0x18]);
+        assert_eq!(&packed[0x30..0x34], &[0x15, 0, 0, 0]);
+        assert_eq!(&packed[0x40..0x44], &[0, 1, 1, 0]);
+        let (_, data) = load_dcx_data(&packed).unwrap();
+        assert_eq!(data, content);
+    }
+
+    #[test]
+    fn test_pack_dcx() {
+        let content = b"Some content that should compress a bit, a bit, a bit.".repeat(16);
+        let original = build_ds1_dcx(&content);
+        let (mut dcx, data) = load_dcx_data(&original).unwrap();
+        assert_eq!(data, content);
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCX_DFLT_10000_24_9);
+
+        let repacked = pack_dcx(&mut dcx, &data).unwrap();
+        assert_eq!(repacked, original);
+        let (_, redata) = load_dcx_data(&repacked).unwrap();
+        assert_eq!(redata, content);
+
+        // Different data must update sizes but keep other headers.
+        let repacked = pack_dcx(&mut dcx, b"ABC").unwrap();
+        assert_eq!(&repacked[..0x1C], &original[..0x1C]);
+        assert_eq!(&repacked[0x24..0x4C], &original[0x24..0x4C]);
+        let (dcx, redata) = load_dcx_data(&repacked).unwrap();
+        assert_eq!(dcx.sizes.uncompressed_size, 3);
+        assert_eq!(dcx.sizes.compressed_size as usize, repacked.len() - 0x4C);
+        assert_eq!(redata, b"ABC");
+
+        // An unk14 that can't follow the data offset is an error, not a wrap.
+        let mut dcx = dcx;
+        dcx.header.as_mut().unwrap().unk14 = 0;
+        dcx.archive.ofs_data = 0x100;
+        assert!(matches!(pack_dcx(&mut dcx, b"ABC"), Err(PackError::Data(_))));
+    }
+
+    #[test]
+    fn test_pack_dcx_variants() {
+        let content = b"Some content of a DCX with another variant.".repeat(4);
+        let mut encoder = ZlibEncoder::new(vec!(), Compression::new(9));
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // DS3 DFLT.
+        let mut original = vec!();
+        original.extend_from_slice(b"DCX\0\0\x01\x10\0\0\0\0\x18\0\0\0\x24\0\0\0\x44\0\0\0\x4C");
+        original.extend_from_slice(b"DCS\0");
+        original.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        original.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        original.extend_from_slice(b"DCP\0DFLT\0\0\0\x20\x09\0\0\0");
+        original.extend_from_slice(b"\0\0\0\0\x0F\0\0\0\0\0\0\0\0\x01\x01\0");
+        original.extend_from_slice(b"DCA\0\0\0\0\x08");
+        original.extend_from_slice(&compressed);
+        let (mut dcx, data) = load_dcx_data(&original).unwrap();
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCX_DFLT_11000_44_9_15);
+        assert_eq!(data, content);
+        assert_eq!(pack_dcx(&mut dcx, &data).unwrap(), original);
+
+        // DeS DCP.
+        let mut original = vec!();
+        original.extend_from_slice(b"DCP\0DFLT\0\0\0\x20\x09\0\0\0");
+        original.extend_from_slice(b"\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\x01\0");
+        original.extend_from_slice(b"DCS\0");
+        original.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        original.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        original.extend_from_slice(&compressed);
+        original.extend_from_slice(b"DCA\0\0\0\0\x08");
+        let (mut dcx, data) = load_dcx_data(&original).unwrap();
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCP_DFLT);
+        assert!(dcx.header.is_none());
+        assert_eq!(data, content);
+        assert_eq!(pack_dcx(&mut dcx, &data).unwrap(), original);
+
+        // DeS DCP with EDGE chunks, packed from an empty template.
+        let mut template = vec!();
+        template.extend_from_slice(b"DCP\0EDGE\0\0\0\x20\x09\0\0\0");
+        template.extend_from_slice(b"\0\x01\0\0\0\0\0\0\0\0\0\0\0\x10\x01\0");
+        template.extend_from_slice(b"DCS\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        template.extend_from_slice(b"DCA\0\0\0\0\x28");
+        template.extend_from_slice(b"EgdT\0\x01\0\0\0\0\0\x20\0\0\0\x10\0\x01\0\0");
+        template.extend_from_slice(b"\0\0\0\x20\0\0\0\0\0\x10\0\0");
+        let (mut dcx, data) = load_dcx_data(&template).unwrap();
+        assert_eq!(dcx.variant, dcx::DcxVariant::DCP_EDGE);
+        assert!(data.is_empty());
+        let content: Vec<u8> = (0..0x18000u32).map(|i| (i % 251) as u8).collect();
+        let packed = pack_dcx(&mut dcx, &content).unwrap();
+        let (mut dcx, data) = load_dcx_data(&packed).unwrap();
+        assert_eq!(data, content);
+        assert_eq!(dcx.archive.ofs_data, 0x48);
+        let edge_table = dcx.edge_table.as_ref().unwrap();
+        assert_eq!(edge_table.num_chunks, 2);
+        assert_eq!(edge_table.table_size, 0x40);
+        assert_eq!(edge_table.unk20, 0x100000);
+        assert_eq!(pack_dcx(&mut dcx, &data).unwrap(), packed);
+    }
+}
@@ -1,14 +1,30 @@
 use std::io;
 
+use crate::unpackers::errors::UnpackError;
+
 #[derive(Debug)]
 pub enum PackError {
     Io(io::Error),
     Compression(String),
+    Data(String),
+    Unpacking(UnpackError),
     Unknown(String),
 }
 
+impl PackError {
+    pub fn encoding_err(s: &str) -> PackError {
+        PackError::Data(format!("Can't encode \"{}\" in Shift JIS.", s))
+    }
+}
+
 impl From<io::Error> for PackError {
     fn from(e: io::Error) -> Self {
         PackError::Io(e)
     }
 }
+
+impl From<UnpackError> for PackError {
+    fn from(e: UnpackError) -> Self {
+        PackError::Unpacking(e)
+    }
+}
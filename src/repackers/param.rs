@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::formats::common::{Pack, VarSizeInt, string_to_sjis, string_to_utf16};
+use crate::formats::param;
+use crate::formats::paramdef;
+use crate::params::diff::index_rows;
+use crate::repackers::errors::PackError;
+use crate::unpackers::param::get_field_column_name;
+use crate::utils::bin::{mask, u16_to_bytes, u32_to_bytes, u64_to_bytes};
+
+/// Write a PARAM to disk, see `pack_param`.
+pub fn pack_param_file(
+    param: &mut param::Param,
+    paramdef: Option<&paramdef::Paramdef>,
+    output_path: &str,
+) -> Result<(), PackError> {
+    let param_data = pack_param(param, paramdef)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&param_data)?;
+    Ok(())
+}
+
+/// Pack a PARAM, rebuilding its row table, data and strings.
+///
+/// If a PARAMDEF is provided, rows with parsed values are serialized
+/// from those values, on top of their raw data so that unknown bits are
+/// kept; other rows are written with their raw data. Offsets, row count
+/// and raw data are updated in `param`. Returns the PARAM data.
+pub fn pack_param(
+    param: &mut param::Param,
+    paramdef: Option<&paramdef::Paramdef>,
+) -> Result<Vec<u8>, PackError> {
+    if param.rows.len() > u16::MAX as usize {
+        return Err(PackError::Data(format!("Too many rows: {}", param.rows.len())))
+    }
+    let header = &mut param.header;
+    header.num_rows = param.rows.len() as u16;
+    let use_u64 = header.has_u64_ofs_data();
+    let make_offset = |o: usize| {
+        if use_u64 { VarSizeInt { vu64: o as u64 } } else { VarSizeInt::from_u32(o as u32) }
+    };
+
+    // Params with only the first flag have some padding after rows.
+    let rows_end = header.header_size() + param.rows.len() * header.row_info_size();
+    let padding = if header.flags2D == param::FLAGS2D_UNK1 { 0x20 } else { 0 };
+    let ofs_data = rows_end + padding;
+    if header.ofs_data_long.is_some() {
+        header.ofs_data = 0;
+        header.ofs_data_long = Some(make_offset(ofs_data));
+    } else {
+        header.ofs_data = ofs_data as u16;
+    }
+
+    let mut data = vec!();
+    for row in &mut param.rows {
+        if let Some(def) = paramdef.filter(|_| !row.data.is_empty()) {
+            row.raw_data.resize(def.row_size(), 0);
+            write_row_data(&mut row.raw_data, &row.data, header.use_be(), def)?;
+        }
+        row.ofs_data = make_offset(ofs_data + data.len());
+        data.extend_from_slice(&row.raw_data);
+    }
+
+    // Strings block: param type if stored there, then row names.
+    let ofs_strings = ofs_data + data.len();
+    header.ofs_strings = ofs_strings as u32;
+    let mut strings = vec!();
+    if header.has_ofs_string_name() {
+        header.ofs_name = Some(ofs_strings as u64);
+        strings.extend_from_slice(header.param_type.as_bytes());
+        strings.push(0);
+    }
+    for row in &mut param.rows {
+        row.ofs_name = match &row.name {
+            Some(name) => {
+                let ofs_name = ofs_strings + strings.len();
+                if header.has_unicode_names() {
+                    strings.append(&mut string_to_utf16(name, header.use_be()));
+                    strings.extend_from_slice(&[0, 0]);
+                } else {
+                    let mut sjis_name =
+                        string_to_sjis(name).ok_or_else(|| PackError::encoding_err(name))?;
+                    strings.append(&mut sjis_name);
+                    strings.push(0);
+                }
+                make_offset(ofs_name)
+            }
+            None => make_offset(0),
+        };
+    }
+
+    let mut output = Vec::with_capacity(ofs_strings + strings.len());
+    header.write(&mut output)?;
+    for row in &param.rows {
+        row.write_info(header, &mut output)?;
+    }
+    output.append(&mut vec![0u8; padding]);
+    output.append(&mut data);
+    output.append(&mut strings);
+    Ok(output)
+}
+
+/// Write row values in `row_data`, using field definitions from PARAMDEF.
+///
+/// Mirrors the parsing in `formats::param`: consecutive bitfields share
+/// the bytes of their containing type. Strings are only written if
+/// they changed, to keep whatever is stored after their terminator.
+pub fn write_row_data(
+    row_data: &mut [u8],
+    values: &[param::ParamRowValue],
+    use_be: bool,
+    paramdef: &paramdef::Paramdef,
+) -> Result<(), PackError> {
+    if values.len() != paramdef.fields.len() {
+        return Err(PackError::Data(format!(
+            "Row has {} values but PARAMDEF has {} fields.", values.len(), paramdef.fields.len()
+        )))
+    }
+    let layout = paramdef.get_layout().map_err(PackError::Data)?;
+    for ((field, value), field_layout) in paramdef.fields.iter().zip(values).zip(layout) {
+        let ofs = field_layout.offset;
+        let field_data = match row_data.get_mut(ofs..ofs + field.byte_count as usize) {
+            Some(field_data) => field_data,
+            None => {
+                let message = format!("Row data is too short for field {}.", field);
+                return Err(PackError::Data(message))
+            }
+        };
+        match field.bit_size() {
+            0 => write_value(field_data, value, use_be)?,
+            bit_size => {
+                let value = match value {
+                    param::ParamRowValue::S8(v) => *v as u8 as u32,
+                    param::ParamRowValue::U8(v) => *v as u32,
+                    param::ParamRowValue::S16(v) => *v as u16 as u32,
+                    param::ParamRowValue::U16(v) => *v as u32,
+                    param::ParamRowValue::S32(v) => *v as u32,
+                    param::ParamRowValue::U32(v) => *v,
+                    v => return Err(PackError::Data(format!("Invalid bitfield value: {:?}", v))),
+                };
+                let mut bitfield = match *field_data {
+                    [b] => b as u32,
+                    [b0, b1] if use_be => u16::from_be_bytes([b0, b1]) as u32,
+                    [b0, b1] => u16::from_le_bytes([b0, b1]) as u32,
+                    [b0, b1, b2, b3] if use_be => u32::from_be_bytes([b0, b1, b2, b3]),
+                    [b0, b1, b2, b3] => u32::from_le_bytes([b0, b1, b2, b3]),
+                    _ => {
+                        let message = format!("Invalid bitfield size: {}", field.byte_count);
+                        return Err(PackError::Data(message))
+                    }
+                };
+                let bit_offset = field_layout.bit_offset;
+                let field_mask = (mask(bit_size) as u32) << bit_offset;
+                bitfield = (bitfield & !field_mask) | ((value << bit_offset) & field_mask);
+                match field_data.len() {
+                    1 => field_data[0] = bitfield as u8,
+                    2 => field_data.copy_from_slice(&u16_to_bytes(bitfield as u16, use_be)),
+                    _ => field_data.copy_from_slice(&u32_to_bytes(bitfield, use_be)),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a single non-bitfield value in its field data.
+fn write_value(
+    field_data: &mut [u8],
+    value: &param::ParamRowValue,
+    use_be: bool,
+) -> Result<(), PackError> {
+    let num_bytes = field_data.len();
+    let bytes = match value {
+        param::ParamRowValue::FIXSTR(s) if param::decode_fixstr(field_data).as_ref() == Some(s) => {
+            return Ok(())
+        }
+        param::ParamRowValue::FIXSTRW(s)
+            if param::decode_fixstr_w(field_data, use_be).as_ref() == Some(s) => {
+            return Ok(())
+        }
+        param::ParamRowValue::FIXSTR(s) => {
+            string_to_sjis(s).ok_or_else(|| PackError::encoding_err(s))?
+        }
+        param::ParamRowValue::FIXSTRW(s) => string_to_utf16(s, use_be),
+        param::ParamRowValue::ARRAY(values) => {
+            if values.is_empty() || !num_bytes.is_multiple_of(values.len()) {
+                let message = format!("Array {:?} does not fit in {} bytes.", value, num_bytes);
+                return Err(PackError::Data(message))
+            }
+            let value_size = num_bytes / values.len();
+            let mut bytes = Vec::with_capacity(num_bytes);
+            for v in values {
+                bytes.append(&mut get_value_bytes(v, value_size, use_be)?);
+            }
+            bytes
+        }
+        v => get_value_bytes(v, num_bytes, use_be)?,
+    };
+    if bytes.len() > num_bytes {
+        let message = format!("Value {:?} does not fit in {} bytes.", value, num_bytes);
+        return Err(PackError::Data(message))
+    }
+    field_data[..bytes.len()].copy_from_slice(&bytes);
+    field_data[bytes.len()..].iter_mut().for_each(|b| *b = 0);
+    Ok(())
+}
+
+/// Replace PARAM rows with rows from a CSV file, see `import_param_csv`.
+pub fn import_param_csv_file(
+    param: &mut param::Param,
+    paramdef: &paramdef::Paramdef,
+    csv_path: &str,
+) -> Result<(), PackError> {
+    let csv_file = fs::File::open(csv_path)?;
+    import_param_csv(param, paramdef, csv_file)
+}
+
+/// Replace PARAM rows with rows from CSV, as exported by
+/// `unpackers::param::export_param_csv`.
+///
+/// Columns must match the PARAMDEF fields. Values are checked against
+/// their field type and, when the PARAMDEF defines a range, against it.
+/// Rows can be added, removed or reordered; existing rows keep their
+/// raw data so unknown bits are kept, rows sharing an ID being matched
+/// in order. The PARAM can then be written
+/// with `pack_param`.
+pub fn import_param_csv(
+    param: &mut param::Param,
+    paramdef: &paramdef::Paramdef,
+    input: impl io::Read,
+) -> Result<(), PackError> {
+    let mut reader = csv::Reader::from_reader(input);
+    let columns = reader.headers().map_err(io::Error::from)?.clone();
+    let mut expected_columns = vec!["ID".to_string(), "Name".to_string()];
+    expected_columns.extend(paramdef.fields.iter().map(get_field_column_name));
+    if columns.iter().ne(expected_columns.iter().map(|c| c.as_str())) {
+        return Err(PackError::Data("CSV columns do not match PARAMDEF fields.".to_owned()))
+    }
+
+    // Rows are only replaced once the whole CSV is valid.
+    let old_rows = index_rows(param);
+    let mut occurrences: HashMap<u32, usize> = HashMap::new();
+    let mut rows = vec!();
+    let row_size = paramdef.row_size();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(io::Error::from)?;
+        let line_error = |e: String| PackError::Data(format!("Row {}: {}", index + 1, e));
+        let id = record[0].trim().parse::<u32>()
+            .map_err(|_| line_error(format!("Invalid ID: \"{}\"", &record[0])))?;
+        let mut data = Vec::with_capacity(paramdef.fields.len());
+        for (field, value_str) in paramdef.fields.iter().zip(record.iter().skip(2)) {
+            let value = parse_field_value(value_str, field).map_err(line_error)?;
+            data.push(value);
+        }
+        let occurrence = occurrences.entry(id).or_insert(0);
+        let raw_data = match old_rows.get(&(id, *occurrence)) {
+            Some(row) => row.raw_data.clone(),
+            None => vec![0u8; row_size],
+        };
+        *occurrence += 1;
+        rows.push(param::ParamRow {
+            id,
+            ofs_data: VarSizeInt { vu64: 0 },
+            ofs_name: VarSizeInt { vu64: 0 },
+            name: Some(record[1].to_string()).filter(|n| !n.is_empty()),
+            data,
+            raw_data,
+        });
+    }
+    param.rows = rows;
+    param.set_fields(paramdef);
+    param.reindex();
+    Ok(())
+}
+
+/// Parse and validate a field value from a string.
+fn parse_field_value(
+    value_str: &str,
+    field: &paramdef::ParamdefField,
+) -> Result<param::ParamRowValue, String> {
+    let column = get_field_column_name(field);
+    let bit_size = field.bit_size();
+    let value = param::ParamRowValue::from_str_with_type(
+        value_str,
+        &field.display_type,
+        field.byte_count as usize,
+        bit_size > 0,
+    ).map_err(|e| format!("{}: {}", column, e))?;
+    if let Some(v) = value.as_f64() {
+        let (min, max) = (field.min_value as f64, field.max_value as f64);
+        if min < max && (v < min || v > max) {
+            return Err(format!("{}: {} is not in range [{}, {}]", column, v, min, max))
+        }
+        if bit_size > 0 && v > mask(bit_size) as f64 {
+            return Err(format!("{}: {} does not fit in {} bits", column, v, bit_size))
+        }
+    }
+    Ok(value)
+}
+
+/// Return the bytes of a single row value.
+fn get_value_bytes(
+    value: &param::ParamRowValue,
+    num_bytes: usize,
+    use_be: bool,
+) -> Result<Vec<u8>, PackError> {
+    let bytes = match value {
+        param::ParamRowValue::S8(v) => vec![*v as u8],
+        param::ParamRowValue::U8(v) => vec![*v],
+        param::ParamRowValue::S16(v) => u16_to_bytes(*v as u16, use_be).to_vec(),
+        param::ParamRowValue::U16(v) => u16_to_bytes(*v, use_be).to_vec(),
+        param::ParamRowValue::S32(v) => u32_to_bytes(*v as u32, use_be).to_vec(),
+        param::ParamRowValue::U32(v) => u32_to_bytes(*v, use_be).to_vec(),
+        param::ParamRowValue::S64(v) => u64_to_bytes(*v as u64, use_be).to_vec(),
+        param::ParamRowValue::U64(v) => u64_to_bytes(*v, use_be).to_vec(),
+        param::ParamRowValue::F32(v) => u32_to_bytes(v.to_bits(), use_be).to_vec(),
+        param::ParamRowValue::F64(v) => u64_to_bytes(v.to_bits(), use_be).to_vec(),
+        param::ParamRowValue::ANGLE32(v) => u32_to_bytes(v.to_bits(), use_be).to_vec(),
+        param::ParamRowValue::UNK(v) => v.clone(),
+        v => return Err(PackError::Data(format!("Invalid single value: {:?}", v))),
+    };
+    if bytes.len() != num_bytes {
+        let message = format!("Value {:?} does not fit in {} bytes.", value, num_bytes);
+        return Err(PackError::Data(message))
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::params::fixtures;
+    use crate::unpackers::errors::UnpackError;
+    use crate::unpackers::param::load_param;
+
+    const FIELDS: &[(&str, u32, &str)] = &[
+        ("s16", 2, "a"), ("u8", 1, "b:1"), ("u8", 1, "c:7"), ("u8", 1, "d"), ("dummy8", 4, "pad"),
+    ];
+
+    /// DS1-style PARAM with two rows of 8 bytes.
+    fn build_param() -> Vec<u8> {
+        let mut data = vec!();
+        data.extend_from_slice(&[0x58, 0, 0, 0, 0x48, 0, 0, 0, 1, 0, 2, 0]);
+        let mut param_type = b"TEST_PARAM_ST\0".to_vec();
+        param_type.resize(0x20, 0);
+        data.extend_from_slice(&param_type);
+        data.extend_from_slice(&[0, 0, 0, 2]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0x48, 0, 0, 0, 0x58, 0, 0, 0]);
+        data.extend_from_slice(&[10, 0, 0, 0, 0x50, 0, 0, 0, 0x5E, 0, 0, 0]);
+        data.extend_from_slice(&[0xFF, 0xFF, 0x03, 7, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x10, 0, 0x00, 8, 0, 0, 0, 0]);
+        data.extend_from_slice(b"First\0\x82\xa0\0");
+        data
+    }
+
+    #[test]
+    fn test_pack_param() {
+        let original = build_param();
+        let mut param = load_param(&original, None).unwrap();
+        assert_eq!(param.rows[1].raw_data.len(), 8);
+        assert_eq!(pack_param(&mut param, None).unwrap(), original);
+
+        let paramdef = fixtures::build_paramdef(FIELDS);
+        let mut param = load_param(&original, Some(&paramdef)).unwrap();
+        assert_eq!(pack_param(&mut param, Some(&paramdef)).unwrap(), original);
+
+        // Edit some values.
+        param.rows[0].data[2] = param::ParamRowValue::U8(0x10);
+        param.rows[1].data[0] = param::ParamRowValue::S16(-2);
+        param.rows[1].name = Some("Second".to_owned());
+        let repacked = pack_param(&mut param, Some(&paramdef)).unwrap();
+        let param = load_param(&repacked, Some(&paramdef)).unwrap();
+        assert_eq!(param.rows[0].raw_data[2], 0x21);
+        assert_eq!(param.rows[1].raw_data[..2], [0xFE, 0xFF]);
+        assert_eq!(param.rows[1].name.as_deref(), Some("Second"));
+
+        param.rows.iter().for_each(|r| assert_eq!(r.data.len(), 5));
+
+        // Rows are too short for this PARAMDEF.
+        let mut paramdef = fixtures::build_paramdef(FIELDS);
+        paramdef.fields.push(paramdef::ParamdefField::new("u8", 1, "e"));
+        match load_param(&original, Some(&paramdef)) {
+            Err(UnpackError::Parsing(message)) => assert_eq!(
+                message,
+                "PARAMDEF row size 9 (version 1) is larger than PARAM rows of 8 bytes (version 1)."
+            ),
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // With a single row, reaching the strings is only a warning.
+        let mut single_row = original.clone();
+        single_row[10] = 1;
+        let param = load_param(&single_row, Some(&paramdef)).unwrap();
+        assert_eq!(param.rows[0].raw_data.len(), 9);
+    }
+
+    #[test]
+    fn test_row_access() {
+        use param::{ParamRowValue, RowAccessError};
+
+        let paramdef = fixtures::build_paramdef(FIELDS);
+        let mut param = load_param(&build_param(), Some(&paramdef)).unwrap();
+        assert_eq!(param.ids().collect::<Vec<u32>>(), vec![0, 10]);
+        let row = param.row(10).unwrap();
+        assert_eq!(row.name.as_deref(), Some("あ"));
+        assert_eq!(row.get_s16("a"), Ok(0x10));
+        assert_eq!(row.get_u32("d"), Ok(8));
+        assert_eq!(row.get_f32("d"), Ok(8.0));
+        assert_eq!(param.row(0).unwrap().get_s32("a"), Ok(-1));
+        let row = param.row(0).unwrap();
+        assert!(matches!(row.get_u8("a"), Err(RowAccessError::Conversion { .. })));
+        assert!(matches!(row.get_str("a"), Err(RowAccessError::Conversion { .. })));
+        assert_eq!(param.row(0).unwrap().get("x").err(), Some(RowAccessError::NoField("x".into())));
+        assert!(matches!(param.row(1), Err(RowAccessError::NoRow(1))));
+
+        let mut row = param.row_mut(10).unwrap();
+        row.set_int("d", 200).unwrap();
+        row.set_float("a", -3.0).unwrap();
+        assert!(row.set_int("d", 256).is_err());
+        assert!(row.set_float("a", 0.5).is_err());
+        assert!(row.set("a", ParamRowValue::U8(1)).is_err());
+        row.set("c", ParamRowValue::U8(5)).unwrap();
+        // Bitfields only accept values fitting in their bits.
+        row.set_int("b", 1).unwrap();
+        assert!(matches!(row.set_int("b", 2), Err(RowAccessError::Conversion { .. })));
+        assert!(matches!(row.set_float("c", 128.0), Err(RowAccessError::Conversion { .. })));
+        let error = row.set("c", ParamRowValue::U8(255));
+        assert!(matches!(error, Err(RowAccessError::Conversion { .. })));
+        let repacked = pack_param(&mut param, Some(&paramdef)).unwrap();
+        let param = load_param(&repacked, Some(&paramdef)).unwrap();
+        let values: Vec<String> = param.row(10).unwrap().fields()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        assert_eq!(values, vec!["a=-3", "b=1", "c=5", "d=200", "pad=[0, 0, 0, 0]"]);
+
+        // Lookups still work after moving rows, then the index can be rebuilt.
+        let mut param = param;
+        param.rows.reverse();
+        assert_eq!(param.row(10).unwrap().get_u8("d"), Ok(200));
+        param.reindex();
+        assert_eq!(param.row_index[&10], 0);
+
+        let param = load_param(&build_param(), None).unwrap();
+        assert_eq!(param.row(0).unwrap().get("a").err(), Some(RowAccessError::NoField("a".into())));
+    }
+
+    #[test]
+    fn test_pack_param_all_types() {
+        let mut paramdef = fixtures::build_paramdef(FIELDS);
+        paramdef.fields = vec![
+            paramdef::ParamdefField::new("fixstr", 8, "name[8]"),
+            paramdef::ParamdefField::new("fixstrW", 8, "wname[4]"),
+            paramdef::ParamdefField::new("s16", 4, "arr[2]"),
+            paramdef::ParamdefField::new("s64", 8, "big"),
+            paramdef::ParamdefField::new("f64", 8, "dbl"),
+            paramdef::ParamdefField::new("angle32", 4, "ang"),
+            paramdef::ParamdefField::new("u32", 4, "x:3"),
+            paramdef::ParamdefField::new("u32", 4, "y:20"),
+            paramdef::ParamdefField::new("s32", 4, "z:4"),
+            paramdef::ParamdefField::new("u8", 1, "flag:1"),
+            paramdef::ParamdefField::new("dummy8", 1, "pad:7"),
+            paramdef::ParamdefField::new("dummy8", 3, "pad2[3]"),
+        ];
+        assert_eq!(paramdef.row_size(), 52);
+
+        let mut row_data = b"abc\0\xFF\xFF\0\0h\0i\0\0\0\0\0\xFF\xFF\x02\0".to_vec();
+        row_data.extend_from_slice(&(-2i64).to_le_bytes());
+        row_data.extend_from_slice(&1.5f64.to_le_bytes());
+        row_data.extend_from_slice(&90f32.to_le_bytes());
+        row_data.extend_from_slice(&(5u32 | (0xABCDE << 3) | 0x8000_0000).to_le_bytes());
+        row_data.extend_from_slice(&[0x0F, 0, 0, 0, 0xFF, 1, 2, 3]);
+        let mut param = load_param(&build_param(), None).unwrap();
+        param.rows.iter_mut().for_each(|r| r.raw_data = row_data.clone());
+        let original = pack_param(&mut param, None).unwrap();
+
+        let mut param = load_param(&original, Some(&paramdef)).unwrap();
+        let values: Vec<String> = param.rows[0].data.iter().map(|v| v.to_value_string()).collect();
+        assert_eq!(values, vec![
+            "abc", "hi", "[-1, 2]", "-2", "1.5", "90", "5", "703710", "15", "1", "127", "010203"
+        ]);
+        assert_eq!(pack_param(&mut param, Some(&paramdef)).unwrap(), original);
+
+        param.rows[0].data[0] = param::ParamRowValue::FIXSTR("abcdefgh".to_owned());
+        param.rows[0].data[1] = param::ParamRowValue::FIXSTRW("x".to_owned());
+        param.rows[0].data[7] = param::ParamRowValue::U32(1);
+        let repacked = pack_param(&mut param, Some(&paramdef)).unwrap();
+        let param = load_param(&repacked, Some(&paramdef)).unwrap();
+        assert_eq!(&param.rows[0].raw_data[..16], b"abcdefghx\0\0\0\0\0\0\0");
+        assert_eq!(&param.rows[0].raw_data[40..44], &0x8000_000Du32.to_le_bytes());
+
+        let mut param = load_param(&original, Some(&paramdef)).unwrap();
+        param.rows[0].data[0] = param::ParamRowValue::FIXSTR("abcdefghi".to_owned());
+        assert!(pack_param(&mut param, Some(&paramdef)).is_err());
+
+        paramdef.fields[5] = paramdef::ParamdefField::new("f32", 4, "ang:1");
+        assert!(load_param(&original, Some(&paramdef)).is_err());
+    }
+
+    #[test]
+    fn test_import_param_csv() {
+        use crate::unpackers::param::export_param_csv;
+
+        let mut paramdef = fixtures::build_paramdef(FIELDS);
+        paramdef.fields[3].max_value = 100.0;
+        let mut param = load_param(&build_param(), Some(&paramdef)).unwrap();
+        let mut csv_data = vec!();
+        export_param_csv(&param, &paramdef, &mut csv_data).unwrap();
+        let csv_string = String::from_utf8(csv_data).unwrap();
+        assert_eq!(
+            csv_string,
+            "ID,Name,a,b,c,d,pad\n0,First,-1,1,1,7,00000000\n10,\u{3042},16,0,0,8,00000000\n"
+        );
+
+        // Reorder rows, edit and add some.
+        let csv_string = "ID,Name,a,b,c,d,pad\n\
+            10,,16,0,0,9,00000000\n\
+            0,First,-1,1,1,7,00000000\n\
+            20,New,3,0,127,0,000000FF\n";
+        import_param_csv(&mut param, &paramdef, csv_string.as_bytes()).unwrap();
+        let packed = pack_param(&mut param, Some(&paramdef)).unwrap();
+        let param = load_param(&packed, Some(&paramdef)).unwrap();
+        assert_eq!(param.rows.iter().map(|r| r.id).collect::<Vec<u32>>(), vec![10, 0, 20]);
+        assert!(param.rows[0].name.is_none());
+        assert_eq!(param.rows[0].raw_data, vec![0x10, 0, 0, 9, 0, 0, 0, 0]);
+        assert_eq!(param.rows[2].raw_data, vec![3, 0, 0xFE, 0, 0, 0, 0, 0xFF]);
+
+        // Rows sharing an ID keep their own raw data.
+        let mut param = load_param(&build_param(), Some(&paramdef)).unwrap();
+        param.rows[1].id = 0;
+        let csv_string = "ID,Name,a,b,c,d,pad\n\
+            0,First,-1,1,1,7,00000000\n\
+            0,Second,16,0,0,8,00000000\n";
+        import_param_csv(&mut param, &paramdef, csv_string.as_bytes()).unwrap();
+        assert_eq!(param.rows[1].raw_data, vec![0x10, 0, 0, 8, 0, 0, 0, 0]);
+
+        // Invalid values, leaving rows untouched.
+        let bad_csvs = [
+            "ID,Name,a,b,c,d,pad\n10,,0,0,0,0,00000000\n0,,-1,1,1,101,00000000\n",
+            "ID,Name,a,b,c,d,pad\n0,,-1,2,1,1,00000000\n",
+            "ID,Name,a,b,c,d,pad\n0,,-1,1,1,1,000000\n",
+            "ID,Name,a,b,c,d,pad\n0,,1.5,1,1,1,00000000\n",
+            "ID,Name,a,b,c,e,pad\n0,,1,1,1,1,00000000\n",
+        ];
+        for bad_csv in &bad_csvs {
+            let mut param = load_param(&build_param(), Some(&paramdef)).unwrap();
+            assert!(import_param_csv(&mut param, &paramdef, bad_csv.as_bytes()).is_err());
+            assert_eq!(param.rows.iter().map(|r| r.id).collect::<Vec<u32>>(), vec![0, 10]);
+        }
+    }
+
+    #[test]
+    fn test_pack_param_long() {
+        // Big endian, 64-bit offsets and param type in the strings block.
+        let mut param = load_param(&build_param(), None).unwrap();
+        param.header.endianness = 0xFF;
+        param.header.flags2D = param::FLAGS2D_64B_OFS_DATA | param::FLAGS2D_OFS_STRING;
+        param.header.ofs_data_long = Some(VarSizeInt { vu64: 0 });
+        let packed = pack_param(&mut param, None).unwrap();
+        assert_eq!(&packed[0x30..0x38], &[0, 0, 0, 0, 0, 0, 0, 0x70]);
+        assert_eq!(&packed[0x70..0x72], &[0xFF, 0xFF]);
+
+        let mut repacked_param = load_param(&packed, None).unwrap();
+        assert_eq!(repacked_param.header.param_type, "TEST_PARAM_ST");
+        assert_eq!(repacked_param.rows[1].id, 10);
+        assert_eq!(repacked_param.rows[1].raw_data, param.rows[1].raw_data);
+        assert_eq!(repacked_param.rows[1].name.as_deref(), Some("\u{3042}"));
+        assert_eq!(pack_param(&mut repacked_param, None).unwrap(), packed);
+    }
+}
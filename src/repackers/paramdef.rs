@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::Write;
+
+use crate::formats::common::{Pack, VarSizeInt, string_to_sjis};
+use crate::formats::paramdef;
+use crate::repackers::errors::PackError;
+
+/// Write a PARAMDEF to disk, see `pack_paramdef`.
+pub fn pack_paramdef_file(
+    paramdef: &mut paramdef::Paramdef,
+    output_path: &str,
+) -> Result<(), PackError> {
+    let paramdef_data = pack_paramdef(paramdef)?;
+    let mut output_file = fs::File::create(output_path)?;
+    output_file.write_all(&paramdef_data)?;
+    Ok(())
+}
+
+/// Pack a PARAMDEF using its header format version.
+///
+/// Fields are written right after the header, followed by their
+/// descriptions. Display names and descriptions are encoded in UTF-16
+/// for unicode PARAMDEFs. Sizes, counts and offsets are updated in `paramdef`.
+/// Strings too long for their fixed size are refused. Returns the
+/// PARAMDEF data.
+pub fn pack_paramdef(paramdef: &mut paramdef::Paramdef) -> Result<Vec<u8>, PackError> {
+    if paramdef.fields.len() > u16::MAX as usize {
+        return Err(PackError::Data(format!("Too many fields: {}", paramdef.fields.len())))
+    }
+    let header = &paramdef.header;
+    check_length("param name", &encode_sjis(&header.param_name)?, 0x20)?;
+    for field in &paramdef.fields {
+        check_length("display name", &encode_display_string(header, &field.display_name)?, 0x40)?;
+        check_length("display type", &encode_sjis(&field.display_type)?, 0x8)?;
+        check_length("display format", &encode_sjis(&field.display_format)?, 0x8)?;
+        check_length("internal type", &encode_sjis(&field.internal_type)?, 0x20)?;
+        if paramdef.header.has_internal_name() {
+            let internal_name = field.internal_name.as_deref().unwrap_or("");
+            check_length("internal name", &encode_sjis(internal_name)?, 0x20)?;
+        }
+    }
+
+    let header = &mut paramdef.header;
+    header.num_fields = paramdef.fields.len() as u16;
+    header.header_size = header.header_size() as u16;
+    header.field_size = header.field_size() as u16;
+    header.ofs_fields = if header.has_ofs_fields() { header.header_size() as u64 } else { 0 };
+
+    // Descriptions block, after the fields.
+    let ofs_descs = header.header_size() + paramdef.fields.len() * header.field_size();
+    let mut descs = vec!();
+    for field in &mut paramdef.fields {
+        let ofs_desc = match &field.description {
+            Some(description) => {
+                let ofs_desc = ofs_descs + descs.len();
+                descs.append(&mut encode_display_string(header, description)?);
+                descs.resize(descs.len() + if header.is_unicode() { 2 } else { 1 }, 0);
+                ofs_desc
+            }
+            None => 0,
+        };
+        field.ofs_desc = if header.has_64b_ofs_desc() {
+            VarSizeInt { vu64: ofs_desc as u64 }
+        } else {
+            VarSizeInt::from_u32(ofs_desc as u32)
+        };
+    }
+    header.file_size = (ofs_descs + descs.len()) as u32;
+
+    let mut output = Vec::with_capacity(header.file_size as usize);
+    header.write(&mut output)?;
+    for field in &paramdef.fields {
+        field.write(header, &mut output)?;
+    }
+    output.append(&mut descs);
+    Ok(output)
+}
+
+fn encode_sjis(s: &str) -> Result<Vec<u8>, PackError> {
+    string_to_sjis(s).ok_or_else(|| PackError::encoding_err(s))
+}
+
+fn encode_display_string(
+    header: &paramdef::ParamdefHeader,
+    s: &str,
+) -> Result<Vec<u8>, PackError> {
+    header.encode_display_string(s).ok_or_else(|| PackError::encoding_err(s))
+}
+
+/// Check that a string fits in a fixed size, with its terminator.
+fn check_length(name: &str, s: &[u8], length: usize) -> Result<(), PackError> {
+    if s.len() >= length {
+        let message = format!("The {} {} is too long.", name, String::from_utf8_lossy(s));
+        return Err(PackError::Data(message))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::unpackers::paramdef::{export_paramdef_xml, load_paramdef, load_paramdef_xml};
+
+    fn build_paramdef(format_version: u16, endianness: u8) -> paramdef::Paramdef {
+        let field = |display_type: &str, byte_count: u32, name: &str| paramdef::ParamdefField {
+            display_name: format!("{} & co", name),
+            display_format: "%d".to_owned(),
+            default_value: 1.0,
+            min_value: -1.0,
+            max_value: 100.0,
+            increment: 1.0,
+            edit_flags: paramdef::EDIT_FLAG_WRAP,
+            internal_type: "TEST_ENUM".to_owned(),
+            sort_id: 5,
+            description: Some(format!("Description of <{}>.", name)),
+            ..paramdef::ParamdefField::new(display_type, byte_count, name)
+        };
+        let mut fields = vec![
+            field("s16", 2, "a"),
+            field("u8", 1, "b:1"),
+            field("u8", 1, "c:7"),
+            field("fixstr", 8, "name[8]"),
+        ];
+        fields[1].description = None;
+        if format_version < 102 {
+            fields.iter_mut().for_each(|f| f.internal_name = None);
+        }
+        if format_version < 104 {
+            fields.iter_mut().for_each(|f| f.sort_id = 0);
+        }
+        let mut paramdef = paramdef::Paramdef::new("TEST_PARAM_ST", fields);
+        paramdef.header.data_version = 3;
+        paramdef.header.endianness = endianness;
+        paramdef.header.format_version = format_version;
+        paramdef
+    }
+
+    #[test]
+    fn test_pack_paramdef() {
+        for (format_version, endianness) in &[(101, 0), (102, 0xFF), (104, 0), (201, 0xFF)] {
+            let mut paramdef = build_paramdef(*format_version, *endianness);
+            let packed = pack_paramdef(&mut paramdef).unwrap();
+            let field_size = paramdef.header.field_size as usize;
+            assert_eq!(paramdef.header.file_size as usize, packed.len());
+            assert_eq!(
+                field_size,
+                match format_version { 101 => 0x8C, 102 => 0xAC, 104 => 0xB0, _ => 0xB4 }
+            );
+
+            let mut loaded = load_paramdef(&packed).unwrap();
+            assert_eq!(loaded.header.num_fields, 4);
+            assert_eq!(loaded.fields[0].display_name, "a & co");
+            assert_eq!(loaded.fields[0].description.as_deref(), Some("Description of <a>."));
+            assert_eq!(loaded.fields[1].description, None);
+            assert_eq!(loaded.fields[2].default_value, 1.0);
+            assert_eq!(loaded.fields[3].byte_count, 8);
+            assert_eq!(pack_paramdef(&mut loaded).unwrap(), packed);
+            if *format_version < 102 {
+                continue
+            }
+
+            // Bitfields and arrays need internal names to go through XML.
+            let mut xml = vec!();
+            export_paramdef_xml(&loaded, &mut xml).unwrap();
+            let mut from_xml = load_paramdef_xml(&String::from_utf8(xml).unwrap()).unwrap();
+            assert_eq!(from_xml.row_size(), 2 + 1 + 8);
+            assert_eq!(pack_paramdef(&mut from_xml).unwrap(), packed);
+        }
+    }
+
+    #[test]
+    fn test_pack_paramdef_unicode() {
+        let mut paramdef = build_paramdef(201, 0);
+        paramdef.header.unicode = 1;
+        paramdef.header.param_name = "テスト_PARAM_ST".to_owned();
+        paramdef.fields[0].display_name = "名前".to_owned();
+        let packed = pack_paramdef(&mut paramdef).unwrap();
+        // Display names and descriptions are UTF-16, other strings Shift JIS.
+        assert_eq!(&packed[0xC..0x12], &[0x83, 0x65, 0x83, 0x58, 0x83, 0x67]);
+        let ofs_field = paramdef.header.header_size as usize;
+        assert_eq!(&packed[ofs_field..ofs_field + 6], &[0x0D, 0x54, 0x4D, 0x52, 0, 0]);
+        let ofs_desc = paramdef.fields[0].ofs_desc.u64_if(true) as usize;
+        assert_eq!(&packed[ofs_desc..ofs_desc + 4], b"D\0e\0");
+
+        let mut loaded = load_paramdef(&packed).unwrap();
+        assert_eq!(loaded.header.param_name, "テスト_PARAM_ST");
+        assert_eq!(loaded.fields[0].display_name, "名前");
+        assert_eq!(loaded.fields[1].display_name, "b:1 & co");
+        assert_eq!(loaded.fields[0].description.as_deref(), Some("Description of <a>."));
+        assert_eq!(pack_paramdef(&mut loaded).unwrap(), packed);
+
+        // A UTF-16 display name takes twice the space.
+        paramdef.fields[0].display_name = "a".repeat(0x20);
+        assert!(pack_paramdef(&mut paramdef).is_err());
+        paramdef.fields[0].display_name = "a".repeat(0x1F);
+        assert!(pack_paramdef(&mut paramdef).is_ok());
+    }
+
+    #[test]
+    fn test_pack_paramdef_errors() {
+        let mut paramdef = build_paramdef(104, 0);
+        paramdef.fields[0].internal_name = Some("a".repeat(0x20));
+        assert!(pack_paramdef(&mut paramdef).is_err());
+        paramdef.fields[0].internal_name = Some("a".repeat(0x1F));
+        assert!(pack_paramdef(&mut paramdef).is_ok());
+
+        // Characters that Shift JIS can't encode.
+        paramdef.fields[0].description = Some("\u{1F600}".to_owned());
+        assert!(matches!(pack_paramdef(&mut paramdef), Err(PackError::Data(_))));
+        paramdef.header.unicode = 1;
+        assert!(pack_paramdef(&mut paramdef).is_ok());
+        paramdef.fields[0].internal_type = "\u{1F600}".to_owned();
+        assert!(matches!(pack_paramdef(&mut paramdef), Err(PackError::Data(_))));
+    }
+}
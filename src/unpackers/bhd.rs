@@ -8,40 +8,69 @@ use nom::Err::{Error as NomError, Failure as NomFailure};
 use crate::name_hashes;
 use crate::formats::bhd;
 use crate::unpackers::errors::UnpackError;
+use crate::utils::crypto::{RsaPublicKey, decrypt_aes_ecb, decrypt_rsa, sha256};
 use crate::utils::fs as utils_fs;
 
 /// Parse a BHD file and extract its content from sister BDT.
 ///
 /// As names are often a path rather than a simple file name,
 /// output path is used as the BHD root and required subdirs
-/// are automatically created.
+/// are automatically created. Encrypted BHD files require the
+/// game's public key, see `load_bhd`.
 pub fn extract_bhd(
     bhd_path: &str,
     names: &HashMap<String, String>,
-    output_path: &str
+    output_path: &str,
+    format: bhd::BhdFormat,
+    key: Option<&RsaPublicKey>,
 ) -> Result<(), UnpackError> {
     let bhd_path = path::Path::new(bhd_path);
     let bhd_data = utils_fs::open_file_to_vec(bhd_path)?;
-    let bhd = match bhd::parse(&bhd_data) {
-        Ok((_, bhd)) => bhd,
-        Err(NomError(e)) | Err(NomFailure(e)) => return Err(UnpackError::parsing_err("BHD", e.1)),
-        e => return Err(UnpackError::Unknown(format!("Unknown error: {:?}", e)))
-    };
+    let bhd = load_bhd(&bhd_data, format, key)?;
 
     let bdt_path = bhd_path.to_path_buf().with_extension("bdt");
     let mut bdt_file = fs::File::open(bdt_path.to_str().unwrap())?;
 
-    extract_files(&bhd, &mut bdt_file, &names, &output_path)?;
+    extract_files(&bhd, &mut bdt_file, names, output_path)?;
     Ok(())
 }
 
+/// Load BHD data, decrypting it first if it is encrypted.
+///
+/// Since DS2, BHD files are encrypted with an RSA private key, so they
+/// are decrypted with the public key before being parsed.
+pub fn load_bhd(
+    bhd_data: &[u8],
+    format: bhd::BhdFormat,
+    key: Option<&RsaPublicKey>,
+) -> Result<bhd::Bhd, UnpackError> {
+    let decrypted_data;
+    let bhd_data = if bhd_data.starts_with(bhd::MAGIC) {
+        bhd_data
+    } else {
+        let key = key.ok_or_else(|| UnpackError::Decryption(
+            "BHD is encrypted, a public key is required.".to_owned()
+        ))?;
+        decrypted_data = decrypt_rsa(bhd_data, key);
+        if !decrypted_data.starts_with(bhd::MAGIC) {
+            return Err(UnpackError::Decryption("Decrypted BHD is invalid, wrong key?".to_owned()))
+        }
+        &decrypted_data
+    };
+    match bhd::parse_with_format(bhd_data, format) {
+        Ok((_, bhd)) => Ok(bhd),
+        Err(NomError(e)) | Err(NomFailure(e)) => Err(UnpackError::parsing_err("BHD", e.1)),
+        e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
+    }
+}
+
 /// Extract files from a BHD/BDT pair.
 fn extract_files(
     bhd: &bhd::Bhd,
     bdt_file: &mut fs::File,
     names: &HashMap<String, String>,
     output_path: &str,
-) -> Result<(), io::Error> {
+) -> Result<(), UnpackError> {
     let output_path = path::Path::new(output_path);
     utils_fs::ensure_dir_exists(output_path)?;
 
@@ -51,7 +80,20 @@ fn extract_files(
             let mut data = vec![0; entry.size as usize];
             bdt_file.read_exact(&mut data)?;
 
-            let hash_str = name_hashes::hash_as_string(entry.hash);
+            let hash_str = if bhd.format >= bhd::BhdFormat::EldenRing {
+                name_hashes::hash_64_as_string(entry.hash)
+            } else {
+                name_hashes::hash_as_string(entry.hash as u32)
+            };
+            if let Err(e) = decrypt_entry_data(entry, &mut data) {
+                eprintln!("Can't decrypt {}, data may be corrupted: {:?}", hash_str, e);
+            }
+            match check_entry_hash(entry, &data) {
+                Ok(true) => {}
+                Ok(false) => eprintln!("SHA-256 mismatch for {}, data may be corrupted.", hash_str),
+                Err(e) => eprintln!("Can't check SHA-256 of {}: {:?}", hash_str, e),
+            }
+            data.truncate(entry.data_size() as usize);
             let rel_path: &str = match names.get(&hash_str) {
                 Some(path) => {
                     path.trim_start_matches("/")
@@ -70,3 +112,91 @@ fn extract_files(
 
     Ok(())
 }
+
+/// Decrypt the AES-encrypted ranges of this entry data.
+///
+/// `data` must contain the full padded entry data; padding is stripped
+/// by the caller, once the hash is checked.
+pub fn decrypt_entry_data(entry: &bhd::BhdFile, data: &mut [u8]) -> Result<(), UnpackError> {
+    if let Some(aes_key) = &entry.aes_key {
+        for range in aes_key.ranges.iter().filter(|r| r.is_used()) {
+            let (start, end) = get_range_bounds(range, data.len())?;
+            decrypt_aes_ecb(&mut data[start..end], &aes_key.key);
+        }
+    }
+    Ok(())
+}
+
+/// Check the SHA-256 hash of the decrypted entry data, if it has one.
+///
+/// The hash is computed over the data of all used ranges, in order,
+/// which may include padding.
+pub fn check_entry_hash(entry: &bhd::BhdFile, data: &[u8]) -> Result<bool, UnpackError> {
+    let sha_hash = match &entry.sha_hash {
+        Some(h) => h,
+        None => return Ok(true),
+    };
+    let mut hashed_data = vec!();
+    for range in sha_hash.ranges.iter().filter(|r| r.is_used()) {
+        let (start, end) = get_range_bounds(range, data.len())?;
+        hashed_data.extend_from_slice(&data[start..end]);
+    }
+    Ok(sha256(&hashed_data) == sha_hash.hash)
+}
+
+/// Return range bounds as indices, ensuring they fit in data of this size.
+fn get_range_bounds(range: &bhd::BhdRange, size: usize) -> Result<(usize, usize), UnpackError> {
+    let (start, end) = (range.start as usize, range.end as usize);
+    if range.start < 0 || start > end || end > size {
+        return Err(UnpackError::Decryption(format!(
+            "Invalid range {:X}-{:X} for data of size {:X}.", range.start, range.end, size
+        )))
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::crypto::encrypt_aes_ecb;
+
+    #[test]
+    fn test_decrypt_entry_data() {
+        let key = vec![0x42u8; 16];
+        let original: Vec<u8> = (0..0x28).collect();
+        let mut data = original.clone();
+        encrypt_aes_ecb(&mut data[0x10..0x20], &key);
+        data.extend_from_slice(&[0u8; 8]);
+        let mut entry = bhd::BhdFile {
+            hash: 0,
+            size: 0x30,
+            offset: 0,
+            ofs_sha_hash: 0,
+            ofs_aes_key: 0,
+            unpadded_size: 0x28,
+            sha_hash: Some(bhd::BhdShaHash {
+                hash: sha256(&[&original[..], &[0u8; 8]].concat()),
+                ranges: vec![bhd::BhdRange { start: 0, end: 0x30 }],
+            }),
+            aes_key: Some(bhd::BhdAesKey {
+                key,
+                ranges: vec![
+                    bhd::BhdRange { start: 0x10, end: 0x20 },
+                    bhd::BhdRange { start: -1, end: -1 },
+                ],
+            }),
+        };
+        decrypt_entry_data(&entry, &mut data).unwrap();
+        assert!(check_entry_hash(&entry, &data).unwrap());
+        assert_eq!(data[..entry.data_size() as usize], original[..]);
+        data[0] = 0xFF;
+        assert!(!check_entry_hash(&entry, &data).unwrap());
+
+        // Hashed padding is no longer there once truncated.
+        data.truncate(entry.data_size() as usize);
+        assert!(check_entry_hash(&entry, &data).is_err());
+        entry.aes_key.as_mut().unwrap().ranges[0].end = 0x40;
+        assert!(decrypt_entry_data(&entry, &mut data).is_err());
+    }
+}
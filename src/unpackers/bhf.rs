@@ -5,18 +5,20 @@ use std::path;
 use nom::Err::{Error as NomError, Failure as NomFailure};
 
 use crate::formats::bhf;
+use crate::formats::bnd4;
 use crate::unpackers::errors::UnpackError;
 use crate::utils::fs as utils_fs;
 
 /// Extract BHF file and corresponding BDT contents to disk.
 ///
-/// Wraps around `extract_bhf` to load the BHF file from disk.
+/// Wraps around `extract_bhf` to load the BHF file from disk. BHF4
+/// files are detected by their magic and extracted with `extract_bhf4`.
 pub fn extract_bhf_file(
     bhf_path: &str,
     output_dir: &str,
     overwrite: bool
 ) -> Result<(), UnpackError> {
-    let bhf = load_bhf_file(bhf_path)?;
+    let bhf_data = utils_fs::open_file_to_vec(path::Path::new(bhf_path))?;
 
     let bdt_path: path::PathBuf = if let Some(path) = get_bdt_for_bhf(bhf_path) {
         if !path.exists() {
@@ -28,7 +30,13 @@ pub fn extract_bhf_file(
     };
     let bdt_data = utils_fs::open_file_to_vec(&bdt_path)?;
 
-    extract_bhf(&bhf, &bdt_data, output_dir, overwrite)?;
+    if bhf_data.starts_with(bhf::BHF4_MAGIC) {
+        let bhf4 = load_bhf4(&bhf_data)?;
+        extract_bhf4(&bhf4, &bdt_data, output_dir, overwrite)?;
+    } else {
+        let bhf = load_bhf(&bhf_data)?;
+        extract_bhf(&bhf, &bdt_data, output_dir, overwrite)?;
+    }
     Ok(())
 }
 
@@ -56,7 +64,7 @@ pub fn get_bdt_for_bhf(bhf_path: &str) -> Option<path::PathBuf> {
 /// preserving directory structure.
 pub fn extract_bhf(
     bhf: &bhf::Bhf,
-    bdt_data: &Vec<u8>,
+    bdt_data: &[u8],
     output_dir: &str,
     overwrite: bool,
 ) -> Result<(), UnpackError> {
@@ -64,9 +72,9 @@ pub fn extract_bhf(
     utils_fs::ensure_dir_exists(output_dir)?;
     for file_info in &bhf.file_infos {
         // Extract all entries, print but ignore path errors.
-        match extract_bhf_entry(file_info, bdt_data, output_dir, overwrite) {
-            Err(UnpackError::Naming(e)) => { eprintln!("{}", e) }
-            _ => {}
+        let result = extract_bhf_entry(file_info, bdt_data, output_dir, overwrite);
+        if let Err(UnpackError::Naming(e)) = result {
+            eprintln!("{}", e);
         }
     }
     Ok(())
@@ -78,7 +86,7 @@ pub fn extract_bhf(
 /// The info struct must have a valid internal path.
 pub fn extract_bhf_entry(
     file_info: &bhf::BhfFileInfo,
-    bdt_data: &Vec<u8>,
+    bdt_data: &[u8],
     output_dir: &path::Path,
     overwrite: bool,
 ) -> Result<(), UnpackError> {
@@ -91,19 +99,77 @@ pub fn extract_bhf_entry(
     let data = &bdt_data[ofs_start..ofs_end];
 
     let internal_path = file_info.path.to_owned().unwrap();
-    let file_name = internal_path.trim_start_matches('\\');
+    write_bhf_entry(&internal_path, data, output_dir, overwrite)
+}
+
+/// Extract BHF4+BDT contents to disk.
+///
+/// Files are written in output_dir, creating it if needed, without
+/// preserving directory structure.
+pub fn extract_bhf4(
+    bhf4: &bhf::Bhf4,
+    bdt_data: &[u8],
+    output_dir: &str,
+    overwrite: bool,
+) -> Result<(), UnpackError> {
+    let output_dir = path::Path::new(output_dir);
+    utils_fs::ensure_dir_exists(output_dir)?;
+    for file_info in &bhf4.file_infos {
+        // Extract all entries, print but ignore path errors.
+        let result = extract_bhf4_entry(file_info, bdt_data, output_dir, overwrite);
+        if let Err(UnpackError::Naming(e)) = result {
+            eprintln!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Extract a file contained in a BHF4+BDT using its Bnd4FileInfo.
+///
+/// The info struct must have a valid internal path.
+pub fn extract_bhf4_entry(
+    file_info: &bnd4::Bnd4FileInfo,
+    bdt_data: &[u8],
+    output_dir: &path::Path,
+    overwrite: bool,
+) -> Result<(), UnpackError> {
+    let internal_path = match &file_info.path {
+        Some(p) => p,
+        None => return Err(UnpackError::Naming("No path for BHF4 entry.".to_owned())),
+    };
+
+    let ofs_start = file_info.ofs_data as usize;
+    let ofs_end = ofs_start + file_info.size as usize;
+    let data = bdt_data.get(ofs_start..ofs_end).ok_or_else(|| {
+        UnpackError::Parsing(format!("Entry {} is out of the BDT data.", internal_path))
+    })?;
+    write_bhf_entry(internal_path, data, output_dir, overwrite)
+}
+
+/// Write BHF entry data to disk, in output_dir.
+fn write_bhf_entry(
+    internal_path: &str,
+    data: &[u8],
+    output_dir: &path::Path,
+    overwrite: bool,
+) -> Result<(), UnpackError> {
     let mut file_path = output_dir.to_path_buf();
-    file_path.push(file_name);
+    file_path.push(get_entry_file_name(internal_path));
     if !overwrite && file_path.exists() {
         let existing = file_path.to_string_lossy();
         return Err(UnpackError::Naming(format!("File already exists: {}", existing)))
     }
 
     let mut output_file = fs::File::create(file_path)?;
-    output_file.write_all(&data)?;
+    output_file.write_all(data)?;
     Ok(())
 }
 
+/// Return the file name used on disk for this BHF internal path.
+pub fn get_entry_file_name(internal_path: &str) -> &str {
+    internal_path.trim_start_matches('\\')
+}
+
 /// Load a BHF file from disk.
 ///
 /// Wraps around `load_bhf` to load the BHF from disk.
@@ -114,13 +180,22 @@ pub fn load_bhf_file(bhf_path: &str) -> Result<bhf::Bhf, UnpackError> {
 
 /// Load a BHF file from a byte slice.
 pub fn load_bhf(bhf_data: &[u8]) -> Result<bhf::Bhf, UnpackError> {
-    match bhf::parse(&bhf_data) {
+    match bhf::parse(bhf_data) {
         Ok((_, bhf)) => Ok(bhf),
         Err(NomError(e)) | Err(NomFailure(e)) => Err(UnpackError::parsing_err("BHF", e.1)),
         e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
     }
 }
 
+/// Load a BHF4 file from a byte slice.
+pub fn load_bhf4(bhf4_data: &[u8]) -> Result<bhf::Bhf4, UnpackError> {
+    match bhf::parse_bhf4(bhf4_data) {
+        Ok((_, bhf4)) => Ok(bhf4),
+        Err(NomError(e)) | Err(NomFailure(e)) => Err(UnpackError::parsing_err("BHF4", e.1)),
+        e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,4 +215,72 @@ mod tests {
         assert!(get_bdt_for_bhf("").is_none());
         assert!(get_bdt_for_bhf("/map/m10/GI_Env_m10").is_none());
     }
+
+    /// Build a BHF4 with two entries, with IDs, names and uncompressed
+    /// sizes, and its BDT.
+    fn build_bhf4() -> (Vec<u8>, Vec<u8>) {
+        let mut bhf4 = vec!();
+        bhf4.extend_from_slice(b"BHF4\0\0\0\0\0\0\x01\0");
+        bhf4.extend_from_slice(&2u32.to_le_bytes());
+        bhf4.extend_from_slice(&0x40u64.to_le_bytes());
+        bhf4.extend_from_slice(b"07D7R6\0\0");
+        bhf4.extend_from_slice(&0x24u64.to_le_bytes());
+        bhf4.extend_from_slice(&0u64.to_le_bytes());
+        bhf4.extend_from_slice(&[0, 0x74, 0, 0, 0, 0, 0, 0]);
+        bhf4.extend_from_slice(&0u64.to_le_bytes());
+        let infos = [(4u64, 0x30u32, 1u32, 0x88u32), (6, 0x40, 2, 0x8E)];
+        for (size, ofs_data, id, ofs_path) in &infos {
+            bhf4.extend_from_slice(&[0x40, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+            bhf4.extend_from_slice(&size.to_le_bytes());
+            bhf4.extend_from_slice(&size.to_le_bytes());
+            bhf4.extend_from_slice(&ofs_data.to_le_bytes());
+            bhf4.extend_from_slice(&id.to_le_bytes());
+            bhf4.extend_from_slice(&ofs_path.to_le_bytes());
+        }
+        bhf4.extend_from_slice(b"a.bin\0\\b.bin\0");
+
+        let mut bdt = b"BDF4".to_vec();
+        bdt.resize(0x30, 0);
+        bdt.extend_from_slice(b"ABCD");
+        bdt.resize(0x40, 0);
+        bdt.extend_from_slice(b"EFGHIJ");
+        (bhf4, bdt)
+    }
+
+    #[test]
+    fn test_load_bhf4() {
+        let (bhf4_data, _) = build_bhf4();
+        let bhf4 = load_bhf4(&bhf4_data).unwrap();
+        assert_eq!(bhf4.header.num_files, 2);
+        assert_eq!(bhf4.file_infos[1].ofs_data, 0x40);
+        assert_eq!(bhf4.file_infos[1].id, 2);
+        assert_eq!(bhf4.file_infos[1].path.as_deref(), Some("\\b.bin"));
+
+        // The magic tells BHF4 and BND4 apart.
+        assert!(load_bhf4(&[b"BND4", &bhf4_data[4..]].concat()).is_err());
+        assert!(bnd4::parse(&bhf4_data).is_err());
+        assert!(load_bhf(&bhf4_data).is_err());
+    }
+
+    #[test]
+    fn test_extract_bhf4() {
+        let (bhf4_data, bdt_data) = build_bhf4();
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("rir-test-bhf4-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bhf_path = dir.join("test.bhd");
+        fs::write(&bhf_path, &bhf4_data).unwrap();
+        fs::write(dir.join("test.bdt"), &bdt_data).unwrap();
+
+        let output_dir = dir.join("output");
+        extract_bhf_file(bhf_path.to_str().unwrap(), output_dir.to_str().unwrap(), false).unwrap();
+        assert_eq!(fs::read(output_dir.join("a.bin")).unwrap(), b"ABCD");
+        assert_eq!(fs::read(output_dir.join("b.bin")).unwrap(), b"EFGHIJ");
+
+        // Entries out of the BDT are not extracted.
+        let bhf4 = load_bhf4(&bhf4_data).unwrap();
+        let result = extract_bhf4_entry(&bhf4.file_infos[1], &bdt_data[..0x42], &output_dir, true);
+        assert!(matches!(result, Err(UnpackError::Parsing(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
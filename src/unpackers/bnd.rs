@@ -5,26 +5,35 @@ use std::path;
 use nom::Err::{Error as NomError, Failure as NomFailure};
 
 use crate::formats::bnd;
+use crate::formats::bnd4;
+use crate::unpackers::bnd4::{extract_bnd4, load_bnd4};
 use crate::unpackers::dcx::load_dcx;
 use crate::unpackers::errors::UnpackError;
 use crate::utils::fs as utils_fs;
 
 /// Extract BND file contents to disk.
 ///
-/// Wraps around `extract_bnd` to load the BND from disk.
+/// Wraps around `extract_bnd` to load the BND from disk. BND4 files
+/// are detected by their magic and extracted with `extract_bnd4`.
 pub fn extract_bnd_file(
     bnd_path: &str,
     output_dir: &str,
     overwrite: bool,
     decompress: bool,
 ) -> Result<(), UnpackError> {
-    let (bnd, bnd_data) = if decompress {
+    let bnd_data = if decompress {
         let (_, decomp_data) = load_dcx(bnd_path)?;
-        (load_bnd(&decomp_data)?, decomp_data)
+        decomp_data
     } else {
-        load_bnd_file(bnd_path)?
+        utils_fs::open_file_to_vec(path::Path::new(bnd_path))?
     };
-    extract_bnd(&bnd, &bnd_data, output_dir, overwrite)?;
+    if bnd_data.starts_with(bnd4::MAGIC) {
+        let bnd4 = load_bnd4(&bnd_data)?;
+        extract_bnd4(&bnd4, &bnd_data, output_dir, overwrite)?;
+    } else {
+        let bnd = load_bnd(&bnd_data)?;
+        extract_bnd(&bnd, &bnd_data, output_dir, overwrite)?;
+    }
     Ok(())
 }
 
@@ -34,7 +43,7 @@ pub fn extract_bnd_file(
 /// it if needed, without preserving directory structure.
 pub fn extract_bnd(
     bnd: &bnd::Bnd,
-    bnd_data: &Vec<u8>,
+    bnd_data: &[u8],
     output_dir: &str,
     overwrite: bool
 ) -> Result<(), UnpackError> {
@@ -42,9 +51,9 @@ pub fn extract_bnd(
     utils_fs::ensure_dir_exists(output_dir)?;
     for file_info in &bnd.file_infos {
         // Extract all entries, print but ignore path errors.
-        match extract_bnd_entry(file_info, bnd_data, output_dir, overwrite) {
-            Err(UnpackError::Naming(e)) => eprintln!("{}", e),
-            _ => {}
+        let result = extract_bnd_entry(file_info, bnd_data, output_dir, overwrite);
+        if let Err(UnpackError::Naming(e)) = result {
+            eprintln!("{}", e);
         }
     }
     Ok(())
@@ -55,7 +64,7 @@ pub fn extract_bnd(
 /// The info struct must have a valid internal path.
 pub fn extract_bnd_entry(
     file_info: &bnd::BndFileInfo,
-    bnd_data: &Vec<u8>,
+    bnd_data: &[u8],
     output_dir: &path::Path,
     overwrite: bool,
 ) -> Result<(), UnpackError> {
@@ -68,24 +77,29 @@ pub fn extract_bnd_entry(
     let data = &bnd_data[ofs_start..ofs_end];
 
     let internal_path = file_info.path.to_owned().unwrap();
-    // For now, do not keep internal dir structure and use only file name.
-    let file_name = if let Some(last_sep_index) = internal_path.rfind('\\') {
-        &internal_path[last_sep_index as usize + 1usize..]
-    } else {
-        &internal_path
-    };
     let mut file_path = output_dir.to_path_buf();
-    file_path.push(file_name);
+    file_path.push(get_entry_file_name(&internal_path));
     if !overwrite && file_path.exists() {
         let existing = file_path.to_string_lossy();
         return Err(UnpackError::Naming(format!("File already exists: {}", existing)))
     }
 
     let mut output_file = fs::File::create(file_path)?;
-    output_file.write_all(&data)?;
+    output_file.write_all(data)?;
     Ok(())
 }
 
+/// Return the file name used on disk for this BND internal path.
+///
+/// For now, do not keep internal dir structure and use only file name.
+pub fn get_entry_file_name(internal_path: &str) -> &str {
+    if let Some(last_sep_index) = internal_path.rfind('\\') {
+        &internal_path[last_sep_index + 1usize..]
+    } else {
+        internal_path
+    }
+}
+
 /// Load a BND file from disk.
 ///
 /// Wraps around `load_bnd` to load the BND from disk. It returns the
@@ -103,3 +117,14 @@ pub fn load_bnd(bnd_data: &[u8]) -> Result<bnd::Bnd, UnpackError> {
         e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_entry_file_name() {
+        assert_eq!(get_entry_file_name("N:\\FRPG\\data\\c0000.flver"), "c0000.flver");
+        assert_eq!(get_entry_file_name("c0000.flver"), "c0000.flver");
+    }
+}
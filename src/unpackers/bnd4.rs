@@ -0,0 +1,83 @@
+use std::fs;
+use std::io::Write;
+use std::path;
+
+use nom::Err::{Error as NomError, Failure as NomFailure};
+
+use crate::formats::bnd4;
+use crate::unpackers::bnd::get_entry_file_name;
+use crate::unpackers::errors::UnpackError;
+use crate::utils::fs as utils_fs;
+
+/// Extract BND4 contents to disk.
+///
+/// Files in the BND4 are written in the output_dir directory, creating
+/// it if needed, without preserving directory structure.
+pub fn extract_bnd4(
+    bnd4: &bnd4::Bnd4,
+    bnd4_data: &[u8],
+    output_dir: &str,
+    overwrite: bool
+) -> Result<(), UnpackError> {
+    let output_dir = path::Path::new(output_dir);
+    utils_fs::ensure_dir_exists(output_dir)?;
+    for file_info in &bnd4.file_infos {
+        // Extract all entries, print but ignore path errors.
+        let result = extract_bnd4_entry(file_info, bnd4_data, output_dir, overwrite);
+        if let Err(UnpackError::Naming(e)) = result {
+            eprintln!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Extract a file contained in a BND4 using its Bnd4FileInfo.
+///
+/// The info struct must have a valid internal path.
+pub fn extract_bnd4_entry(
+    file_info: &bnd4::Bnd4FileInfo,
+    bnd4_data: &[u8],
+    output_dir: &path::Path,
+    overwrite: bool,
+) -> Result<(), UnpackError> {
+    let internal_path = match &file_info.path {
+        Some(p) => p,
+        None => return Err(UnpackError::Naming("No path for BND4 entry.".to_owned())),
+    };
+
+    let ofs_start = file_info.ofs_data as usize;
+    let data = ofs_start.checked_add(file_info.size as usize)
+        .and_then(|ofs_end| bnd4_data.get(ofs_start..ofs_end))
+        .ok_or_else(|| {
+            UnpackError::Parsing(format!("Entry {} is out of the BND4 data.", internal_path))
+        })?;
+
+    let mut file_path = output_dir.to_path_buf();
+    file_path.push(get_entry_file_name(internal_path));
+    if !overwrite && file_path.exists() {
+        let existing = file_path.to_string_lossy();
+        return Err(UnpackError::Naming(format!("File already exists: {}", existing)))
+    }
+
+    let mut output_file = fs::File::create(file_path)?;
+    output_file.write_all(data)?;
+    Ok(())
+}
+
+/// Load a BND4 file from disk.
+///
+/// Wraps around `load_bnd4` to load the BND4 from disk. It returns the
+/// parsed BND4 metadata and the whole file as a byte vector.
+pub fn load_bnd4_file(bnd4_path: &str) -> Result<(bnd4::Bnd4, Vec<u8>), UnpackError> {
+    let bnd4_data = utils_fs::open_file_to_vec(path::Path::new(bnd4_path))?;
+    Ok((load_bnd4(&bnd4_data)?, bnd4_data))
+}
+
+/// Load a BND4 file from a bytes slice.
+pub fn load_bnd4(bnd4_data: &[u8]) -> Result<bnd4::Bnd4, UnpackError> {
+    match bnd4::parse(bnd4_data) {
+        Ok((_, result)) => Ok(result),
+        Err(NomError(e)) | Err(NomFailure(e)) => Err(UnpackError::parsing_err("BND4", e.1)),
+        e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
+    }
+}
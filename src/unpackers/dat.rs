@@ -25,9 +25,8 @@ pub fn extract_dat(
     let output_dir = path::Path::new(output_path);
     utils_fs::ensure_dir_exists(output_dir)?;
     for file_entry in &dat.files {
-        match extract_file(file_entry, &dat_data, output_dir) {
-            Err(UnpackError::Io(e)) => eprintln!("Can't extract {}: {}", file_entry.name, e),
-            _ => {}
+        if let Err(UnpackError::Io(e)) = extract_file(file_entry, &dat_data, output_dir) {
+            eprintln!("Can't extract {}: {}", file_entry.name, e);
         }
     }
     Ok(())
@@ -36,7 +35,7 @@ pub fn extract_dat(
 /// Extract one `DatFileEntry`, preserving internal dir structure.
 fn extract_file(
     file_entry: &dat::DatFileEntry,
-    data: &Vec<u8>,
+    data: &[u8],
     output_dir: &path::Path
 ) -> Result<(), UnpackError> {
     let ofs_start = file_entry.ofs_data as usize;
@@ -55,7 +54,7 @@ fn extract_file(
     let mut file_path = output_dir.to_path_buf();
     file_path.push(internal_path);
     let mut output_file = fs::File::create(file_path)?;
-    output_file.write_all(&data)?;
+    output_file.write_all(data)?;
     Ok(())
 }
 
@@ -67,7 +66,7 @@ pub fn load_dat_file(dat_path: &str) -> Result<(dat::Dat, Vec<u8>), UnpackError>
 
 /// Load a DAT file from a bytes slice.
 pub fn load_dat(dat_data: &[u8]) -> Result<dat::Dat, UnpackError> {
-    match dat::parse(&dat_data) {
+    match dat::parse(dat_data) {
         Ok((_, dat)) => Ok(dat),
         Err(NomError(e)) | Err(NomFailure(e)) => Err(UnpackError::parsing_err("DAT", e.1)),
         e => Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
@@ -2,7 +2,7 @@ use std::fs;
 use std::io::{Read, Write};
 use std::path;
 
-use flate2::read::ZlibDecoder;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
 use nom::Err::{Error as NomError, Failure as NomFailure};
 
 use crate::formats::dcx;
@@ -21,7 +21,12 @@ pub fn extract_dcx(dcx_path: &str, output_path: &str) -> Result<(), UnpackError>
 pub fn load_dcx(dcx_path: &str) -> Result<(dcx::Dcx, Vec<u8>), UnpackError> {
     let dcx_path = path::Path::new(dcx_path);
     let dcx_data = utils_fs::open_file_to_vec(dcx_path)?;
-    let (data, dcx) = match dcx::parse(&dcx_data) {
+    load_dcx_data(&dcx_data)
+}
+
+/// Load DCX data along with its decompressed content.
+pub fn load_dcx_data(dcx_data: &[u8]) -> Result<(dcx::Dcx, Vec<u8>), UnpackError> {
+    let (data, dcx) = match dcx::parse(dcx_data) {
         Ok(result) => result,
         Err(NomError(e)) | Err(NomFailure(e)) => return Err(UnpackError::parsing_err("DCX", e.1)),
         e => return Err(UnpackError::Unknown(format!("Unknown error: {:?}", e))),
@@ -31,10 +36,15 @@ pub fn load_dcx(dcx_path: &str) -> Result<(dcx::Dcx, Vec<u8>), UnpackError> {
     Ok((dcx, decomp_data))
 }
 
-fn decompress_dcx(dcx: &dcx::Dcx, comp_data: &[u8]) -> Result<Vec<u8>, UnpackError> {
+/// Decompress data using DCX params.
+pub fn decompress_dcx(dcx: &dcx::Dcx, comp_data: &[u8]) -> Result<Vec<u8>, UnpackError> {
     let method: &[u8] = dcx.params.method.as_slice();
     if method == b"DFLT" {
         decompress_dcx_dflt(dcx, comp_data)
+    } else if method == b"EDGE" {
+        decompress_dcx_edge(dcx, comp_data)
+    } else if method == b"ZSTD" {
+        decompress_dcx_zstd(dcx, comp_data)
     } else {
         let method_string = String::from_utf8_lossy(method).to_string();
         Err(UnpackError::Compression(format!("Unknown method: {}", method_string)))
@@ -48,6 +58,43 @@ fn decompress_dcx_dflt(dcx: &dcx::Dcx, comp_data: &[u8]) -> Result<Vec<u8>, Unpa
     Ok(data)
 }
 
+fn decompress_dcx_edge(dcx: &dcx::Dcx, comp_data: &[u8]) -> Result<Vec<u8>, UnpackError> {
+    let edge_table = dcx.edge_table.as_ref()
+        .ok_or_else(|| UnpackError::Compression("Missing EDGE chunk table.".to_owned()))?;
+    let mut data = Vec::with_capacity(dcx.sizes.uncompressed_size as usize);
+    for chunk_info in &edge_table.chunk_infos {
+        let ofs_start = chunk_info.offset as usize;
+        let ofs_end = ofs_start + chunk_info.size as usize;
+        if ofs_end > comp_data.len() {
+            return Err(UnpackError::Compression(format!("Invalid EDGE chunk at {:X}.", ofs_start)))
+        }
+        let chunk_data = &comp_data[ofs_start..ofs_end];
+        if chunk_info.is_compressed() {
+            let mut deflater = DeflateDecoder::new(chunk_data);
+            deflater.read_to_end(&mut data)?;
+        } else {
+            data.extend_from_slice(chunk_data);
+        }
+    }
+    if data.len() != dcx.sizes.uncompressed_size as usize {
+        return Err(UnpackError::Compression(format!(
+            "EDGE data has size {:X} instead of {:X}.", data.len(), dcx.sizes.uncompressed_size
+        )))
+    }
+    Ok(data)
+}
+
+fn decompress_dcx_zstd(dcx: &dcx::Dcx, comp_data: &[u8]) -> Result<Vec<u8>, UnpackError> {
+    let comp_data = &comp_data[..(dcx.sizes.compressed_size as usize).min(comp_data.len())];
+    let data = zstd::stream::decode_all(comp_data)?;
+    if data.len() != dcx.sizes.uncompressed_size as usize {
+        return Err(UnpackError::Compression(format!(
+            "ZSTD data has size {:X} instead of {:X}.", data.len(), dcx.sizes.uncompressed_size
+        )))
+    }
+    Ok(data)
+}
+
 /// Get a decompressed path for this file in this path.
 ///
 /// If the path is some valid file path (existing or not), use it.
@@ -6,6 +6,7 @@ pub enum UnpackError {
     Parsing(String),
     Compression(String),
     Naming(String),
+    Decryption(String),
     Unknown(String),
 }
 
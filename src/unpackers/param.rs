@@ -1,3 +1,5 @@
+use std::fs;
+use std::io;
 use std::path;
 
 use nom::Err::{Error as NomError, Failure as NomFailure};
@@ -15,17 +17,47 @@ pub fn load_param_file(
     paramdef: Option<&paramdef::Paramdef>
 ) -> Result<param::Param, UnpackError> {
     let param_data = utils_fs::open_file_to_vec(path::Path::new(param_path))?;
-    Ok(load_param(&param_data, paramdef)?)
+    load_param(&param_data, paramdef)
 }
 
 /// Load a PARAM from a byte slice.
 ///
-/// If paramdef is provided, it copies the right amount of bytes into
-/// row data, without parsing them. Else it loads the PARAM with
-/// empty row data.
+/// If paramdef is provided, row data is parsed into values. Else it
+/// loads the PARAM with empty row data. Raw row data is always loaded.
 pub fn load_param(
     param_data: &[u8],
     paramdef: Option<&paramdef::Paramdef>
+) -> Result<param::Param, UnpackError> {
+    if let Some(def) = paramdef {
+        if let Err(e) = def.get_layout() {
+            return Err(UnpackError::Parsing(format!("Invalid PARAMDEF: {}", e)))
+        }
+        check_row_size(&parse_param(param_data, None)?, def)?;
+    }
+    parse_param(param_data, paramdef)
+}
+
+/// Check that PARAMDEF rows fit in the PARAM rows.
+///
+/// A larger row size would parse data of other rows, usually because
+/// the PARAMDEF is meant for another version of the PARAM.
+fn check_row_size(param: &param::Param, paramdef: &paramdef::Paramdef) -> Result<(), UnpackError> {
+    match param.max_row_size() {
+        Some(max_row_size) if paramdef.row_size() > max_row_size => {
+            Err(UnpackError::Parsing(format!(
+                "PARAMDEF row size {} (version {}) is larger than PARAM rows of {} bytes \
+                 (version {}).",
+                paramdef.row_size(), paramdef.header.data_version,
+                max_row_size, param.header.paramdef_data_version
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_param(
+    param_data: &[u8],
+    paramdef: Option<&paramdef::Paramdef>
 ) -> Result<param::Param, UnpackError> {
     match param::parse(param_data, paramdef) {
         Ok((_, result)) => Ok(result),
@@ -39,7 +71,7 @@ pub fn print_param(param: &param::Param) {
     println!("{}", param);
     for row in &param.rows {
         println!("  - {}", row);
-        if row.data.len() > 0 {
+        if !row.data.is_empty() {
             println!("    {:?}", row.data);
         }
     }
@@ -57,3 +89,40 @@ pub fn print_param_with_def(param: &param::Param, paramdef: &paramdef::Paramdef)
         println!("{}", desc.as_str());
     }
 }
+
+/// Return the CSV column name for this field: its internal name without
+/// bit size or array length, or its display name.
+pub fn get_field_column_name(field: &paramdef::ParamdefField) -> String {
+    field.column_name()
+}
+
+/// Export a PARAM to a CSV file, see `export_param_csv`.
+pub fn export_param_csv_file(
+    param: &param::Param,
+    paramdef: &paramdef::Paramdef,
+    output_path: &str,
+) -> Result<(), UnpackError> {
+    let output_file = fs::File::create(output_path)?;
+    export_param_csv(param, paramdef, output_file)
+}
+
+/// Export PARAM rows as CSV, with ID, name and a column for each field.
+///
+/// The PARAM must have been loaded with this PARAMDEF.
+pub fn export_param_csv(
+    param: &param::Param,
+    paramdef: &paramdef::Paramdef,
+    output: impl io::Write,
+) -> Result<(), UnpackError> {
+    let mut writer = csv::Writer::from_writer(output);
+    let mut columns = vec!["ID".to_string(), "Name".to_string()];
+    columns.extend(paramdef.fields.iter().map(get_field_column_name));
+    writer.write_record(&columns).map_err(io::Error::from)?;
+    for row in &param.rows {
+        let mut record = vec![row.id.to_string(), row.name.clone().unwrap_or_default()];
+        record.extend(row.data.iter().map(|v| v.to_value_string()));
+        writer.write_record(&record).map_err(io::Error::from)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
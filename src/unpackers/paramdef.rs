@@ -1,7 +1,10 @@
+use std::fs;
+use std::io;
 use std::path;
 
 use nom::Err::{Error as NomError, Failure as NomFailure};
 
+use crate::formats::common::VarSizeInt;
 use crate::formats::paramdef;
 use crate::unpackers::errors::UnpackError;
 use crate::utils::fs as utils_fs;
@@ -11,7 +14,7 @@ use crate::utils::fs as utils_fs;
 /// Wraps around `load_paramdef` to load the PARAMDEF from disk.
 pub fn load_paramdef_file(paramdef_path: &str) -> Result<paramdef::Paramdef, UnpackError> {
     let paramdef_data = utils_fs::open_file_to_vec(path::Path::new(paramdef_path))?;
-    Ok(load_paramdef(&paramdef_data)?)
+    load_paramdef(&paramdef_data)
 }
 
 /// Load a PARAMDEF file from a byte slice.
@@ -23,6 +26,273 @@ pub fn load_paramdef(paramdef_data: &[u8]) -> Result<paramdef::Paramdef, UnpackE
     }
 }
 
+/// Load a PARAMDEF from an XML layout file.
+///
+/// Wraps around `load_paramdef_xml` to load the XML from disk.
+pub fn load_paramdef_xml_file(xml_path: &str) -> Result<paramdef::Paramdef, UnpackError> {
+    let xml = fs::read_to_string(xml_path)?;
+    load_paramdef_xml(&xml)
+}
+
+/// Load a PARAMDEF from an XML layout, as used by Paramdex.
+///
+/// The XML only describes the layout: the header sizes and offsets
+/// that only make sense in a binary PARAMDEF are left to 0.
+pub fn load_paramdef_xml(xml: &str) -> Result<paramdef::Paramdef, UnpackError> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| UnpackError::Parsing(format!("PARAMDEF XML parsing failed: {}", e)))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "PARAMDEF" {
+        return Err(xml_error("root element is not PARAMDEF"))
+    }
+
+    let param_name = get_child_text(root, "ParamType").unwrap_or("").to_string();
+    let data_version = parse_child(root, "DataVersion")?.unwrap_or(0);
+    let big_endian = parse_child_bool(root, "BigEndian")?.unwrap_or(false);
+    let unicode = parse_child_bool(root, "Unicode")?.unwrap_or(false);
+    let format_version = parse_child(root, "FormatVersion")?.unwrap_or(0);
+
+    let fields_node = root.children().find(|n| n.has_tag_name("Fields"))
+        .ok_or_else(|| xml_error("no Fields element"))?;
+    let fields = fields_node.children()
+        .filter(|n| n.has_tag_name("Field"))
+        .map(parse_xml_field)
+        .collect::<Result<Vec<paramdef::ParamdefField>, UnpackError>>()?;
+
+    let header = paramdef::ParamdefHeader {
+        file_size: 0,
+        header_size: 0,
+        data_version,
+        num_fields: fields.len() as u16,
+        field_size: 0,
+        param_name,
+        endianness: if big_endian { 0xFF } else { 0x00 },
+        unicode: unicode as u8,
+        format_version,
+        ofs_fields: 0,
+    };
+    Ok(paramdef::Paramdef { header, fields })
+}
+
+fn xml_error(message: &str) -> UnpackError {
+    UnpackError::Parsing(format!("PARAMDEF XML parsing failed: {}", message))
+}
+
+fn get_child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|n| n.has_tag_name(name)).and_then(|n| n.text()).map(|t| t.trim())
+}
+
+fn parse_child<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    name: &str,
+) -> Result<Option<T>, UnpackError> {
+    match get_child_text(node, name) {
+        Some(text) => text.parse::<T>().map(Some)
+            .map_err(|_| xml_error(&format!("invalid {} value \"{}\"", name, text))),
+        None => Ok(None),
+    }
+}
+
+fn parse_child_bool(node: roxmltree::Node, name: &str) -> Result<Option<bool>, UnpackError> {
+    match get_child_text(node, name) {
+        Some(text) => text.to_lowercase().parse::<bool>().map(Some)
+            .map_err(|_| xml_error(&format!("invalid {} value \"{}\"", name, text))),
+        None => Ok(None),
+    }
+}
+
+/// Parse a Field element, described mostly by its Def attribute, e.g.
+/// "u8 isDummy:1", "fixstr name[32]" or "f32 speed = 1.5".
+fn parse_xml_field(node: roxmltree::Node) -> Result<paramdef::ParamdefField, UnpackError> {
+    let def = node.attribute("Def").ok_or_else(|| xml_error("field without Def"))?;
+    let invalid_def = || xml_error(&format!("invalid field definition \"{}\"", def));
+    let (decl, default_str) = match def.find('=') {
+        Some(index) => (&def[..index], Some(def[index + 1..].trim())),
+        None => (def, None),
+    };
+    let mut decl_parts = decl.split_whitespace();
+    let display_type = decl_parts.next().ok_or_else(invalid_def)?.to_string();
+    let internal_name = decl_parts.collect::<Vec<&str>>().join("");
+    if internal_name.is_empty() {
+        return Err(invalid_def())
+    }
+    let name = internal_name.split([':', '[']).next().unwrap_or("").to_string();
+    let array_len = match internal_name.find('[') {
+        Some(index) => internal_name[index + 1..].trim_end_matches(']').parse::<u32>()
+            .map_err(|_| invalid_def())?,
+        None => 1,
+    };
+    let type_size = paramdef::get_type_size(&display_type).ok_or_else(|| {
+        xml_error(&format!("unknown type \"{}\" for field {}", display_type, name))
+    })?;
+    let default_value = match default_str {
+        Some(d) => d.parse::<f32>().map_err(|_| invalid_def())?,
+        None => parse_child(node, "Default")?.unwrap_or(0.0),
+    };
+    let (type_min, type_max, type_increment) = get_type_limits(&display_type);
+    let is_float = ["f32", "f64", "angle32"].contains(&display_type.as_str());
+
+    Ok(paramdef::ParamdefField {
+        display_name: get_child_text(node, "DisplayName").unwrap_or(&name).to_string(),
+        internal_type: get_child_text(node, "Enum").unwrap_or(&display_type).to_string(),
+        display_format: get_child_text(node, "DisplayFormat")
+            .unwrap_or(if is_float { "%f" } else { "%d" })
+            .to_string(),
+        display_type,
+        default_value,
+        min_value: parse_child(node, "Minimum")?.unwrap_or(type_min),
+        max_value: parse_child(node, "Maximum")?.unwrap_or(type_max),
+        increment: parse_child(node, "Increment")?.unwrap_or(type_increment),
+        edit_flags: parse_edit_flags(get_child_text(node, "EditFlags").unwrap_or(""))?,
+        byte_count: type_size * array_len,
+        ofs_desc: VarSizeInt { vu64: 0 },
+        internal_name: Some(internal_name),
+        sort_id: parse_child(node, "SortID")?.unwrap_or(0),
+        description: get_child_text(node, "Description").map(|d| d.to_string()),
+    })
+}
+
+/// Return default minimum, maximum and increment for a type.
+fn get_type_limits(type_str: &str) -> (f32, f32, f32) {
+    match type_str {
+        "s8" => (i8::MIN as f32, i8::MAX as f32, 1.0),
+        "u8" => (0.0, u8::MAX as f32, 1.0),
+        "s16" => (i16::MIN as f32, i16::MAX as f32, 1.0),
+        "u16" => (0.0, u16::MAX as f32, 1.0),
+        "s32" => (i32::MIN as f32, i32::MAX as f32, 1.0),
+        "u32" => (0.0, u32::MAX as f32, 1.0),
+        "b32" => (0.0, 1.0, 1.0),
+        "f32" | "f64" | "angle32" => (f32::MIN, f32::MAX, 0.01),
+        _ => (0.0, 0.0, 0.0),
+    }
+}
+
+/// Parse EditFlags text, like "Wrap, Lock".
+fn parse_edit_flags(text: &str) -> Result<u32, UnpackError> {
+    let mut flags = 0;
+    for flag in text.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+        flags |= match flag {
+            "None" => 0,
+            "Wrap" => paramdef::EDIT_FLAG_WRAP,
+            "Lock" => paramdef::EDIT_FLAG_LOCK,
+            f => return Err(xml_error(&format!("unknown edit flag \"{}\"", f))),
+        };
+    }
+    Ok(flags)
+}
+
+/// Export a PARAMDEF to an XML file, see `export_paramdef_xml`.
+pub fn export_paramdef_xml_file(
+    paramdef: &paramdef::Paramdef,
+    output_path: &str,
+) -> Result<(), UnpackError> {
+    let output_file = fs::File::create(output_path)?;
+    export_paramdef_xml(paramdef, io::BufWriter::new(output_file))
+}
+
+/// Export a PARAMDEF to XML, in the format read by `load_paramdef_xml`.
+///
+/// Values matching the defaults of the XML loader are omitted, and
+/// array lengths are added to internal names if missing. Fields without
+/// internal name use their display name. Edit flags other than Wrap and
+/// Lock are lost.
+pub fn export_paramdef_xml(
+    paramdef: &paramdef::Paramdef,
+    mut output: impl io::Write,
+) -> Result<(), UnpackError> {
+    let header = &paramdef.header;
+    writeln!(output, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(output, r#"<PARAMDEF XmlVersion="3">"#)?;
+    writeln!(output, "  <ParamType>{}</ParamType>", escape_xml(&header.param_name))?;
+    writeln!(output, "  <DataVersion>{}</DataVersion>", header.data_version)?;
+    writeln!(output, "  <BigEndian>{}</BigEndian>", to_xml_bool(header.use_be()))?;
+    writeln!(output, "  <Unicode>{}</Unicode>", to_xml_bool(header.unicode != 0))?;
+    writeln!(output, "  <FormatVersion>{}</FormatVersion>", header.format_version)?;
+    writeln!(output, "  <Fields>")?;
+    for field in &paramdef.fields {
+        write_xml_field(&mut output, field)?;
+    }
+    writeln!(output, "  </Fields>")?;
+    writeln!(output, "</PARAMDEF>")?;
+    Ok(())
+}
+
+fn write_xml_field(
+    output: &mut impl io::Write,
+    field: &paramdef::ParamdefField,
+) -> Result<(), io::Error> {
+    let display_type = &field.display_type;
+    let mut internal_name: String = match &field.internal_name {
+        Some(name) => name.clone(),
+        None => field.display_name.split_whitespace().collect(),
+    };
+    let type_size = paramdef::get_type_size(display_type).unwrap_or(1);
+    if field.byte_count > type_size && !internal_name.contains('[') {
+        internal_name.push_str(&format!("[{}]", field.byte_count / type_size));
+    }
+    let mut def = format!("{} {}", display_type, internal_name);
+    if field.default_value != 0.0 {
+        def.push_str(&format!(" = {}", field.default_value));
+    }
+
+    let name = field.column_name();
+    let (type_min, type_max, type_increment) = get_type_limits(display_type);
+    let is_float = ["f32", "f64", "angle32"].contains(&display_type.as_str());
+    let known_flags = [(paramdef::EDIT_FLAG_WRAP, "Wrap"), (paramdef::EDIT_FLAG_LOCK, "Lock")];
+    let edit_flags: Vec<&str> = known_flags.iter()
+        .filter(|(flag, _)| field.edit_flags & flag != 0)
+        .map(|(_, flag_name)| *flag_name)
+        .collect();
+    let mut children: Vec<(&str, String)> = vec!();
+    if field.display_name != name {
+        children.push(("DisplayName", field.display_name.clone()));
+    }
+    if field.internal_type != *display_type {
+        children.push(("Enum", field.internal_type.clone()));
+    }
+    if let Some(description) = &field.description {
+        children.push(("Description", description.clone()));
+    }
+    if field.display_format != if is_float { "%f" } else { "%d" } {
+        children.push(("DisplayFormat", field.display_format.clone()));
+    }
+    if field.min_value != type_min {
+        children.push(("Minimum", field.min_value.to_string()));
+    }
+    if field.max_value != type_max {
+        children.push(("Maximum", field.max_value.to_string()));
+    }
+    if field.increment != type_increment {
+        children.push(("Increment", field.increment.to_string()));
+    }
+    if !edit_flags.is_empty() {
+        children.push(("EditFlags", edit_flags.join(", ")));
+    }
+    if field.sort_id != 0 {
+        children.push(("SortID", field.sort_id.to_string()));
+    }
+
+    if children.is_empty() {
+        return writeln!(output, r#"    <Field Def="{}" />"#, escape_xml(&def))
+    }
+    writeln!(output, r#"    <Field Def="{}">"#, escape_xml(&def))?;
+    for (tag, text) in children {
+        writeln!(output, "      <{}>{}</{}>", tag, escape_xml(&text), tag)?;
+    }
+    writeln!(output, "    </Field>")
+}
+
+fn to_xml_bool(b: bool) -> &'static str {
+    if b { "True" } else { "False" }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 /// Print verbose data about a PARAMDEF.
 pub fn print_paramdef(paramdef: &paramdef::Paramdef) {
     println!("{}", paramdef);
@@ -40,3 +310,64 @@ pub fn print_paramdef(paramdef: &paramdef::Paramdef) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<PARAMDEF XmlVersion="3">
+  <ParamType>TEST_PARAM_ST</ParamType>
+  <DataVersion>2</DataVersion>
+  <BigEndian>False</BigEndian>
+  <Unicode>True</Unicode>
+  <FormatVersion>203</FormatVersion>
+  <Fields>
+    <Field Def="s16 a">
+      <DisplayName>A value</DisplayName>
+      <Description>First value.</Description>
+      <Minimum>-1</Minimum>
+      <Maximum>100</Maximum>
+      <EditFlags>Wrap, Lock</EditFlags>
+      <SortID>10</SortID>
+    </Field>
+    <Field Def="u8 b:1" />
+    <Field Def="u8 c: 7" />
+    <Field Def="f32 d = 1.5" />
+    <Field Def="fixstrW name[8]" />
+    <Field Def="dummy8 pad[2]" />
+  </Fields>
+</PARAMDEF>"#;
+
+    #[test]
+    fn test_load_paramdef_xml() {
+        let paramdef = load_paramdef_xml(XML).unwrap();
+        assert_eq!(paramdef.header.param_name, "TEST_PARAM_ST");
+        assert_eq!(paramdef.header.data_version, 2);
+        assert_eq!(paramdef.header.unicode, 1);
+        assert_eq!(paramdef.header.format_version, 203);
+        assert_eq!(paramdef.header.num_fields, 6);
+        assert_eq!(paramdef.row_size(), 2 + 1 + 4 + 16 + 2);
+
+        let a = &paramdef.fields[0];
+        assert_eq!(a.display_name, "A value");
+        assert_eq!(a.description.as_deref(), Some("First value."));
+        assert_eq!((a.min_value, a.max_value, a.default_value), (-1.0, 100.0, 0.0));
+        assert_eq!(a.edit_flags, paramdef::EDIT_FLAG_WRAP | paramdef::EDIT_FLAG_LOCK);
+        assert_eq!(a.sort_id, 10);
+        assert_eq!(paramdef.fields[1].bit_size(), 1);
+        assert_eq!(paramdef.fields[2].bit_size(), 7);
+        assert_eq!(paramdef.fields[2].internal_name.as_deref(), Some("c:7"));
+        assert_eq!(paramdef.fields[3].default_value, 1.5);
+        assert_eq!(paramdef.fields[4].byte_count, 16);
+        assert_eq!(paramdef.fields[5].display_name, "pad");
+    }
+
+    #[test]
+    fn test_load_paramdef_xml_errors() {
+        assert!(load_paramdef_xml("<PARAM />").is_err());
+        assert!(load_paramdef_xml(&XML.replace("f32 d", "f33 d")).is_err());
+        assert!(load_paramdef_xml(&XML.replace("[8]", "[x]")).is_err());
+        assert!(load_paramdef_xml(&XML.replace("<DataVersion>2", "<DataVersion>z")).is_err());
+    }
+}
@@ -16,6 +16,21 @@ pub fn pad(ofs: usize, alignment: usize) -> usize {
     (alignment - (ofs % alignment)) % alignment
 }
 
+/// Return the bytes of a u16 in the requested byte order.
+pub fn u16_to_bytes(value: u16, big_endian: bool) -> [u8; 2] {
+    if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+}
+
+/// Return the bytes of a u32 in the requested byte order.
+pub fn u32_to_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+    if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+}
+
+/// Return the bytes of a u64 in the requested byte order.
+pub fn u64_to_bytes(value: u64, big_endian: bool) -> [u8; 8] {
+    if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -47,4 +62,12 @@ mod test {
         assert_eq!(pad(16, 16), 0);
         assert_eq!(pad(17, 16), 15);
     }
+
+    #[test]
+    fn test_u32_to_bytes() {
+        assert_eq!(u32_to_bytes(0x12345678, false), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(u32_to_bytes(0x12345678, true), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(u16_to_bytes(0x1234, false), [0x34, 0x12]);
+        assert_eq!(u64_to_bytes(0x1234, true), [0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+    }
 }
@@ -0,0 +1,257 @@
+use std::fs;
+
+use aes::{Aes128, Aes256};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use nom::IResult;
+use nom::bytes::complete::take;
+use nom::number::complete::be_u8;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_SEQUENCE: u8 = 0x30;
+
+/// RSA public key, as used to "decrypt" encrypted archive headers.
+#[derive(Debug)]
+pub struct RsaPublicKey {
+    pub modulus: BigUint,
+    pub exponent: BigUint,
+}
+
+impl RsaPublicKey {
+    /// Return the key size in bytes, which is the encrypted block size.
+    pub fn size(&self) -> usize {
+        self.modulus.bits().div_ceil(8)
+    }
+}
+
+/// Load an RSA public key from a PEM file.
+pub fn load_rsa_public_key_file(pem_path: &str) -> Result<RsaPublicKey, String> {
+    let pem = fs::read_to_string(pem_path).map_err(|e| format!("{}", e))?;
+    load_rsa_public_key(&pem)
+}
+
+/// Load an RSA public key from a PEM string.
+///
+/// Both PKCS#1 ("RSA PUBLIC KEY") and SubjectPublicKeyInfo ("PUBLIC
+/// KEY") formats are supported.
+pub fn load_rsa_public_key(pem: &str) -> Result<RsaPublicKey, String> {
+    let b64: String = pem.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with("-----"))
+        .collect();
+    let der = BASE64.decode(&b64).map_err(|e| format!("Invalid PEM: {}", e))?;
+    let key = if pem.contains("BEGIN RSA PUBLIC KEY") {
+        parse_pkcs1_public_key(&der)
+    } else {
+        parse_spki_public_key(&der)
+    };
+    match key {
+        // A null modulus has no block size and can't be used for decryption.
+        Ok((_, key)) if key.size() == 0 => Err("Invalid RSA public key modulus.".to_owned()),
+        Ok((_, key)) => Ok(key),
+        Err(_) => Err("Invalid RSA public key.".to_owned()),
+    }
+}
+
+/// Parse a DER element, returning its tag and content.
+fn parse_der(i: &[u8]) -> IResult<&[u8], (u8, &[u8])> {
+    let (i, tag) = be_u8(i)?;
+    let (i, len) = be_u8(i)?;
+    let (i, len) = if len & 0x80 == 0 {
+        (i, len as usize)
+    } else {
+        let (i, len_bytes) = take((len & 0x7F) as usize)(i)?;
+        (i, len_bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize))
+    };
+    let (i, content) = take(len)(i)?;
+    Ok((i, (tag, content)))
+}
+
+/// Parse a DER element, failing if it does not have this tag.
+fn parse_der_tag(i: &[u8], expected: u8) -> IResult<&[u8], &[u8]> {
+    let (rest, (tag, content)) = parse_der(i)?;
+    if tag != expected {
+        return Err(nom::Err::Error((i, nom::error::ErrorKind::Tag)))
+    }
+    Ok((rest, content))
+}
+
+fn parse_pkcs1_public_key(i: &[u8]) -> IResult<&[u8], RsaPublicKey> {
+    let (rest, seq) = parse_der_tag(i, DER_SEQUENCE)?;
+    let (seq, modulus) = parse_der_tag(seq, DER_INTEGER)?;
+    let (_, exponent) = parse_der_tag(seq, DER_INTEGER)?;
+    Ok((
+        rest,
+        RsaPublicKey {
+            modulus: BigUint::from_bytes_be(modulus),
+            exponent: BigUint::from_bytes_be(exponent),
+        }
+    ))
+}
+
+fn parse_spki_public_key(i: &[u8]) -> IResult<&[u8], RsaPublicKey> {
+    let (rest, seq) = parse_der_tag(i, DER_SEQUENCE)?;
+    let (seq, _algorithm) = parse_der_tag(seq, DER_SEQUENCE)?;
+    let (_, bit_string) = parse_der_tag(seq, DER_BIT_STRING)?;
+    // Skip the unused bits count.
+    let key_data = bit_string.get(1..)
+        .ok_or(nom::Err::Error((bit_string, nom::error::ErrorKind::Eof)))?;
+    let (_, key) = parse_pkcs1_public_key(key_data)?;
+    Ok((rest, key))
+}
+
+/// Decrypt data encrypted block per block with the RSA private key.
+///
+/// Each input block has the size of the key, and is decrypted in a
+/// block one byte shorter, left-padded with zeros.
+pub fn decrypt_rsa(data: &[u8], key: &RsaPublicKey) -> Vec<u8> {
+    let input_block_size = key.size();
+    let output_block_size = input_block_size - 1;
+    let mut output = Vec::with_capacity(data.len());
+    for block in data.chunks(input_block_size) {
+        let value = BigUint::from_bytes_be(block).modpow(&key.exponent, &key.modulus);
+        let bytes = value.to_bytes_be();
+        if bytes.len() < output_block_size {
+            output.resize(output.len() + output_block_size - bytes.len(), 0);
+        }
+        output.extend_from_slice(&bytes);
+    }
+    output
+}
+
+/// AES block size in bytes.
+pub const AES_BLOCK_SIZE: usize = 0x10;
+
+/// Decrypt data in place with AES-128 in ECB mode.
+///
+/// Only full blocks are decrypted, a trailing partial block is left as is.
+pub fn decrypt_aes_ecb(data: &mut [u8], key: &[u8]) {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+}
+
+/// Encrypt data in place with AES-128 in ECB mode, see `decrypt_aes_ecb`.
+pub fn encrypt_aes_ecb(data: &mut [u8], key: &[u8]) {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+}
+
+/// Decrypt data in place with AES-256 in CBC mode.
+///
+/// Only full blocks are decrypted, a trailing partial block is left as is.
+pub fn decrypt_aes_cbc(data: &mut [u8], key: &[u8], iv: &[u8]) {
+    let cipher = Aes256::new(GenericArray::from_slice(key));
+    let mut previous = [0u8; AES_BLOCK_SIZE];
+    previous.copy_from_slice(iv);
+    for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+        let mut encrypted = [0u8; AES_BLOCK_SIZE];
+        encrypted.copy_from_slice(block);
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        block.iter_mut().zip(previous.iter()).for_each(|(b, p)| *b ^= p);
+        previous = encrypted;
+    }
+}
+
+/// Encrypt data in place with AES-256 in CBC mode, see `decrypt_aes_cbc`.
+pub fn encrypt_aes_cbc(data: &mut [u8], key: &[u8], iv: &[u8]) {
+    let cipher = Aes256::new(GenericArray::from_slice(key));
+    let mut previous = [0u8; AES_BLOCK_SIZE];
+    previous.copy_from_slice(iv);
+    for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+        block.iter_mut().zip(previous.iter()).for_each(|(b, p)| *b ^= p);
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        previous.copy_from_slice(block);
+    }
+}
+
+/// Return the SHA-256 digest of data.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Toy key with n = 61 * 53 and e = 17; private exponent is 2753.
+    const PKCS1_PEM: &str =
+        "-----BEGIN RSA PUBLIC KEY-----\nMAcCAgyhAgER\n-----END RSA PUBLIC KEY-----\n";
+    const SPKI_PEM: &str = "-----BEGIN PUBLIC KEY-----\n\
+        MBswDQYJKoZIhvcNAQEBBQADCgAwBwICDKECARE=\n\
+        -----END PUBLIC KEY-----\n";
+
+    #[test]
+    fn test_load_rsa_public_key() {
+        for pem in &[PKCS1_PEM, SPKI_PEM] {
+            let key = load_rsa_public_key(pem).unwrap();
+            assert_eq!(key.modulus, BigUint::from(3233u32));
+            assert_eq!(key.exponent, BigUint::from(17u32));
+            assert_eq!(key.size(), 2);
+        }
+        assert!(load_rsa_public_key("-----BEGIN PUBLIC KEY-----\nAAAA\n").is_err());
+        // Empty bit string.
+        assert_eq!(
+            load_rsa_public_key("-----BEGIN PUBLIC KEY-----\nMAQwAAMA\n").unwrap_err(),
+            "Invalid RSA public key."
+        );
+        // Null and empty moduli.
+        assert!(load_rsa_public_key("-----BEGIN RSA PUBLIC KEY-----\nMAYCAQACARE=\n").is_err());
+        assert!(load_rsa_public_key("-----BEGIN RSA PUBLIC KEY-----\nMAUCAAIBEQ==\n").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_rsa() {
+        let key = load_rsa_public_key(PKCS1_PEM).unwrap();
+        // 65 and 66 encrypted with the private exponent.
+        let data = [(588u16).to_be_bytes(), (2215u16).to_be_bytes()].concat();
+        assert_eq!(decrypt_rsa(&data, &key), vec![65, 66]);
+    }
+
+    #[test]
+    fn test_aes_ecb() {
+        // FIPS-197 AES-128 example vector.
+        let key: Vec<u8> = (0..16).collect();
+        let mut data: Vec<u8> = (0..16).map(|b| b * 0x11).collect();
+        data.extend_from_slice(b"tail");
+        encrypt_aes_ecb(&mut data, &key);
+        assert_eq!(&data[..4], &[0x69, 0xC4, 0xE0, 0xD8]);
+        assert_eq!(&data[16..], b"tail");
+        decrypt_aes_ecb(&mut data, &key);
+        assert_eq!(data[15], 0xFF);
+    }
+
+    #[test]
+    fn test_aes_cbc() {
+        // NIST SP 800-38A CBC-AES256 example vector.
+        let key = [
+            0x60, 0x3D, 0xEB, 0x10, 0x15, 0xCA, 0x71, 0xBE, 0x2B, 0x73, 0xAE, 0xF0, 0x85, 0x7D,
+            0x77, 0x81, 0x1F, 0x35, 0x2C, 0x07, 0x3B, 0x61, 0x08, 0xD7, 0x2D, 0x98, 0x10, 0xA3,
+            0x09, 0x14, 0xDF, 0xF4,
+        ];
+        let iv: Vec<u8> = (0..16).collect();
+        let plaintext = [
+            0x6B, 0xC1, 0xBE, 0xE2, 0x2E, 0x40, 0x9F, 0x96, 0xE9, 0x3D, 0x7E, 0x11, 0x73, 0x93,
+            0x17, 0x2A,
+        ];
+        let mut data = plaintext.to_vec();
+        data.extend_from_slice(&plaintext);
+        encrypt_aes_cbc(&mut data, &key, &iv);
+        assert_eq!(&data[..4], &[0xF5, 0x8C, 0x4C, 0x04]);
+        assert_ne!(data[..16], data[16..]);
+        decrypt_aes_cbc(&mut data, &key, &iv);
+        assert_eq!(data[16..], plaintext);
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(&sha256(b"abc")[..4], &[0xBA, 0x78, 0x16, 0xBF]);
+    }
+}
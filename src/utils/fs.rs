@@ -28,7 +28,7 @@ pub fn ensure_dir_exists(path: &path::Path) -> Result<(), io::Error> {
         if path.exists() {
             return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Not a directory."));
         }
-        fs::create_dir_all(&path)?;
+        fs::create_dir_all(path)?;
     }
     Ok(())
 }
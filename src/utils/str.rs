@@ -12,3 +12,30 @@ pub fn n_pluralise<'a>(num: i32, singular_name: &'a str, plural_name: &'a str) -
 pub fn n_bytes_pluralise(num: i32) -> String {
     n_pluralise(num, "byte", "bytes")
 }
+
+/// Return bytes as an uppercase hex string, e.g. "00FF".
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Parse a hex string as returned by `bytes_to_hex`.
+pub fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(bytes_to_hex(&[0x00, 0xFF, 0x1A]), "00FF1A");
+        assert_eq!(hex_to_bytes("00ff1A"), Some(vec![0x00, 0xFF, 0x1A]));
+        assert_eq!(hex_to_bytes(""), Some(vec![]));
+        assert_eq!(hex_to_bytes("0"), None);
+        assert_eq!(hex_to_bytes("0G"), None);
+    }
+}